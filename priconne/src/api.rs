@@ -0,0 +1,46 @@
+//! HTTP API
+//!
+//! Routes under `/api/v1`, served next to the Telegram webhook.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+
+use crate::{search::SearchHit, PriconneService};
+
+/// Maximum number of search results.
+const SEARCH_LIMIT: usize = 50;
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+pub fn router(priconne: Arc<PriconneService>) -> Router {
+    Router::new()
+        .route("/api/v1/search", get(search))
+        .with_state(priconne)
+}
+
+async fn search(
+    State(priconne): State<Arc<PriconneService>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<SearchHit>>, StatusCode> {
+    if params.q.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let limit = params.limit.min(SEARCH_LIMIT);
+    Ok(Json(priconne.search.search(&params.q, limit)))
+}
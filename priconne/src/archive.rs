@@ -0,0 +1,276 @@
+//! Raw page archive
+//!
+//! Every page fetched by an [`AnnouncementClient`](crate::resource::service::AnnouncementClient)
+//! is saved as an [`ArchivedPage`], so that when the extractor is improved,
+//! old posts can be [reparsed](PriconneService::reparse) offline.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use mongodb::bson::{self, oid::ObjectId, spec::BinarySubtype, Binary};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::PageArchiveRepository,
+    insight::{AnnouncementInsight, AnnouncementPage, EventInAnnouncement, Extractor, Tags},
+    resource::{
+        announcement::{sources::AnnouncementSource, AnnouncementResponse},
+        information::InformationPage,
+        news::NewsPage,
+    },
+    search::SearchDocument,
+    Page, PriconneService, Result,
+};
+
+/// A raw page as fetched from remote, with gzip-compressed HTML.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchivedPage {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub source: AnnouncementSource,
+    pub post_id: i32,
+    pub url: url::Url,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub fetch_time: DateTime<Utc>,
+    /// Gzip-compressed HTML.
+    pub html: Binary,
+}
+
+impl ArchivedPage {
+    pub fn new<P: AnnouncementPage>(response: &AnnouncementResponse<P>) -> Result<Self> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(response.html.as_bytes())?;
+
+        Ok(Self {
+            id: ObjectId::new(),
+            source: response.source.clone(),
+            post_id: response.post_id,
+            url: response.url.clone(),
+            fetch_time: Utc::now(),
+            html: Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: encoder.finish()?,
+            },
+        })
+    }
+
+    /// Decompressed HTML of the page.
+    pub fn html(&self) -> Result<String> {
+        let mut html = String::new();
+        GzDecoder::new(self.html.bytes.as_slice()).read_to_string(&mut html)?;
+        Ok(html)
+    }
+
+    fn response<P: AnnouncementPage + Page>(&self) -> Result<AnnouncementResponse<P>> {
+        let html = self.html()?;
+        Ok(AnnouncementResponse {
+            post_id: self.post_id,
+            source: self.source.clone(),
+            url: self.url.clone(),
+            page: P::from_html(html.clone())?,
+            html,
+        })
+    }
+
+    /// Document of the page for the [search index](crate::search).
+    pub fn search_document(&self, announcement_id: Option<ObjectId>) -> Result<SearchDocument> {
+        let document = match self.source {
+            AnnouncementSource::Website => SearchDocument::new(
+                &self.response::<NewsPage>()?,
+                announcement_id,
+                self.fetch_time,
+            ),
+            AnnouncementSource::Api(_) => SearchDocument::new(
+                &self.response::<InformationPage>()?,
+                announcement_id,
+                self.fetch_time,
+            ),
+        };
+        Ok(document)
+    }
+
+    /// Parse the page again and extract insight from it.
+    pub fn extract(&self, extractor: &Extractor) -> Result<AnnouncementInsight<bson::Bson>> {
+        let insight = match self.source {
+            AnnouncementSource::Website => extractor
+                .extract_announcement(&self.response::<NewsPage>()?)
+                .into_bson(),
+            AnnouncementSource::Api(_) => extractor
+                .extract_announcement(&self.response::<InformationPage>()?)
+                .into_bson(),
+        };
+        Ok(insight)
+    }
+}
+
+/// Difference between a stored insight and the one extracted again.
+#[derive(Debug, Clone)]
+pub struct ReparseDiff {
+    pub announcement_id: ObjectId,
+    pub title: String,
+    pub source: AnnouncementSource,
+    pub post_id: i32,
+    pub old_tags: Tags,
+    pub new_tags: Tags,
+    pub old_events: Vec<EventInAnnouncement>,
+    pub new_events: Vec<EventInAnnouncement>,
+}
+
+impl ReparseDiff {
+    pub fn is_empty(&self) -> bool {
+        self.old_tags == self.new_tags && self.old_events == self.new_events
+    }
+}
+
+impl Display for ReparseDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} ({} #{}) {}",
+            self.announcement_id, self.source, self.post_id, self.title
+        )?;
+
+        for tag in self.old_tags.iter().filter(|t| !self.new_tags.contains(*t)) {
+            writeln!(f, "- #{tag}")?;
+        }
+        for tag in self.new_tags.iter().filter(|t| !self.old_tags.contains(*t)) {
+            writeln!(f, "+ #{tag}")?;
+        }
+
+        let format_event = |e: &EventInAnnouncement| {
+            format!(
+                "{}: {} - {}",
+                e.title,
+                e.start.format("%Y/%m/%d %H:%M"),
+                e.end.format("%Y/%m/%d %H:%M")
+            )
+        };
+        for event in self
+            .old_events
+            .iter()
+            .filter(|e| !self.new_events.contains(e))
+        {
+            writeln!(f, "- {}", format_event(event))?;
+        }
+        for event in self
+            .new_events
+            .iter()
+            .filter(|e| !self.old_events.contains(e))
+        {
+            writeln!(f, "+ {}", format_event(event))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PriconneService {
+    pub fn page_archive(&self) -> Result<Arc<dyn PageArchiveRepository>> {
+        self.storage.page_archive()
+    }
+
+    /// Re-run the extractor over the latest archived page of every resource,
+    /// and return the changed ones.
+    ///
+    /// When `write` is true, the regenerated tags and events are saved back.
+    pub async fn reparse(&self, write: bool) -> Result<Vec<ReparseDiff>> {
+        let announcements = self.storage.announcements()?;
+
+        // Pages are sorted by fetch time, so only the latest one is kept
+        let mut latest = HashMap::new();
+        for page in self.page_archive()?.all().await? {
+            latest.insert((page.source.clone(), page.post_id), page);
+        }
+
+        let mut diffs = Vec::new();
+        for ((source, post_id), page) in latest {
+            let Some(mut announcement) = announcements.find_by_source(&source, post_id).await?
+            else {
+                tracing::warn!("no announcement for archived page {source} #{post_id}");
+                continue;
+            };
+
+            let insight = match page.extract(&self.extractor) {
+                Ok(insight) => insight,
+                Err(error) => {
+                    tracing::error!("failed to reparse {source} #{post_id}: {error}");
+                    continue;
+                }
+            };
+
+            let last = announcement.data.len().saturating_sub(1);
+            let Some((index, stored)) = announcement
+                .data
+                .iter_mut()
+                .enumerate()
+                .rev()
+                .find(|(_, d)| d.source == source && d.id == post_id)
+            else {
+                continue;
+            };
+
+            let diff = ReparseDiff {
+                announcement_id: announcement.id,
+                title: stored.title.clone(),
+                source,
+                post_id,
+                old_tags: stored.tags.clone(),
+                new_tags: insight.tags.clone(),
+                old_events: stored.events.clone(),
+                new_events: insight.events.clone(),
+            };
+            if diff.is_empty() {
+                continue;
+            }
+
+            if write {
+                stored.tags = insight.tags;
+                stored.events = insight.events.clone();
+                if index == last {
+                    announcement.events = insight.events;
+                }
+                announcements.upsert(&announcement).await?;
+            }
+
+            diffs.push(diff);
+        }
+
+        Ok(diffs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::insight::tagging::RegexTagger;
+
+    #[test]
+    fn test_archive_and_extract() {
+        let html = std::fs::read_to_string("tests/news_page.html").unwrap();
+        let response = AnnouncementResponse {
+            post_id: 1,
+            source: AnnouncementSource::Website,
+            url: url::Url::parse("http://www.princessconnect.so-net.tw/news/newsDetail/1").unwrap(),
+            page: NewsPage::from_html(html.clone()).unwrap(),
+            html: html.clone(),
+        };
+
+        let archived = ArchivedPage::new(&response).unwrap();
+        assert!(archived.html.bytes.len() < html.len());
+        assert_eq!(archived.html().unwrap(), html);
+
+        let extractor = Extractor {
+            tagger: RegexTagger { tag_rules: vec![] },
+            time_zones: Default::default(),
+        };
+        let insight = archived.extract(&extractor).unwrap();
+        assert_eq!(insight.events.len(), 1);
+        assert!(insight.tags.contains("轉蛋"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Discord webhook publisher
+//!
+//! Posts are sent as an [embed](https://discord.com/developers/docs/resources/channel#embed-object),
+//! with the title, tags, one field for each event and a thumbnail.
+
+use async_trait::async_trait;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use super::{
+    webhook::{default_retries, post_with_retry},
+    Publication, Publisher, PublisherKind, SendResult,
+};
+use crate::Error;
+
+/// Limits of embed fields, from Discord documentation.
+const TITLE_LIMIT: usize = 256;
+const DESCRIPTION_LIMIT: usize = 4096;
+const FIELDS_LIMIT: usize = 25;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DiscordConfig {
+    /// Webhook url, like `https://discord.com/api/webhooks/<id>/<token>`
+    pub url: Url,
+    /// Override the default username of the webhook
+    pub username: Option<String>,
+    /// Times to retry when the delivery fails
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+pub struct DiscordPublisher {
+    pub client: reqwest::Client,
+    pub config: DiscordConfig,
+}
+
+/// Message object returned by Discord with `wait=true`.
+#[derive(Debug, Deserialize)]
+struct DiscordMessage {
+    id: String,
+}
+
+fn truncate(s: &str, limit: usize) -> String {
+    if s.chars().count() <= limit {
+        return s.to_string();
+    }
+    let mut s: String = s.chars().take(limit - 1).collect();
+    s.push('…');
+    s
+}
+
+/// Embed object of the publication.
+pub fn embed(publication: &Publication) -> serde_json::Value {
+    let tags = publication
+        .tags
+        .iter()
+        .map(|tag| format!("#{tag}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let fields = publication
+        .events
+        .iter()
+        .take(FIELDS_LIMIT)
+        .map(|e| {
+            json!({
+                "name": truncate(&e.title, TITLE_LIMIT),
+                "value": format!("<t:{}:f> - <t:{}:f>", e.start.timestamp(), e.end.timestamp()),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut embed = json!({
+        "title": truncate(&publication.title, TITLE_LIMIT),
+        "fields": fields,
+    });
+    if !tags.is_empty() {
+        embed["description"] = json!(truncate(&tags, DESCRIPTION_LIMIT));
+    }
+    if let Some(url) = &publication.url {
+        embed["url"] = json!(url);
+    }
+    if let Some(image) = &publication.image {
+        embed["thumbnail"] = json!({ "url": image });
+    }
+    embed
+}
+
+#[async_trait]
+impl Publisher for DiscordPublisher {
+    fn kind(&self) -> PublisherKind {
+        PublisherKind::Discord
+    }
+
+    async fn publish(&self, publication: &Publication) -> Result<SendResult, Error> {
+        let mut body = json!({ "embeds": [embed(publication)] });
+        if let Some(username) = &self.config.username {
+            body["username"] = json!(username);
+        }
+
+        // Wait for the message to be created, so we get its id
+        let mut url = self.config.url.clone();
+        url.query_pairs_mut().append_pair("wait", "true");
+
+        let response = post_with_retry(
+            &self.client,
+            url,
+            serde_json::to_vec(&body)?,
+            None,
+            self.config.retries,
+        )
+        .await?;
+        let message: DiscordMessage = response.json().await?;
+
+        Ok(SendResult {
+            url: None,
+            backend: PublisherKind::Discord,
+            recipient: None,
+            chat_id: None,
+            message_id: None,
+            remote_id: Some(message.id),
+            resource_id: publication.resource_id.clone(),
+            update_time: Utc::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{chat::webhook::tests::publication, insight::EventInAnnouncement};
+
+    #[test]
+    fn test_embed() {
+        let mut publication = publication();
+        publication.events.push(EventInAnnouncement {
+            title: "活動期間".to_string(),
+            start: Utc.with_ymd_and_hms(2023, 1, 1, 4, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 8, 15, 59, 0).unwrap(),
+            pattern: None,
+        });
+
+        let embed = embed(&publication);
+        assert_eq!(embed["title"], "第 1 話: 美食殿堂");
+        assert_eq!(embed["description"], "#漫畫");
+        assert_eq!(embed["thumbnail"]["url"], "https://example.com/1.png");
+        assert_eq!(embed["fields"][0]["name"], "活動期間");
+        assert_eq!(
+            embed["fields"][0]["value"],
+            "<t:1672545600:f> - <t:1673193540:f>"
+        );
+        assert!(embed.get("url").is_none());
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("公主連結", 4), "公主連結");
+        assert_eq!(truncate("公主連結", 3), "公主…");
+    }
+}
@@ -0,0 +1,141 @@
+//! Inline query search
+//!
+//! Typing `@bot 公主祭典` in any chat searches stored announcements, see
+//! [`AnnouncementRepository::search`](crate::database::AnnouncementRepository::search).
+//! Choosing a result sends the same message as the one posted to the channel.
+//! Inline mode must be enabled for the bot with BotFather.
+
+use teloxide::{
+    payloads::AnswerInlineQuerySetters,
+    requests::Requester,
+    types::{
+        InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputMessageContent,
+        InputMessageContentText, ParseMode,
+    },
+    Bot,
+};
+
+use crate::{chat::Message, resource::Announcement, template::Templates, PriconneService};
+
+/// Results in one page.
+const PAGE_SIZE: usize = 20;
+
+/// Title, date and tags of the announcement as an inline result sending
+/// `message`, with dates in the zone of `templates`.
+fn article(
+    announcement: &Announcement,
+    message: Message,
+    templates: &Templates,
+) -> Option<InlineQueryResult> {
+    let data = announcement.data.last()?;
+
+    let time = data
+        .create_time
+        .unwrap_or_else(|| announcement.id.timestamp().to_chrono());
+    let mut description = time
+        .with_timezone(&templates.time_zone())
+        .format("%Y/%m/%d")
+        .to_string();
+    for tag in &data.tags {
+        description.push_str(" #");
+        description.push_str(tag);
+    }
+
+    let content = InputMessageContentText::new(message.text).parse_mode(ParseMode::Html);
+    let article = InlineQueryResultArticle::new(
+        announcement.id.to_hex(),
+        &data.title,
+        InputMessageContent::Text(content),
+    )
+    .description(description);
+
+    Some(InlineQueryResult::Article(article))
+}
+
+pub(super) async fn answer(
+    bot: Bot,
+    query: InlineQuery,
+    priconne: PriconneService,
+) -> crate::Result<()> {
+    let skip = query.offset.parse().unwrap_or(0);
+    let found = priconne
+        .storage
+        .announcements()?
+        .search(&query.query, skip, PAGE_SIZE)
+        .await?;
+
+    // An empty offset tells Telegram there are no more results
+    let next_offset = match found.len() {
+        PAGE_SIZE => (skip + PAGE_SIZE).to_string(),
+        _ => String::new(),
+    };
+    let mut results = Vec::new();
+    for announcement in &found {
+        let message = priconne.announcement_message(announcement).await?;
+        results.extend(article(announcement, message, &priconne.templates));
+    }
+
+    bot.answer_inline_query(query.id, results)
+        .next_offset(next_offset)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::{
+        chat::Sendable, insight::AnnouncementInsight,
+        resource::announcement::sources::AnnouncementSource,
+    };
+
+    #[test]
+    fn test_article() {
+        let mut insight = AnnouncementInsight {
+            title: "【轉蛋】公主祭典".to_string(),
+            source: AnnouncementSource::Website,
+            id: 1,
+            url: url::Url::parse("http://www.princessconnect.so-net.tw/news/newsDetail/1").unwrap(),
+            tags: Default::default(),
+            create_time: Some(Utc.with_ymd_and_hms(2023, 1, 1, 20, 0, 0).unwrap()),
+            update_time: None,
+            telegraph_url: None,
+            events: vec![],
+            extra: (),
+        };
+        insight.tags.insert("轉蛋".to_string());
+        insight.tags.insert("公主祭典".to_string());
+        let announcement = Announcement::new(insight, None);
+
+        let Some(InlineQueryResult::Article(article)) =
+            article(&announcement, announcement.message(), &Templates::default())
+        else {
+            panic!("not an article");
+        };
+        assert_eq!(article.id, announcement.id.to_hex());
+        assert_eq!(article.title, "【轉蛋】公主祭典");
+        assert_eq!(
+            article.description.as_deref(),
+            Some("2023/01/02 #轉蛋 #公主祭典")
+        );
+        let InputMessageContent::Text(content) = article.input_message_content else {
+            panic!("not a text message");
+        };
+        assert_eq!(content.message_text, announcement.message().text);
+        assert_eq!(content.parse_mode, Some(ParseMode::Html));
+
+        // Dates are in the zone of recipients
+        let templates = Templates::new(&Default::default(), chrono_tz::America::New_York).unwrap();
+        let Some(InlineQueryResult::Article(result)) =
+            super::article(&announcement, announcement.message(), &templates)
+        else {
+            panic!("not an article");
+        };
+        assert_eq!(
+            result.description.as_deref(),
+            Some("2023/01/01 #轉蛋 #公主祭典")
+        );
+    }
+}
@@ -1,21 +1,60 @@
-use mongodb::Collection;
+//! Chat module
+//!
+//! Posts are delivered by a [`Publisher`]. [`TelegramPublisher`] sends them with
+//! the bot, while [`WebhookPublisher`] and [`DiscordPublisher`] post them to
+//! HTTP endpoints. [`ChatManager`] picks the publisher of each recipient and
+//! records every delivery as a [`SendResult`].
+//!
+//! Users may also follow tags with `/subscribe`, see [`subscription`], and
+//! search announcements with inline queries, see [`inline`].
+
+mod discord;
+pub mod inline;
+mod preview;
+mod route;
+pub mod subscription;
+mod telegram;
+mod text;
+mod webhook;
+
+pub use discord::{DiscordConfig, DiscordPublisher};
+pub use preview::{nodes_to_html, Preview, PreviewWriter};
+pub use route::{Route, RouteFilter, RouteKind, Router};
+pub use subscription::{ChatState, Subscription};
+pub use telegram::TelegramPublisher;
+pub use text::{fit_html, Fragment, MessageText, CAPTION_LIMIT};
+pub use webhook::{sign, WebhookConfig, WebhookPublisher};
+
+use async_trait::async_trait;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{borrow::Cow, fmt::Debug, sync::Arc};
 use teloxide::{
     dispatching::{
         dialogue::{self, InMemStorage},
         UpdateFilterExt, UpdateHandler,
     },
-    payloads::{SendMessageSetters, SendPhotoSetters},
+    payloads::SendMessageSetters,
     prelude::Dispatcher,
     requests::{Request, Requester},
-    types::{ChatId, InputFile, MessageId, Recipient, Update},
-    utils::command::BotCommands,
+    types::{ChatId, MessageId, Recipient, Update},
+    utils::{command::BotCommands, html::escape},
     Bot,
 };
 
-use crate::{config::TelegramConfig, resource::Announcement, Error, PriconneService};
+use crate::{
+    config::TelegramConfig,
+    database::{MessageRepository, SubscriptionRepository},
+    insight::{EventInAnnouncement, Tags},
+    resource::{
+        cartoon::Cartoon,
+        post::{PostConfig, PostSource},
+        sources::AnnouncementSource,
+        Announcement, Region, ResourceId,
+    },
+    template::Templates,
+    Error, PriconneService,
+};
 
 #[derive(Debug, BotCommands, Clone)]
 #[command(
@@ -37,98 +76,227 @@ enum TelegramCommand {
     ArticleAll,
     #[command(description = "send all news.")]
     NewsAll,
+    #[command(description = "follow a tag or keyword.")]
+    Subscribe(String),
+    #[command(description = "list followed tags and keywords.")]
+    Subscriptions,
+    #[command(description = "unfollow a tag or keyword.")]
+    Unsubscribe(String),
+    #[command(description = "search announcements.")]
+    Search(String),
 }
 
 pub struct ChatManager {
     pub bot: teloxide::Bot,
     pub config: TelegramConfig,
-    pub post_recipient: Recipient,
-    pub cartoon_recipient: Recipient,
-    pub messages: Collection<SendResult>,
+    pub router: Router,
+    pub messages: Arc<dyn MessageRepository>,
+    pub subscriptions: Arc<dyn SubscriptionRepository>,
+}
+
+/// Backend of a [`Publisher`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PublisherKind {
+    #[default]
+    Telegram,
+    Webhook,
+    Discord,
 }
 
 /// Message send result
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendResult {
     pub url: Option<Url>,
-    pub recipient: Recipient,
+    /// Backend that delivered the message.
+    /// Results recorded before other backends were added are all from Telegram.
+    #[serde(default)]
+    pub backend: PublisherKind,
+    /// Telegram recipient, `None` for other backends
+    pub recipient: Option<Recipient>,
     /// Chat id of the recipient, may be the same as `recipient`,
     /// but we store it for a more stable reference
-    pub chat_id: ChatId,
-    pub message_id: MessageId,
-    pub resource_id: crate::resource::ResourceId,
+    pub chat_id: Option<ChatId>,
+    pub message_id: Option<MessageId>,
+    /// Message id given by other backends, like a Discord snowflake
+    #[serde(default)]
+    pub remote_id: Option<String>,
+    pub resource_id: ResourceId,
     pub update_time: chrono::DateTime<chrono::Utc>,
 }
 
+/// Maximum length of a Telegram message text, after entities parsing.
+pub const TEXT_LIMIT: usize = 4096;
+
+/// A Telegram message, with text in HTML
+///
+/// Messages of posts are [rendered](crate::resource::post::Post::render) with
+/// the fields below `image_src` filled. They are only built for sending, and
+/// never saved.
+#[derive(Debug, Clone, Default)]
 pub struct Message {
+    /// Rendered text in HTML
     pub text: String,
+    /// End of `text` kept intact when cut, see [`Rendered`](crate::template::Rendered)
+    pub footer: String,
+    /// Send without notification
     pub silent: bool,
     pub image_src: Option<Url>,
+    pub tags: Tags,
+    /// Title in the message, without the category
+    pub title: String,
+    pub summary: Option<String>,
+    /// Full content in Telegram HTML, sent instead of the summary for short posts
+    pub content: Option<String>,
+    pub events: Vec<EventInAnnouncement>,
+    /// Telegraph URL
+    pub telegraph: Option<String>,
+    /// Source URL
+    pub source: Option<Url>,
+    pub create_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub sources: Vec<PostSource>,
+    /// Message to reply to. Message ids are per chat, so when published, it is
+    /// replaced by the latest message of the same resource in each chat.
+    pub reply_to: Option<MessageId>,
+}
+
+/// Content of a post, from which each [`Publisher`] builds its own payload.
+#[derive(Debug, Clone)]
+pub struct Publication {
+    pub resource_id: ResourceId,
+    pub title: String,
+    /// Link to the full content
+    pub url: Option<Url>,
+    pub tags: Vec<String>,
+    pub events: Vec<EventInAnnouncement>,
+    pub image: Option<Url>,
+    /// Source of the latest data, `None` for resources other than announcements
+    pub source: Option<AnnouncementSource>,
+    pub region: Region,
+    /// Rendered Telegram message
+    pub message: Message,
 }
 
 pub trait Sendable {
     fn message(&self) -> Message;
+    fn publication(&self) -> Publication;
+}
+
+/// A route that failed to deliver a publication.
+#[derive(Debug)]
+pub struct FailedDelivery {
+    pub backend: PublisherKind,
+    pub error: Error,
+}
+
+/// Outcome of publishing to every matching route.
+#[derive(Debug, Default)]
+pub struct Delivery {
+    pub results: Vec<SendResult>,
+    pub failures: Vec<FailedDelivery>,
+}
+
+impl Delivery {
+    /// The results, or the last error if every route failed.
+    pub fn into_results(mut self) -> Result<Vec<SendResult>, Error> {
+        match self.failures.pop() {
+            Some(failure) if self.results.is_empty() => Err(failure.error),
+            _ => Ok(self.results),
+        }
+    }
+}
+
+/// Delivers a [`Publication`] to a recipient.
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    fn kind(&self) -> PublisherKind;
+    /// Telegram chat that messages are sent to
+    fn recipient(&self) -> Option<&Recipient> {
+        None
+    }
+    async fn publish(&self, publication: &Publication) -> Result<SendResult, Error>;
 }
 
 impl ChatManager {
-    async fn send_to<C>(
-        &self,
-        message: Message,
-        chat_id: C,
-    ) -> Result<teloxide::prelude::Message, Error>
-    where
-        C: Into<Recipient> + Clone,
-    {
-        let send_result = if let Some(image_src) = message.image_src {
-            self.bot
-                .send_photo(chat_id.clone(), InputFile::url(image_src))
-                .caption(message.text)
-                .disable_notification(message.silent)
-                .parse_mode(teloxide::types::ParseMode::Html)
-                .await?
-        } else {
-            self.bot
-                .send_message(chat_id.clone(), message.text.clone())
-                .disable_notification(message.silent)
-                .parse_mode(teloxide::types::ParseMode::Html)
-                .await?
+    /// Publish to every matching route, and record the results.
+    ///
+    /// A failed delivery is logged and does not stop the others. It is not
+    /// retried, the publication only fails if every route failed, see
+    /// [`Delivery::into_results`].
+    async fn publish(&self, publication: &Publication) -> Result<Delivery, Error> {
+        let mut delivery = Delivery::default();
+        let sent = match publication.message.reply_to {
+            Some(_) => {
+                self.messages
+                    .find_by_resource(&publication.resource_id)
+                    .await?
+            }
+            None => Vec::new(),
         };
 
-        Ok(send_result)
+        for route in self.router.routes(publication) {
+            let publication = match route.publisher.recipient() {
+                Some(recipient) if publication.message.reply_to.is_some() => {
+                    let mut publication = publication.clone();
+                    publication.message.reply_to = reply_target(&sent, recipient);
+                    Cow::Owned(publication)
+                }
+                _ => Cow::Borrowed(publication),
+            };
+            match route.publisher.publish(&publication).await {
+                Ok(result) => {
+                    self.messages.insert(&result).await?;
+                    delivery.results.push(result);
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "failed to publish {} to {:?}: {e}",
+                        publication.title,
+                        route.publisher.kind()
+                    );
+                    delivery.failures.push(FailedDelivery {
+                        backend: route.publisher.kind(),
+                        error: e,
+                    });
+                }
+            }
+        }
+        Ok(delivery)
     }
 
+    /// Publish the announcement, `html` is the page it is fetched from.
     pub async fn send_announcement(
         &self,
         post: &Announcement,
-    ) -> Result<teloxide::prelude::Message, Error> {
-        let message = self
-            .send_to(post.message(), self.post_recipient.clone())
-            .await?;
-        self.messages
-            .insert_one(
-                SendResult {
-                    recipient: self.post_recipient.clone(),
-                    chat_id: message.chat.id,
-                    resource_id: crate::resource::ResourceId::Announcement(post.id),
-                    update_time: message.date,
-                    message_id: message.id,
-                    url: message.url(),
-                },
-                None,
-            )
-            .await?;
-        Ok(message)
+        html: Option<&str>,
+        config: &PostConfig,
+        templates: &Templates,
+    ) -> Result<Delivery, Error> {
+        self.publish(&post.publication_with_page(html, config, templates))
+            .await
     }
 
-    pub async fn send_cartoon<M: Sendable>(
+    /// Publish the cartoon, with the caption rendered by `templates`.
+    pub async fn send_cartoon(
         &self,
-        cartoon: &M,
-    ) -> Result<teloxide::prelude::Message, Error> {
-        self.send_to(cartoon.message(), self.cartoon_recipient.clone())
-            .await
+        cartoon: &Cartoon,
+        templates: &Templates,
+    ) -> Result<Delivery, Error> {
+        let mut publication = cartoon.publication();
+        publication.message = cartoon.message_with(templates);
+        self.publish(&publication).await
     }
 }
 
+/// Latest Telegram message sent to `recipient`, among `sent`.
+fn reply_target(sent: &[SendResult], recipient: &Recipient) -> Option<MessageId> {
+    sent.iter()
+        .filter(|result| result.backend == PublisherKind::Telegram)
+        .filter(|result| result.recipient.as_ref() == Some(recipient))
+        .max_by_key(|result| result.update_time)
+        .and_then(|result| result.message_id)
+}
+
 pub fn dispatcher(
     priconne: &PriconneService,
     bot: &teloxide::Bot,
@@ -141,7 +309,7 @@ pub fn dispatcher(
     use dptree::deps;
 
     Dispatcher::builder(bot.clone(), schema())
-        .dependencies(deps![priconne.clone(), InMemStorage::<()>::new()])
+        .dependencies(deps![priconne.clone(), InMemStorage::<ChatState>::new()])
         .enable_ctrlc_handler()
         .build()
 }
@@ -154,11 +322,23 @@ pub fn schema() -> UpdateHandler<crate::Error> {
         .branch(case![TelegramCommand::CartoonAll].endpoint(cartoon_all))
         .branch(case![TelegramCommand::NewsAll].endpoint(news_all))
         .branch(case![TelegramCommand::ArticleAll].endpoint(article_all))
+        .branch(case![TelegramCommand::Subscribe(keyword)].endpoint(subscription::subscribe))
+        .branch(case![TelegramCommand::Subscriptions].endpoint(subscription::subscriptions))
+        .branch(case![TelegramCommand::Unsubscribe(keyword)].endpoint(subscription::unsubscribe))
+        .branch(case![TelegramCommand::Search(query)].endpoint(search))
         .branch(dptree::endpoint(todo_command));
 
-    let message_handler = Update::filter_message().branch(command_handler);
+    let message_handler = Update::filter_message()
+        .branch(command_handler)
+        .branch(case![ChatState::ReceiveSubscribe].endpoint(subscription::receive_subscribe))
+        .branch(case![ChatState::ReceiveUnsubscribe].endpoint(subscription::receive_unsubscribe));
 
-    dialogue::enter::<Update, InMemStorage<_>, (), _>().branch(message_handler)
+    // Inline queries have no chat, so they are handled outside of dialogues
+    let inline_handler = Update::filter_inline_query().endpoint(inline::answer);
+
+    dptree::entry()
+        .branch(inline_handler)
+        .branch(dialogue::enter::<Update, InMemStorage<_>, ChatState, _>().branch(message_handler))
 }
 
 async fn help(bot: teloxide::Bot, msg: teloxide::types::Message) -> crate::Result<()> {
@@ -169,6 +349,41 @@ async fn help(bot: teloxide::Bot, msg: teloxide::types::Message) -> crate::Resul
     Ok(())
 }
 
+/// Results of `/search`.
+const SEARCH_LIMIT: usize = 5;
+
+async fn search(
+    bot: teloxide::Bot,
+    msg: teloxide::types::Message,
+    priconne: PriconneService,
+    query: String,
+) -> crate::Result<()> {
+    let hits = priconne.search.search(&query, SEARCH_LIMIT);
+    let text = if query.trim().is_empty() {
+        "Usage: /search <query>".to_string()
+    } else if hits.is_empty() {
+        format!("No results for <b>{}</b>.", escape(query.trim()))
+    } else {
+        hits.iter()
+            .map(|hit| {
+                format!(
+                    "<a href=\"{}\">{}</a>\n{}",
+                    escape(hit.url.as_str()),
+                    escape(&hit.title),
+                    hit.snippet
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
 async fn cartoon_all(priconne: PriconneService) -> crate::Result<()> {
     priconne
         .run_service(crate::resource::ResourceKind::Cartoon)
@@ -206,8 +421,151 @@ async fn todo_command(
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Mutex;
+
     use dptree::{prelude::DependencyMap, Endpoint};
 
+    use super::{webhook::tests::publication, *};
+    use crate::database::{EmbeddedStore, Storage};
+
+    /// Records the messages replied to, instead of sending.
+    struct RecordingPublisher {
+        recipient: Recipient,
+        replies: Mutex<Vec<Option<MessageId>>>,
+    }
+
+    #[async_trait]
+    impl Publisher for RecordingPublisher {
+        fn kind(&self) -> PublisherKind {
+            PublisherKind::Telegram
+        }
+
+        fn recipient(&self) -> Option<&Recipient> {
+            Some(&self.recipient)
+        }
+
+        async fn publish(&self, publication: &Publication) -> Result<SendResult, Error> {
+            self.replies
+                .lock()
+                .unwrap()
+                .push(publication.message.reply_to);
+            Ok(SendResult {
+                url: None,
+                backend: PublisherKind::Telegram,
+                recipient: Some(self.recipient.clone()),
+                chat_id: None,
+                message_id: Some(MessageId(100)),
+                remote_id: None,
+                resource_id: publication.resource_id.clone(),
+                update_time: chrono::Utc::now(),
+            })
+        }
+    }
+
+    /// Always fails, like an unreachable endpoint.
+    struct FailingPublisher;
+
+    #[async_trait]
+    impl Publisher for FailingPublisher {
+        fn kind(&self) -> PublisherKind {
+            PublisherKind::Webhook
+        }
+
+        async fn publish(&self, _publication: &Publication) -> Result<SendResult, Error> {
+            Err(Error::SendError("unreachable".to_string()))
+        }
+    }
+
+    fn recording(name: &str) -> Arc<RecordingPublisher> {
+        Arc::new(RecordingPublisher {
+            recipient: Recipient::ChannelUsername(name.to_string()),
+            replies: Mutex::new(vec![]),
+        })
+    }
+
+    fn manager(publishers: Vec<Arc<dyn Publisher>>) -> Result<ChatManager, Error> {
+        let storage = Storage::Embedded(EmbeddedStore::in_memory());
+        Ok(ChatManager {
+            bot: Bot::new("0:token"),
+            config: serde_yaml::from_str(
+                "name: bot\ntoken: '0:token'\nrecipient:\n  debug: '@debug'",
+            )
+            .unwrap(),
+            router: Router {
+                routes: publishers
+                    .into_iter()
+                    .map(|publisher| Route {
+                        publisher,
+                        filter: RouteFilter::default(),
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            messages: storage.messages()?,
+            subscriptions: storage.subscriptions()?,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_reply_per_chat() -> Result<(), Error> {
+        let publishers = [recording("@first"), recording("@second")];
+        let manager = manager(
+            publishers
+                .iter()
+                .map(|publisher| publisher.clone() as Arc<dyn Publisher>)
+                .collect(),
+        )?;
+
+        let mut publication = publication();
+        for (publisher, message_id) in publishers.iter().zip([10, 20]) {
+            manager
+                .messages
+                .insert(&SendResult {
+                    message_id: Some(MessageId(message_id)),
+                    ..publisher.publish(&publication).await?
+                })
+                .await?;
+        }
+
+        // Each chat replies to its own message
+        publication.message.reply_to = Some(MessageId(10));
+        let delivery = manager.publish(&publication).await?;
+        assert_eq!(delivery.results.len(), 2);
+        let replies: Vec<_> = publishers
+            .iter()
+            .map(|publisher| *publisher.replies.lock().unwrap().last().unwrap())
+            .collect();
+        assert_eq!(replies, [Some(MessageId(10)), Some(MessageId(20))]);
+
+        // Not a reply
+        publication.message.reply_to = None;
+        manager.publish(&publication).await?;
+        assert_eq!(*publishers[1].replies.lock().unwrap().last().unwrap(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery() -> Result<(), Error> {
+        let publisher = recording("@channel");
+        let manager = manager(vec![Arc::new(FailingPublisher), publisher.clone()])?;
+
+        let delivery = manager.publish(&publication()).await?;
+        assert_eq!(delivery.results.len(), 1);
+        assert_eq!(delivery.failures.len(), 1);
+        assert_eq!(delivery.failures[0].backend, PublisherKind::Webhook);
+        assert_eq!(manager.messages.all().await?.len(), 1);
+        assert!(delivery.into_results().is_ok());
+
+        // Every route failed
+        let manager = self::manager(vec![Arc::new(FailingPublisher)])?;
+        assert!(manager
+            .publish(&publication())
+            .await?
+            .into_results()
+            .is_err());
+        Ok(())
+    }
+
     type WebHandler = Endpoint<'static, DependencyMap, String>;
 
     fn smiles_handler() -> WebHandler {
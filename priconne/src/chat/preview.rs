@@ -0,0 +1,219 @@
+//! Previews for dry runs
+//!
+//! In a [dry run](crate::config::FetchConfig::dry_run), resources go through
+//! the whole pipeline, but instead of creating Telegraph pages and sending
+//! messages, a [`Preview`] is written to a directory or stdout.
+//!
+//! In a directory, each preview is saved to `<dir>/<name>/`, with the rendered
+//! message, Telegraph nodes, extracted insight, and an `index.html` page
+//! showing all of them.
+
+use std::path::PathBuf;
+
+use telegraph_rs::Node;
+use teloxide::utils::html::escape;
+
+use super::Message;
+use crate::Result;
+
+/// What would be published for a resource.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    /// Name of the preview, like `news-1460`
+    pub name: String,
+    pub title: String,
+    pub message: Message,
+    /// Telegraph content, as JSON of nodes
+    pub telegraph: Option<String>,
+    /// Extracted insight, as JSON
+    pub insight: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PreviewWriter {
+    Stdout,
+    Dir(PathBuf),
+}
+
+impl PreviewWriter {
+    /// Write to `dir`, or stdout if not given.
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        match dir {
+            Some(dir) => PreviewWriter::Dir(dir),
+            None => PreviewWriter::Stdout,
+        }
+    }
+
+    pub async fn write(&self, preview: &Preview) -> Result<()> {
+        let telegraph = preview
+            .telegraph
+            .as_deref()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .transpose()?
+            .map(|nodes| serde_json::to_string_pretty(&nodes))
+            .transpose()?;
+        let insight = preview
+            .insight
+            .as_ref()
+            .map(serde_json::to_string_pretty)
+            .transpose()?;
+
+        match self {
+            PreviewWriter::Stdout => {
+                println!("==> {}: {}", preview.name, preview.title);
+                println!("{}", preview.message.text);
+                if let Some(image_src) = &preview.message.image_src {
+                    println!("[image] {image_src}");
+                }
+                if let Some(telegraph) = telegraph {
+                    println!("--- telegraph\n{telegraph}");
+                }
+                if let Some(insight) = insight {
+                    println!("--- insight\n{insight}");
+                }
+                println!();
+            }
+            PreviewWriter::Dir(dir) => {
+                let dir = dir.join(&preview.name);
+                tokio::fs::create_dir_all(&dir).await?;
+                tokio::fs::write(dir.join("message.html"), &preview.message.text).await?;
+                if let Some(telegraph) = telegraph {
+                    tokio::fs::write(dir.join("telegraph.json"), telegraph).await?;
+                }
+                if let Some(insight) = insight {
+                    tokio::fs::write(dir.join("insight.json"), insight).await?;
+                }
+                tokio::fs::write(dir.join("index.html"), preview.html()?).await?;
+                tracing::info!("preview written to {}", dir.display());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Preview {
+    /// A standalone HTML page showing the preview.
+    pub fn html(&self) -> Result<String> {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        html.push_str(concat!(
+            "<style>\n",
+            "body { max-width: 720px; margin: auto; font-family: sans-serif; }\n",
+            ".message { white-space: pre-wrap; background: #eef; padding: 1em; border-radius: 8px; }\n",
+            ".message img, article img { max-width: 100%; }\n",
+            "</style>\n</head>\n<body>\n"
+        ));
+        html.push_str(&format!("<h1>{}</h1>\n", escape(&self.title)));
+
+        html.push_str("<h2>Message</h2>\n<div class=\"message\">");
+        if let Some(image_src) = &self.message.image_src {
+            html.push_str(&format!("<img src=\"{}\">\n", escape(image_src.as_str())));
+        }
+        // Telegram HTML is a subset of HTML
+        html.push_str(&self.message.text);
+        html.push_str("</div>\n");
+
+        if let Some(telegraph) = &self.telegraph {
+            let nodes: Vec<Node> = serde_json::from_str(telegraph)?;
+            html.push_str("<h2>Telegraph</h2>\n<article>");
+            html.push_str(&nodes_to_html(&nodes));
+            html.push_str("</article>\n");
+        }
+
+        if let Some(insight) = &self.insight {
+            html.push_str("<h2>Insight</h2>\n<pre>");
+            html.push_str(&escape(&serde_json::to_string_pretty(insight)?));
+            html.push_str("</pre>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        Ok(html)
+    }
+}
+
+/// Render Telegraph nodes back to HTML.
+pub fn nodes_to_html(nodes: &[Node]) -> String {
+    let mut html = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => html.push_str(&escape(text)),
+            Node::NodeElement(element) => {
+                html.push('<');
+                html.push_str(&element.tag);
+                let mut attrs: Vec<_> = element.attrs.iter().flatten().collect();
+                attrs.sort();
+                for (name, value) in attrs {
+                    match value {
+                        Some(value) => html.push_str(&format!(" {name}=\"{}\"", escape(value))),
+                        None => html.push_str(&format!(" {name}")),
+                    }
+                }
+                html.push('>');
+
+                // Void elements like `br` and `img` have no children
+                if let Some(children) = &element.children {
+                    html.push_str(&nodes_to_html(children));
+                    html.push_str(&format!("</{}>", element.tag));
+                }
+            }
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preview() -> Preview {
+        Preview {
+            name: "news-1".to_string(),
+            title: "<公告>".to_string(),
+            message: Message {
+                text: "<b>公告</b>\n#維護".to_string(),
+                silent: false,
+                image_src: None,
+                ..Default::default()
+            },
+            telegraph: Some(
+                r#"[{"tag":"p","children":["a < b",{"tag":"br"},{"tag":"a","attrs":{"href":"https://example.com"},"children":["link"]}]}]"#
+                    .to_string(),
+            ),
+            insight: Some(serde_json::json!({ "tags": ["維護"] })),
+        }
+    }
+
+    #[test]
+    fn test_nodes_to_html() {
+        let nodes: Vec<Node> = serde_json::from_str(preview().telegraph.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            nodes_to_html(&nodes),
+            r#"<p>a &lt; b<br><a href="https://example.com">link</a></p>"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_dir() -> Result<()> {
+        let dir = std::env::temp_dir().join("priconne-test-preview");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+
+        PreviewWriter::new(Some(dir.clone()))
+            .write(&preview())
+            .await?;
+
+        let dir = dir.join("news-1");
+        let index = tokio::fs::read_to_string(dir.join("index.html")).await?;
+        assert!(index.contains("<title>&lt;公告&gt;</title>"));
+        assert!(index.contains("<b>公告</b>"));
+        assert!(index.contains(r#"<a href="https://example.com">link</a>"#));
+        let insight = tokio::fs::read_to_string(dir.join("insight.json")).await?;
+        assert!(insight.contains("維護"));
+        assert!(dir.join("telegraph.json").exists());
+        assert!(dir.join("message.html").exists());
+
+        tokio::fs::remove_dir_all(dir.parent().unwrap()).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Routing
+//!
+//! Each [`Route`] delivers to one [`Publisher`], when its [`RouteFilter`]
+//! matches the [`Publication`]. A publication is sent to every matching route.
+
+use std::{collections::HashMap, sync::Arc};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{Publication, Publisher};
+use crate::resource::{sources::AnnouncementSource, Region, ResourceId};
+
+/// Kind of publications in [`RouteFilter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteKind {
+    Announcement,
+    Cartoon,
+}
+
+impl From<&ResourceId> for RouteKind {
+    fn from(id: &ResourceId) -> Self {
+        match id {
+            ResourceId::Announcement(_) => RouteKind::Announcement,
+            ResourceId::Cartoon(_) => RouteKind::Cartoon,
+        }
+    }
+}
+
+/// Conditions for a publication to be routed. Empty lists match everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RouteFilter {
+    /// Kinds of publications
+    #[serde(default)]
+    pub kinds: Vec<RouteKind>,
+    /// Publications with any of these tags
+    #[serde(default)]
+    pub include_tags: Vec<String>,
+    /// Publications without any of these tags
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    /// Sources, either a source name like `news` and `announce`,
+    /// or the id of an API server
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Regions of publications
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    /// Minimum priority, see [`Router::priority`]
+    pub min_priority: Option<i32>,
+}
+
+impl RouteFilter {
+    /// Filter matching only `kind`.
+    pub fn kind(kind: RouteKind) -> Self {
+        Self {
+            kinds: vec![kind],
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, publication: &Publication, priority: i32) -> bool {
+        let has_tag = |tags: &[String]| tags.iter().any(|tag| publication.tags.contains(tag));
+
+        if !self.kinds.is_empty()
+            && !self
+                .kinds
+                .contains(&RouteKind::from(&publication.resource_id))
+        {
+            return false;
+        }
+        if !self.include_tags.is_empty() && !has_tag(&self.include_tags) {
+            return false;
+        }
+        if has_tag(&self.exclude_tags) {
+            return false;
+        }
+        if !self.sources.is_empty() {
+            let Some(source) = &publication.source else {
+                return false;
+            };
+            let matched = self.sources.iter().any(|name| {
+                *name == source.name()
+                    || matches!(source, AnnouncementSource::Api(id) if id == name)
+            });
+            if !matched {
+                return false;
+            }
+        }
+        if !self.regions.is_empty() && !self.regions.contains(&publication.region) {
+            return false;
+        }
+        if self.min_priority.is_some_and(|min| priority < min) {
+            return false;
+        }
+
+        true
+    }
+}
+
+pub struct Route {
+    pub publisher: Arc<dyn Publisher>,
+    pub filter: RouteFilter,
+}
+
+/// Routing table of [`ChatManager`](super::ChatManager).
+#[derive(Default)]
+pub struct Router {
+    pub routes: Vec<Route>,
+    /// Priority of tags
+    pub priorities: HashMap<String, i32>,
+}
+
+impl Router {
+    /// Priority of a publication, which is the highest priority of its tags,
+    /// or 0 if none of its tags has one.
+    pub fn priority(&self, publication: &Publication) -> i32 {
+        publication
+            .tags
+            .iter()
+            .filter_map(|tag| self.priorities.get(tag))
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Routes matching the publication.
+    pub fn routes<'a>(&'a self, publication: &'a Publication) -> impl Iterator<Item = &'a Route> {
+        let priority = self.priority(publication);
+        self.routes
+            .iter()
+            .filter(move |route| route.filter.matches(publication, priority))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{webhook::tests::publication, Message};
+
+    fn announcement(tags: &[&str], source: AnnouncementSource) -> Publication {
+        Publication {
+            resource_id: ResourceId::Announcement(mongodb::bson::oid::ObjectId::new()),
+            title: "公告".to_string(),
+            url: None,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            events: vec![],
+            image: None,
+            source: Some(source),
+            region: Region::TW,
+            message: Message {
+                text: String::new(),
+                silent: false,
+                image_src: None,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_route_filter() {
+        let gacha = announcement(&["精選轉蛋"], AnnouncementSource::Website);
+        let maintenance = announcement(
+            &["停機維護", "補償"],
+            AnnouncementSource::Api("PROD1".to_string()),
+        );
+        let cartoon = publication();
+
+        let filter = RouteFilter::default();
+        assert!(filter.matches(&gacha, 0));
+        assert!(filter.matches(&cartoon, 0));
+
+        let filter = RouteFilter::kind(RouteKind::Announcement);
+        assert!(filter.matches(&gacha, 0));
+        assert!(!filter.matches(&cartoon, 0));
+
+        let filter = RouteFilter {
+            include_tags: vec!["精選轉蛋".to_string(), "公主祭典".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&gacha, 0));
+        assert!(!filter.matches(&maintenance, 0));
+
+        let filter = RouteFilter {
+            exclude_tags: vec!["補償".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&gacha, 0));
+        assert!(!filter.matches(&maintenance, 0));
+
+        let filter = RouteFilter {
+            sources: vec!["PROD1".to_string()],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&gacha, 0));
+        assert!(filter.matches(&maintenance, 0));
+        assert!(!filter.matches(&cartoon, 0));
+
+        let filter = RouteFilter {
+            sources: vec!["news".to_string()],
+            regions: vec![Region::TW],
+            ..Default::default()
+        };
+        assert!(filter.matches(&gacha, 0));
+
+        let filter = RouteFilter {
+            regions: vec![Region::JP],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&gacha, 0));
+
+        let filter = RouteFilter {
+            min_priority: Some(5),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&gacha, 0));
+        assert!(filter.matches(&gacha, 5));
+    }
+
+    #[test]
+    fn test_priority() {
+        let router = Router {
+            routes: vec![],
+            priorities: HashMap::from([("停機維護".to_string(), 10), ("補償".to_string(), 5)]),
+        };
+        let maintenance = announcement(&["補償", "停機維護"], AnnouncementSource::Website);
+        assert_eq!(router.priority(&maintenance), 10);
+        assert_eq!(router.priority(&publication()), 0);
+    }
+}
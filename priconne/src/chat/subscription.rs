@@ -0,0 +1,310 @@
+//! Personal subscriptions
+//!
+//! Users follow tags or keywords with `/subscribe` in a private chat, and get
+//! a direct message when a new or updated announcement matches any of them.
+//! When the keyword is omitted, the bot asks for it, and the dialogue state is
+//! kept in the dispatcher's [`InMemStorage`].
+
+use std::{collections::BTreeSet, time::Duration};
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    dispatching::dialogue::{Dialogue, InMemStorage},
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, Recipient},
+    utils::html::escape,
+    Bot,
+};
+
+use super::{ChatManager, Publication, Publisher, SendResult, TelegramPublisher};
+use crate::{Error, PriconneService};
+
+/// Interval between direct messages.
+///
+/// Telegram allows about 30 messages per second in total.
+const MESSAGE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum length of a keyword, in characters.
+const KEYWORD_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub chat_id: ChatId,
+    /// Tag or keyword in title
+    pub keyword: String,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub create_time: DateTime<Utc>,
+}
+
+impl Subscription {
+    pub fn new(chat_id: ChatId, keyword: String) -> Self {
+        Self {
+            id: ObjectId::new(),
+            chat_id,
+            keyword,
+            create_time: Utc::now(),
+        }
+    }
+
+    /// Whether the publication has the keyword as a tag, or in its title.
+    pub fn matches(&self, publication: &Publication) -> bool {
+        publication.tags.contains(&self.keyword) || publication.title.contains(&self.keyword)
+    }
+}
+
+/// Normalize user input to a keyword, `None` if invalid.
+///
+/// Leading `#` is removed, so that both `#公主祭典` and `公主祭典` follow the tag.
+pub fn normalize_keyword(input: &str) -> Option<String> {
+    let keyword = input.trim().trim_start_matches('#').trim();
+    if keyword.is_empty() || keyword.chars().count() > KEYWORD_LIMIT {
+        return None;
+    }
+    Some(keyword.to_string())
+}
+
+/// State of the subscription dialogue.
+#[derive(Debug, Clone, Default)]
+pub enum ChatState {
+    #[default]
+    Start,
+    /// Waiting for a keyword to subscribe
+    ReceiveSubscribe,
+    /// Waiting for a keyword to unsubscribe
+    ReceiveUnsubscribe,
+}
+
+pub type ChatDialogue = Dialogue<ChatState, InMemStorage<ChatState>>;
+
+impl ChatManager {
+    /// Send the publication to users subscribing to it, one message per user.
+    ///
+    /// Failed deliveries, for example to users who blocked the bot, are logged and skipped.
+    pub async fn notify_subscribers(
+        &self,
+        publication: &Publication,
+    ) -> Result<Vec<SendResult>, Error> {
+        let chats: BTreeSet<ChatId> = self
+            .subscriptions
+            .all()
+            .await?
+            .into_iter()
+            .filter(|subscription| subscription.matches(publication))
+            .map(|subscription| subscription.chat_id)
+            .collect();
+
+        // Replies only make sense in the post channel
+        let mut publication = publication.clone();
+        publication.message.reply_to = None;
+
+        let mut results = Vec::new();
+        let mut interval = tokio::time::interval(MESSAGE_INTERVAL);
+        for chat_id in chats {
+            interval.tick().await;
+            let publisher = TelegramPublisher {
+                bot: self.bot.clone(),
+                recipient: Recipient::Id(chat_id),
+            };
+            match publisher.publish(&publication).await {
+                Ok(result) => {
+                    self.messages.insert(&result).await?;
+                    results.push(result);
+                }
+                Err(e) => tracing::warn!("failed to notify {chat_id}: {e}"),
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+async fn reply(bot: &Bot, chat_id: ChatId, text: String) -> crate::Result<()> {
+    bot.send_message(chat_id, text)
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// Reply and return `false` if the chat is not private.
+async fn ensure_private(bot: &Bot, msg: &teloxide::types::Message) -> crate::Result<bool> {
+    if msg.chat.is_private() {
+        return Ok(true);
+    }
+    reply(
+        bot,
+        msg.chat.id,
+        "Please manage subscriptions in a private chat with me.".to_string(),
+    )
+    .await?;
+    Ok(false)
+}
+
+async fn subscribe_keyword(
+    bot: &Bot,
+    priconne: &PriconneService,
+    chat_id: ChatId,
+    input: &str,
+) -> crate::Result<()> {
+    let Some(keyword) = normalize_keyword(input) else {
+        return reply(bot, chat_id, "Invalid keyword.".to_string()).await;
+    };
+
+    let subscriptions = &priconne.chat_manager.subscriptions;
+    let text = if subscriptions.subscribe(chat_id, &keyword).await? {
+        format!("Subscribed to <b>{}</b>.", escape(&keyword))
+    } else {
+        format!("Already subscribed to <b>{}</b>.", escape(&keyword))
+    };
+    reply(bot, chat_id, text).await
+}
+
+async fn unsubscribe_keyword(
+    bot: &Bot,
+    priconne: &PriconneService,
+    chat_id: ChatId,
+    input: &str,
+) -> crate::Result<()> {
+    let Some(keyword) = normalize_keyword(input) else {
+        return reply(bot, chat_id, "Invalid keyword.".to_string()).await;
+    };
+
+    let subscriptions = &priconne.chat_manager.subscriptions;
+    let text = if subscriptions.unsubscribe(chat_id, &keyword).await? {
+        format!("Unsubscribed from <b>{}</b>.", escape(&keyword))
+    } else {
+        format!("Not subscribed to <b>{}</b>.", escape(&keyword))
+    };
+    reply(bot, chat_id, text).await
+}
+
+pub(super) async fn subscribe(
+    bot: Bot,
+    dialogue: ChatDialogue,
+    msg: teloxide::types::Message,
+    priconne: PriconneService,
+    keyword: String,
+) -> crate::Result<()> {
+    if !ensure_private(&bot, &msg).await? {
+        return Ok(());
+    }
+    if keyword.trim().is_empty() {
+        dialogue
+            .update(ChatState::ReceiveSubscribe)
+            .await
+            .map_err(|e| Error::DialogueError(e.to_string()))?;
+        return reply(
+            &bot,
+            msg.chat.id,
+            "Send me a tag or keyword to follow.".to_string(),
+        )
+        .await;
+    }
+    subscribe_keyword(&bot, &priconne, msg.chat.id, &keyword).await
+}
+
+pub(super) async fn unsubscribe(
+    bot: Bot,
+    dialogue: ChatDialogue,
+    msg: teloxide::types::Message,
+    priconne: PriconneService,
+    keyword: String,
+) -> crate::Result<()> {
+    if !ensure_private(&bot, &msg).await? {
+        return Ok(());
+    }
+    if keyword.trim().is_empty() {
+        dialogue
+            .update(ChatState::ReceiveUnsubscribe)
+            .await
+            .map_err(|e| Error::DialogueError(e.to_string()))?;
+        return reply(
+            &bot,
+            msg.chat.id,
+            "Send me the tag or keyword to unfollow.".to_string(),
+        )
+        .await;
+    }
+    unsubscribe_keyword(&bot, &priconne, msg.chat.id, &keyword).await
+}
+
+pub(super) async fn subscriptions(
+    bot: Bot,
+    msg: teloxide::types::Message,
+    priconne: PriconneService,
+) -> crate::Result<()> {
+    if !ensure_private(&bot, &msg).await? {
+        return Ok(());
+    }
+
+    let subscriptions = priconne
+        .chat_manager
+        .subscriptions
+        .find_by_chat(msg.chat.id)
+        .await?;
+    let text = if subscriptions.is_empty() {
+        "You have no subscriptions. Use /subscribe to follow a tag or keyword.".to_string()
+    } else {
+        let keywords: Vec<_> = subscriptions
+            .iter()
+            .map(|subscription| format!("- {}", escape(&subscription.keyword)))
+            .collect();
+        format!("You are following:\n{}", keywords.join("\n"))
+    };
+    reply(&bot, msg.chat.id, text).await
+}
+
+pub(super) async fn receive_subscribe(
+    bot: Bot,
+    dialogue: ChatDialogue,
+    msg: teloxide::types::Message,
+    priconne: PriconneService,
+) -> crate::Result<()> {
+    dialogue
+        .exit()
+        .await
+        .map_err(|e| Error::DialogueError(e.to_string()))?;
+    subscribe_keyword(&bot, &priconne, msg.chat.id, msg.text().unwrap_or_default()).await
+}
+
+pub(super) async fn receive_unsubscribe(
+    bot: Bot,
+    dialogue: ChatDialogue,
+    msg: teloxide::types::Message,
+    priconne: PriconneService,
+) -> crate::Result<()> {
+    dialogue
+        .exit()
+        .await
+        .map_err(|e| Error::DialogueError(e.to_string()))?;
+    unsubscribe_keyword(&bot, &priconne, msg.chat.id, msg.text().unwrap_or_default()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::webhook::tests::publication;
+
+    #[test]
+    fn test_normalize_keyword() {
+        assert_eq!(
+            normalize_keyword(" #公主祭典 "),
+            Some("公主祭典".to_string())
+        );
+        assert_eq!(normalize_keyword("蘭法"), Some("蘭法".to_string()));
+        assert_eq!(normalize_keyword(" # "), None);
+        assert_eq!(normalize_keyword(&"長".repeat(KEYWORD_LIMIT + 1)), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let publication = publication();
+        assert!(Subscription::new(ChatId(1), "漫畫".to_string()).matches(&publication));
+        assert!(Subscription::new(ChatId(1), "美食殿堂".to_string()).matches(&publication));
+        assert!(!Subscription::new(ChatId(1), "公主祭典".to_string()).matches(&publication));
+    }
+}
@@ -0,0 +1,75 @@
+//! Telegram publisher
+
+use async_trait::async_trait;
+use teloxide::{
+    payloads::{SendMessageSetters, SendPhotoSetters},
+    requests::Requester,
+    types::{InputFile, ParseMode, Recipient},
+    Bot,
+};
+
+use super::{
+    fit_html, Publication, Publisher, PublisherKind, SendResult, CAPTION_LIMIT, TEXT_LIMIT,
+};
+use crate::Error;
+
+/// Sends messages to a chat or channel with the bot.
+pub struct TelegramPublisher {
+    pub bot: Bot,
+    pub recipient: Recipient,
+}
+
+#[async_trait]
+impl Publisher for TelegramPublisher {
+    fn kind(&self) -> PublisherKind {
+        PublisherKind::Telegram
+    }
+
+    fn recipient(&self) -> Option<&Recipient> {
+        Some(&self.recipient)
+    }
+
+    async fn publish(&self, publication: &Publication) -> Result<SendResult, Error> {
+        let message = publication.message.clone();
+        let sent = if let Some(image_src) = message.image_src {
+            let mut request = self
+                .bot
+                .send_photo(self.recipient.clone(), InputFile::url(image_src))
+                .caption(fit_html(&message.text, &message.footer, CAPTION_LIMIT))
+                .disable_notification(message.silent)
+                .parse_mode(ParseMode::Html);
+            if let Some(reply_to) = message.reply_to {
+                request = request
+                    .reply_to_message_id(reply_to)
+                    .allow_sending_without_reply(true);
+            }
+            request.await?
+        } else {
+            let mut request = self
+                .bot
+                .send_message(
+                    self.recipient.clone(),
+                    fit_html(&message.text, &message.footer, TEXT_LIMIT),
+                )
+                .disable_notification(message.silent)
+                .parse_mode(ParseMode::Html);
+            if let Some(reply_to) = message.reply_to {
+                request = request
+                    .reply_to_message_id(reply_to)
+                    .allow_sending_without_reply(true);
+            }
+            request.await?
+        };
+
+        Ok(SendResult {
+            url: sent.url(),
+            backend: PublisherKind::Telegram,
+            recipient: Some(self.recipient.clone()),
+            chat_id: Some(sent.chat.id),
+            message_id: Some(sent.id),
+            remote_id: None,
+            resource_id: publication.resource_id.clone(),
+            update_time: sent.date,
+        })
+    }
+}
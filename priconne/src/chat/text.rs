@@ -0,0 +1,599 @@
+//! Message text builder
+//!
+//! [`MessageText`] builds a message from typed [`Fragment`]s, escaped for the
+//! chosen parse mode when rendered. Telegram counts the length of the text
+//! after parsing entities, in UTF-16 code units, so the length of a message is
+//! the length of its visible text.
+//!
+//! A message over the limit is [truncated](MessageText::truncate) or
+//! [split](MessageText::split), and its footer is always kept intact.
+
+use std::borrow::Cow;
+
+use kuchikiki::{iter::NodeIterator, traits::TendrilSink, NodeRef};
+use teloxide::{
+    types::ParseMode,
+    utils::{html, markdown},
+};
+
+use super::TEXT_LIMIT;
+
+/// Maximum length of a photo caption, after entities parsing.
+pub const CAPTION_LIMIT: usize = 1024;
+
+const ELLIPSIS: &str = "…";
+
+/// A piece of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fragment {
+    Text(String),
+    Bold(String),
+    Italic(String),
+    Underline(String),
+    Strikethrough(String),
+    Code(String),
+    Link {
+        text: String,
+        url: String,
+    },
+    /// Tag without `#`
+    Hashtag(String),
+}
+
+impl Fragment {
+    /// Text shown to users.
+    fn visible(&self) -> Cow<'_, str> {
+        match self {
+            Fragment::Text(text)
+            | Fragment::Bold(text)
+            | Fragment::Italic(text)
+            | Fragment::Underline(text)
+            | Fragment::Strikethrough(text)
+            | Fragment::Code(text)
+            | Fragment::Link { text, .. } => Cow::Borrowed(text),
+            Fragment::Hashtag(tag) => Cow::Owned(format!("#{tag}")),
+        }
+    }
+
+    fn len(&self) -> usize {
+        length(&self.visible())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.visible().is_empty()
+    }
+
+    /// The same kind of fragment with `text`, `None` if it can't be cut.
+    fn with_text(&self, text: String) -> Option<Fragment> {
+        match self {
+            Fragment::Text(_) => Some(Fragment::Text(text)),
+            Fragment::Bold(_) => Some(Fragment::Bold(text)),
+            Fragment::Italic(_) => Some(Fragment::Italic(text)),
+            Fragment::Underline(_) => Some(Fragment::Underline(text)),
+            Fragment::Strikethrough(_) => Some(Fragment::Strikethrough(text)),
+            Fragment::Code(_) => Some(Fragment::Code(text)),
+            Fragment::Link { url, .. } => Some(Fragment::Link {
+                text,
+                url: url.clone(),
+            }),
+            Fragment::Hashtag(_) => None,
+        }
+    }
+
+    /// Cut the fragment after `limit` units, `None` if it can't be cut.
+    fn cut(&self, limit: usize) -> Option<(Fragment, Fragment)> {
+        let visible = self.visible();
+        let (head, tail) = split_at_length(&visible, limit);
+        Some((
+            self.with_text(head.to_string())?,
+            self.with_text(tail.to_string())?,
+        ))
+    }
+
+    fn render(&self, mode: ParseMode) -> String {
+        match mode {
+            ParseMode::Html => match self {
+                Fragment::Text(text) => html::escape(text),
+                Fragment::Bold(text) => format!("<b>{}</b>", html::escape(text)),
+                Fragment::Italic(text) => format!("<i>{}</i>", html::escape(text)),
+                Fragment::Underline(text) => format!("<u>{}</u>", html::escape(text)),
+                Fragment::Strikethrough(text) => format!("<s>{}</s>", html::escape(text)),
+                Fragment::Code(text) => format!("<code>{}</code>", html::escape(text)),
+                Fragment::Link { text, url } => format!(
+                    "<a href=\"{}\">{}</a>",
+                    html::escape(url).replace('"', "&quot;"),
+                    html::escape(text)
+                ),
+                Fragment::Hashtag(tag) => format!("#{}", html::escape(tag)),
+            },
+            _ => match self {
+                Fragment::Text(text) => markdown::escape(text),
+                Fragment::Bold(text) => format!("*{}*", markdown::escape(text)),
+                Fragment::Italic(text) => format!("_{}_", markdown::escape(text)),
+                Fragment::Underline(text) => format!("__{}__", markdown::escape(text)),
+                Fragment::Strikethrough(text) => format!("~{}~", markdown::escape(text)),
+                Fragment::Code(text) => format!("`{}`", markdown::escape_code(text)),
+                Fragment::Link { text, url } => format!(
+                    "[{}]({})",
+                    markdown::escape(text),
+                    markdown::escape_link_url(url)
+                ),
+                Fragment::Hashtag(tag) => format!("\\#{}", markdown::escape(tag)),
+            },
+        }
+    }
+}
+
+/// A message made of fragments, with a footer kept when cut.
+#[derive(Debug, Clone)]
+pub struct MessageText {
+    mode: ParseMode,
+    body: Vec<Fragment>,
+    footer: Vec<Fragment>,
+}
+
+impl Default for MessageText {
+    fn default() -> Self {
+        Self::new(ParseMode::Html)
+    }
+}
+
+impl MessageText {
+    /// An empty message rendered for `mode`, MarkdownV2 if not HTML.
+    pub fn new(mode: ParseMode) -> Self {
+        Self {
+            mode,
+            body: Vec::new(),
+            footer: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, fragment: Fragment) -> &mut Self {
+        if !fragment.is_empty() {
+            self.body.push(fragment);
+        }
+        self
+    }
+
+    pub fn text(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Text(text.into()))
+    }
+
+    pub fn bold(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Bold(text.into()))
+    }
+
+    pub fn italic(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Italic(text.into()))
+    }
+
+    pub fn underline(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Underline(text.into()))
+    }
+
+    pub fn strikethrough(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Strikethrough(text.into()))
+    }
+
+    pub fn code(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Code(text.into()))
+    }
+
+    pub fn link(&mut self, text: impl Into<String>, url: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Link {
+            text: text.into(),
+            url: url.into(),
+        })
+    }
+
+    pub fn hashtag(&mut self, tag: impl Into<String>) -> &mut Self {
+        self.push(Fragment::Hashtag(tag.into()))
+    }
+
+    /// Append to the footer, which is never cut.
+    pub fn footer(&mut self, fragment: Fragment) -> &mut Self {
+        if !fragment.is_empty() {
+            self.footer.push(fragment);
+        }
+        self
+    }
+
+    /// Length of the visible text, in UTF-16 code units.
+    pub fn len(&self) -> usize {
+        total_length(&self.body) + total_length(&self.footer)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty() && self.footer.is_empty()
+    }
+
+    /// The whole message.
+    pub fn render(&self) -> String {
+        render(self.mode, self.body.iter().chain(&self.footer))
+    }
+
+    /// The message in at most `limit` units, with the end of the body replaced
+    /// by `…` if too long.
+    pub fn truncate(&self, limit: usize) -> String {
+        if self.len() <= limit {
+            return self.render();
+        }
+
+        let room = limit.saturating_sub(total_length(&self.footer));
+        let mut body = Vec::new();
+        if room > 0 {
+            let (head, _) = take(&self.body, room - length(ELLIPSIS), false);
+            body = head;
+            trim_end(&mut body);
+            body.push(Fragment::Text(ELLIPSIS.to_string()));
+        }
+        render(self.mode, body.iter().chain(&self.footer))
+    }
+
+    /// The message split into parts of at most `limit` units, at the last line
+    /// break if possible. The footer ends the last part.
+    pub fn split(&self, limit: usize) -> Vec<String> {
+        let mut parts = Vec::new();
+        let mut rest = self.body.clone();
+        while total_length(&rest) > limit {
+            let (head, tail) = take(&rest, limit, true);
+            if head.is_empty() {
+                // A hashtag longer than the limit
+                parts.push(vec![rest.remove(0)]);
+                continue;
+            }
+            parts.push(head);
+            rest = tail;
+        }
+
+        if total_length(&rest) + total_length(&self.footer) <= limit {
+            rest.extend(self.footer.iter().cloned());
+            parts.push(rest);
+        } else {
+            parts.push(rest);
+            parts.push(self.footer.clone());
+        }
+
+        parts
+            .iter()
+            .filter(|part| !part.is_empty())
+            .map(|part| render(self.mode, part))
+            .collect()
+    }
+
+    /// Parse a message in Telegram HTML, keeping `footer`, the end of `text`,
+    /// as the footer. Tags other than those of [`Fragment`] are dropped.
+    pub fn from_html(text: &str, footer: &str) -> Self {
+        let (body, footer) = match text.strip_suffix(footer) {
+            Some(body) => (body, footer),
+            None => {
+                tracing::warn!("footer is not at the end of the message");
+                (text, "")
+            }
+        };
+
+        let mut message = Self::new(ParseMode::Html);
+        for fragment in parse_html(body) {
+            message.push(fragment);
+        }
+        for fragment in parse_html(footer) {
+            message.footer(fragment);
+        }
+        message
+    }
+}
+
+/// Fit `text` in Telegram HTML into `limit`, truncating the body if needed.
+/// `footer`, the end of `text`, is kept.
+pub fn fit_html(text: &str, footer: &str, limit: usize) -> String {
+    let message = MessageText::from_html(text, footer);
+    if message.len() <= limit {
+        return text.to_string();
+    }
+    tracing::warn!(
+        "message of {} characters is truncated to {limit}",
+        message.len()
+    );
+    message.truncate(limit.min(TEXT_LIMIT))
+}
+
+/// Fragments of Telegram HTML.
+fn parse_html(text: &str) -> Vec<Fragment> {
+    // The parser drops whitespace before the document
+    let content = text.trim_start();
+    let leading = &text[..text.len() - content.len()];
+    let document = kuchikiki::parse_html().one(content);
+    let mut fragments = vec![Fragment::Text(leading.to_string())];
+    for node in document.descendants().text_nodes() {
+        let text = node.borrow().clone();
+        let fragment = match style(node.as_node()) {
+            Some(fragment) => fragment.with_text(text).unwrap(),
+            None => Fragment::Text(text),
+        };
+        match (fragments.last_mut(), &fragment) {
+            (Some(Fragment::Text(last)), Fragment::Text(text)) => last.push_str(text),
+            _ => fragments.push(fragment),
+        }
+    }
+    fragments
+}
+
+/// Style of a text node from its nearest styled ancestor, as an empty fragment.
+fn style(node: &NodeRef) -> Option<Fragment> {
+    node.ancestors().find_map(|ancestor| {
+        let element = ancestor.as_element()?;
+        match &*element.name.local {
+            "a" => Some(Fragment::Link {
+                text: String::new(),
+                url: element.attributes.borrow().get("href")?.to_string(),
+            }),
+            "code" | "pre" => Some(Fragment::Code(String::new())),
+            "b" | "strong" => Some(Fragment::Bold(String::new())),
+            "i" | "em" => Some(Fragment::Italic(String::new())),
+            "u" | "ins" => Some(Fragment::Underline(String::new())),
+            "s" | "strike" | "del" => Some(Fragment::Strikethrough(String::new())),
+            _ => None,
+        }
+    })
+}
+
+/// Take fragments from the start, up to `limit` units. Cut at the last line
+/// break if `at_line` is set and there is one.
+fn take(fragments: &[Fragment], limit: usize, at_line: bool) -> (Vec<Fragment>, Vec<Fragment>) {
+    let mut head = Vec::new();
+    let mut tail = Vec::new();
+    let mut length = 0;
+    for (index, fragment) in fragments.iter().enumerate() {
+        if length + fragment.len() <= limit {
+            length += fragment.len();
+            head.push(fragment.clone());
+            continue;
+        }
+        match fragment.cut(limit - length) {
+            Some((first, rest)) => {
+                head.push(first);
+                tail.push(rest);
+            }
+            None => tail.push(fragment.clone()),
+        }
+        tail.extend(fragments[index + 1..].iter().cloned());
+        break;
+    }
+
+    if at_line && !tail.is_empty() {
+        let line = head
+            .iter()
+            .rposition(|fragment| matches!(fragment, Fragment::Text(text) if text.contains('\n')));
+        if let Some(index) = line {
+            let Fragment::Text(text) = &head[index] else {
+                unreachable!()
+            };
+            let (before, after) = text.rsplit_once('\n').unwrap();
+            let (before, after) = (before.to_string(), after.to_string());
+            let moved: Vec<Fragment> = std::iter::once(Fragment::Text(after))
+                .chain(head.drain(index..).skip(1))
+                .collect();
+            head.push(Fragment::Text(before));
+            tail.splice(0..0, moved);
+        }
+    }
+
+    merge(&mut head);
+    merge(&mut tail);
+    (head, tail)
+}
+
+/// Remove empty fragments, and join adjacent fragments of the same style.
+fn merge(fragments: &mut Vec<Fragment>) {
+    let mut merged: Vec<Fragment> = Vec::new();
+    for fragment in fragments.drain(..).filter(|fragment| !fragment.is_empty()) {
+        match (merged.last_mut(), fragment) {
+            (Some(Fragment::Text(last)), Fragment::Text(text))
+            | (Some(Fragment::Bold(last)), Fragment::Bold(text))
+            | (Some(Fragment::Italic(last)), Fragment::Italic(text))
+            | (Some(Fragment::Underline(last)), Fragment::Underline(text))
+            | (Some(Fragment::Strikethrough(last)), Fragment::Strikethrough(text))
+            | (Some(Fragment::Code(last)), Fragment::Code(text)) => last.push_str(&text),
+            (_, fragment) => merged.push(fragment),
+        }
+    }
+    *fragments = merged;
+}
+
+/// Remove whitespace at the end of the last text.
+fn trim_end(fragments: &mut Vec<Fragment>) {
+    while let Some(Fragment::Text(text)) = fragments.last_mut() {
+        let trimmed = text.trim_end();
+        if !trimmed.is_empty() {
+            *text = trimmed.to_string();
+            break;
+        }
+        fragments.pop();
+    }
+}
+
+fn render<'a>(mode: ParseMode, fragments: impl IntoIterator<Item = &'a Fragment>) -> String {
+    fragments
+        .into_iter()
+        .map(|fragment| fragment.render(mode))
+        .collect()
+}
+
+/// Length as counted by Telegram, in UTF-16 code units.
+pub fn length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn total_length(fragments: &[Fragment]) -> usize {
+    fragments.iter().map(Fragment::len).sum()
+}
+
+/// Split `text` after at most `limit` UTF-16 code units, at a char boundary.
+fn split_at_length(text: &str, limit: usize) -> (&str, &str) {
+    let mut units = 0;
+    for (index, char) in text.char_indices() {
+        units += char.len_utf16();
+        if units > limit {
+            return text.split_at(index);
+        }
+    }
+    (text, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> MessageText {
+        let mut message = MessageText::default();
+        message
+            .hashtag("維護")
+            .text("\n")
+            .bold("<停機維護> & 補償")
+            .text("\n\n第一行\n第二行\n")
+            .footer(Fragment::Link {
+                text: "https://telegra.ph/a".to_string(),
+                url: "https://telegra.ph/a?b=1&c=\"2\"".to_string(),
+            })
+            .footer(Fragment::Text("\n".to_string()))
+            .footer(Fragment::Code("news#1".to_string()));
+        message
+    }
+
+    #[test]
+    fn test_render() {
+        let message = message();
+        assert_eq!(
+            message.render(),
+            "#維護\n<b>&lt;停機維護&gt; &amp; 補償</b>\n\n第一行\n第二行\n\
+             <a href=\"https://telegra.ph/a?b=1&amp;c=&quot;2&quot;\">https://telegra.ph/a</a>\n\
+             <code>news#1</code>"
+        );
+        assert_eq!(
+            message.len(),
+            length("#維護\n<停機維護> & 補償\n\n第一行\n第二行\nhttps://telegra.ph/a\nnews#1")
+        );
+
+        let mut markdown = MessageText::new(ParseMode::MarkdownV2);
+        markdown
+            .hashtag("維護")
+            .text(" 1.5")
+            .bold("a*b")
+            .code("x`y")
+            .link("連結", "https://example.com/(a)");
+        assert_eq!(
+            markdown.render(),
+            "\\#維護 1\\.5*a\\*b*`x\\`y`[連結](https://example.com/(a\\))"
+        );
+    }
+
+    #[test]
+    fn test_length() {
+        // Emoji outside the BMP are two UTF-16 code units
+        assert_eq!(length("📅"), 2);
+        assert_eq!(split_at_length("a📅b", 2), ("a", "📅b"));
+        assert_eq!(split_at_length("a📅b", 3), ("a📅", "b"));
+        assert_eq!(split_at_length("ab", 5), ("ab", ""));
+
+        let mut message = MessageText::default();
+        message.bold("a < b").hashtag("tag");
+        assert_eq!(message.len(), 9);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let message = message();
+        let footer = "<a href=\"https://telegra.ph/a?b=1&amp;c=&quot;2&quot;\">https://telegra.ph/a</a>\n<code>news#1</code>";
+
+        // Fits
+        assert_eq!(message.truncate(message.len()), message.render());
+
+        // Cut inside the bold text, escaped after cut
+        let limit = message.len() - 10;
+        let truncated = message.truncate(limit);
+        assert_eq!(
+            truncated,
+            format!("#維護\n<b>&lt;停機維護&gt; &amp; 補</b>…{footer}")
+        );
+        assert_eq!(MessageText::from_html(&truncated, "").len(), limit);
+
+        // Only the footer fits
+        let footer_length = length("https://telegra.ph/a\nnews#1");
+        assert_eq!(message.truncate(footer_length), footer);
+        assert_eq!(message.truncate(footer_length + 1), format!("…{footer}"));
+        // The footer is never cut
+        assert_eq!(message.truncate(1), footer);
+        // A hashtag is not cut
+        assert_eq!(message.truncate(footer_length + 3), format!("…{footer}"));
+    }
+
+    #[test]
+    fn test_split() {
+        let message = message();
+        assert_eq!(message.split(TEXT_LIMIT), vec![message.render()]);
+
+        let parts = message.split(20);
+        assert_eq!(
+            parts,
+            vec![
+                "#維護\n<b>&lt;停機維護&gt; &amp; 補償</b>\n",
+                "第一行\n第二行\n",
+                "<a href=\"https://telegra.ph/a?b=1&amp;c=&quot;2&quot;\">https://telegra.ph/a</a>\n<code>news#1</code>",
+            ]
+        );
+
+        // Cut in the middle of a line without line breaks
+        let mut long = MessageText::default();
+        long.text("a".repeat(25))
+            .footer(Fragment::Text("\nend".to_string()));
+        assert_eq!(
+            long.split(10),
+            vec!["a".repeat(10), "a".repeat(10), "aaaaa\nend".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_from_html() {
+        let footer = "\nhttps://telegra.ph/a\n2022-07-01 03:55:00 UTC <code>news#1774</code>";
+        let html = format!(
+            "#維護\n<b>停機 &amp; 維護</b><u>補償</u><s>延長</s>\n\n\
+             <a href=\"https://example.com\">公告</a><code>#1</code>{footer}"
+        );
+        let html = html.as_str();
+        let message = MessageText::from_html(html, footer);
+        assert_eq!(
+            message.body,
+            vec![
+                Fragment::Text("#維護\n".to_string()),
+                Fragment::Bold("停機 & 維護".to_string()),
+                Fragment::Underline("補償".to_string()),
+                Fragment::Strikethrough("延長".to_string()),
+                Fragment::Text("\n\n".to_string()),
+                Fragment::Link {
+                    text: "公告".to_string(),
+                    url: "https://example.com".to_string()
+                },
+                Fragment::Code("#1".to_string()),
+            ]
+        );
+        assert_eq!(
+            message.footer,
+            vec![
+                Fragment::Text("\nhttps://telegra.ph/a\n2022-07-01 03:55:00 UTC ".to_string()),
+                Fragment::Code("news#1774".to_string()),
+            ]
+        );
+        assert_eq!(message.render(), html);
+
+        assert_eq!(fit_html(html, footer, TEXT_LIMIT), html);
+        let fitted = fit_html(html, footer, 60);
+        assert!(fitted
+            .ends_with("…\nhttps://telegra.ph/a\n2022-07-01 03:55:00 UTC <code>news#1774</code>"));
+        assert!(MessageText::from_html(&fitted, footer).len() <= 60);
+
+        // A footer not at the end is ignored
+        let message = MessageText::from_html(html, "<code>#1</code>");
+        assert!(message.footer.is_empty());
+        assert_eq!(message.render(), html);
+    }
+}
@@ -0,0 +1,294 @@
+//! JSON webhook publisher
+//!
+//! Posts are sent as JSON to the configured url. When a secret is set, the body
+//! is signed with HMAC-SHA256 in the `X-Priconne-Signature` header, as
+//! `sha256=<hex digest>`, so that the receiver can verify it.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{header, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use url::Url;
+
+use super::{Publication, Publisher, PublisherKind, SendResult};
+use crate::{resource::ResourceId, Error};
+
+pub const SIGNATURE_HEADER: &str = "X-Priconne-Signature";
+
+/// Delay before the first retry, doubled on each retry.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay between retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    pub url: Url,
+    /// Secret to sign the body with
+    pub secret: Option<String>,
+    /// Times to retry when the delivery fails
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+pub(super) fn default_retries() -> u32 {
+    3
+}
+
+pub struct WebhookPublisher {
+    pub client: reqwest::Client,
+    pub config: WebhookConfig,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookEvent<'a> {
+    title: &'a str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+/// Body of the webhook request.
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    /// `announcement` or `cartoon`
+    kind: &'static str,
+    id: String,
+    title: &'a str,
+    url: Option<&'a Url>,
+    tags: &'a [String],
+    events: Vec<WebhookEvent<'a>>,
+    image: Option<&'a Url>,
+    /// Rendered message in Telegram HTML
+    text: &'a str,
+}
+
+impl<'a> From<&'a Publication> for WebhookPayload<'a> {
+    fn from(publication: &'a Publication) -> Self {
+        let (kind, id) = match &publication.resource_id {
+            ResourceId::Announcement(id) => ("announcement", id.to_hex()),
+            ResourceId::Cartoon(id) => ("cartoon", id.to_string()),
+        };
+
+        Self {
+            kind,
+            id,
+            title: &publication.title,
+            url: publication.url.as_ref(),
+            tags: &publication.tags,
+            events: publication
+                .events
+                .iter()
+                .map(|e| WebhookEvent {
+                    title: &e.title,
+                    start: e.start,
+                    end: e.end,
+                })
+                .collect(),
+            image: publication.image.as_ref(),
+            text: &publication.message.text,
+        }
+    }
+}
+
+/// Signature of `body` with `secret`, as `sha256=<hex digest>`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Post a JSON `body` to `url`, retrying on connection errors, rate limits
+/// and server errors.
+pub(super) async fn post_with_retry(
+    client: &reqwest::Client,
+    url: Url,
+    body: Vec<u8>,
+    secret: Option<&str>,
+    retries: u32,
+) -> Result<reqwest::Response, Error> {
+    let mut attempt = 0;
+    loop {
+        let mut request = client
+            .post(url.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(secret) = secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+
+        let retryable = match request.send().await {
+            Ok(response)
+                if response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                if attempt >= retries {
+                    return Ok(response.error_for_status()?);
+                }
+                format!("status {}", response.status())
+            }
+            Ok(response) => return Ok(response.error_for_status()?),
+            Err(error) if attempt < retries => error.to_string(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let delay = retry_delay(attempt);
+        tracing::warn!("failed to post to {url} ({retryable}), retry in {delay:?}");
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Delay before the retry following `attempt`, at most [`MAX_RETRY_DELAY`].
+fn retry_delay(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .and_then(|factor| RETRY_DELAY.checked_mul(factor))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+#[async_trait]
+impl Publisher for WebhookPublisher {
+    fn kind(&self) -> PublisherKind {
+        PublisherKind::Webhook
+    }
+
+    async fn publish(&self, publication: &Publication) -> Result<SendResult, Error> {
+        let body = serde_json::to_vec(&WebhookPayload::from(publication))?;
+        post_with_retry(
+            &self.client,
+            self.config.url.clone(),
+            body,
+            self.config.secret.as_deref(),
+            self.config.retries,
+        )
+        .await?;
+
+        Ok(SendResult {
+            url: None,
+            backend: PublisherKind::Webhook,
+            recipient: None,
+            chat_id: None,
+            message_id: None,
+            remote_id: None,
+            resource_id: publication.resource_id.clone(),
+            update_time: Utc::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+
+    use super::*;
+    use crate::{chat::Message, resource::Region};
+
+    pub fn publication() -> Publication {
+        Publication {
+            resource_id: ResourceId::Cartoon(1),
+            title: "第 1 話: 美食殿堂".to_string(),
+            url: None,
+            tags: vec!["漫畫".to_string()],
+            events: vec![],
+            image: Some(Url::parse("https://example.com/1.png").unwrap()),
+            source: None,
+            region: Region::TW,
+            message: Message {
+                text: "<b>第 1 話</b>: 美食殿堂".to_string(),
+                silent: false,
+                image_src: None,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), RETRY_DELAY);
+        assert_eq!(retry_delay(2), RETRY_DELAY * 4);
+        assert_eq!(retry_delay(10), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(40), MAX_RETRY_DELAY);
+    }
+
+    #[derive(Clone, Default)]
+    struct Received {
+        count: Arc<AtomicUsize>,
+        last: Arc<Mutex<Option<(String, String)>>>,
+    }
+
+    async fn flaky(
+        State(received): State<Received>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+        *received.last.lock().unwrap() = Some((signature, body));
+        // Fail on the first attempt
+        match received.count.fetch_add(1, Ordering::SeqCst) {
+            0 => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::NO_CONTENT,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_with_retry() -> Result<(), Error> {
+        let received = Received::default();
+        let app = Router::new()
+            .route("/hook", post(flaky))
+            .with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(axum::Server::from_tcp(listener)?.serve(app.into_make_service()));
+
+        let publisher = WebhookPublisher {
+            client: reqwest::Client::new(),
+            config: WebhookConfig {
+                url: Url::parse(&format!("http://{addr}/hook"))?,
+                secret: Some("secret".to_string()),
+                retries: 1,
+            },
+        };
+        let result = publisher.publish(&publication()).await?;
+        assert_eq!(result.backend, PublisherKind::Webhook);
+        assert_eq!(received.count.load(Ordering::SeqCst), 2);
+
+        let (signature, body) = received.last.lock().unwrap().clone().unwrap();
+        assert_eq!(signature, sign("secret", body.as_bytes()));
+        let body: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(body["kind"], "cartoon");
+        assert_eq!(body["id"], "1");
+        assert_eq!(body["tags"][0], "漫畫");
+
+        // Without retries, the first failure is returned
+        received.count.store(0, Ordering::SeqCst);
+        let publisher = WebhookPublisher {
+            config: WebhookConfig {
+                retries: 0,
+                ..publisher.config
+            },
+            ..publisher
+        };
+        assert!(publisher.publish(&publication()).await.is_err());
+        Ok(())
+    }
+}
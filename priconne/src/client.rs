@@ -12,21 +12,32 @@
 //! [`MemorizedResourceClient`], which will cache the resources in the database.
 //! Only new resources will be fetched and parsed.
 
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use futures::stream::BoxStream;
-use mongodb::{bson::doc, Collection};
 
 use crate::{
-    chat::Sendable, database::ResourceMetadataCollection, resource::ResourceMetadata, Error,
+    chat::Sendable, database::MetadataRepository, resource::ResourceMetadata, utils::Tz, Error,
 };
 
 /// Types for [`MemorizedResourceClient`].
 mod memorize;
 pub use memorize::{FetchState, FetchStrategy, MemorizedResourceClient, MetadataFindResult};
 
+/// HTTP transport with record and replay support.
+mod transport;
+pub use transport::{
+    cassette_path, save_interaction, HttpResponse, HttpTransport, Interaction, TransportConfig,
+};
+
 /// The response from a resource client.
 pub trait ResourceResponse {
-    fn telegraph_content(&self, _extra: Option<String>) -> Result<Option<String>, crate::Error> {
+    /// Serialized Telegraph content, one for each page.
+    fn telegraph_content(
+        &self,
+        _extra: Option<String>,
+    ) -> Result<Option<Vec<String>>, crate::Error> {
         Ok(None)
     }
 }
@@ -42,6 +53,8 @@ where
 {
     type Response: ResourceResponse;
     fn try_stream(&self) -> BoxStream<Result<M, Error>>;
+    /// Time zone of local times in the metadata.
+    fn time_zone(&self) -> Tz;
     async fn get_by_id(&self, id: i32) -> Result<Self::Response, Error>;
     async fn fetch(&self, resource: &M) -> Result<Self::Response, Error> {
         self.get_by_id(resource.id()).await
@@ -49,7 +62,7 @@ where
     /// Create a [`MemorizedResourceClient`] from this client.
     fn memorize(
         self,
-        collection: Collection<M>,
+        collection: Arc<dyn MetadataRepository<M>>,
         strategy: FetchStrategy,
     ) -> MemorizedResourceClient<M, Self>
     where
@@ -4,11 +4,12 @@
 //! [`FetchState`] to determine whether a resource should be fetched.
 //! [`MetadataFindResult`] is the resource metadata find result in database.
 
-use super::{ResourceClient, ResourceMetadataCollection};
-use crate::{resource::ResourceMetadata, Error};
+use std::sync::Arc;
+
+use super::ResourceClient;
+use crate::{database::MetadataRepository, resource::ResourceMetadata, utils::Tz, Error};
 use async_trait::async_trait;
 use futures::{future, stream::BoxStream, TryStreamExt};
-use mongodb::{bson::doc, Collection};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -20,7 +21,7 @@ where
 {
     pub client: Client,
     pub strategy: FetchStrategy,
-    pub collection: ResourceMetadataCollection<M>,
+    pub collection: Arc<dyn MetadataRepository<M>>,
 }
 
 impl<M, Client> MemorizedResourceClient<M, Client>
@@ -28,11 +29,15 @@ where
     Client: ResourceClient<M>,
     M: ResourceMetadata,
 {
-    pub(super) fn new(client: Client, strategy: FetchStrategy, collection: Collection<M>) -> Self {
+    pub(super) fn new(
+        client: Client,
+        strategy: FetchStrategy,
+        collection: Arc<dyn MetadataRepository<M>>,
+    ) -> Self {
         Self {
             client,
             strategy,
-            collection: ResourceMetadataCollection::new(collection),
+            collection,
         }
     }
 
@@ -48,7 +53,7 @@ where
     }
 
     pub(crate) async fn upsert(&self, item: &M) -> Result<Option<M>, Error> {
-        self.collection.upsert(item).await.map_err(Error::from)
+        self.collection.upsert(item).await
     }
 
     /// Fetches all resources and their state in the database, as a stream.
@@ -71,7 +76,7 @@ where
         Self: Sync,
         M: Send + 'stream,
     {
-        let mut fetch_state = self.strategy.build();
+        let mut fetch_state = self.strategy.build(self.client.time_zone());
         let result = self
             .compared_stream()
             .try_take_while(move |update| {
@@ -113,6 +118,9 @@ where
     fn try_stream(&self) -> BoxStream<Result<M, Error>> {
         self.client.try_stream()
     }
+    fn time_zone(&self) -> Tz {
+        self.client.time_zone()
+    }
     async fn get_by_id(&self, id: i32) -> Result<Self::Response, Error> {
         self.client.get_by_id(id).await
     }
@@ -130,8 +138,8 @@ pub struct FetchStrategy {
 }
 
 impl FetchStrategy {
-    pub fn build(&self) -> FetchState<i32> {
-        FetchState::new(self.clone())
+    pub fn build(&self, time_zone: Tz) -> FetchState<i32> {
+        FetchState::new(self.clone(), time_zone)
     }
     pub fn override_by(self, rhs: &Self) -> Self {
         Self {
@@ -161,20 +169,23 @@ impl Default for FetchStrategy {
 pub struct FetchState<I> {
     pub strategy: FetchStrategy,
     pub fuse_count: I,
+    /// Time zone of the metadata
+    pub time_zone: Tz,
 }
 
 impl FetchState<i32> {
-    pub fn new(strategy: FetchStrategy) -> Self {
+    pub fn new(strategy: FetchStrategy, time_zone: Tz) -> Self {
         Self {
             strategy,
             fuse_count: 0,
+            time_zone,
         }
     }
 
     pub fn keep_going<M: ResourceMetadata>(&mut self, found: &MetadataFindResult<M>) -> bool {
         let metadata = found.item();
         let id = metadata.id();
-        let update_time = metadata.update_time();
+        let update_time = metadata.update_time(self.time_zone);
 
         // For fuse_count calculating
         let mut in_range = true;
@@ -0,0 +1,197 @@
+//! HTTP transport for resource clients.
+//!
+//! Resource clients send their requests through [`HttpTransport`] instead of
+//! [`reqwest::Client`]. Besides passing requests through, the transport can
+//! record every response to a cassette directory, and replay them later
+//! without network access.
+
+use std::path::{Path, PathBuf};
+
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use url::Url;
+
+use crate::Error;
+
+/// Transport mode in configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase", tag = "mode", content = "dir")]
+pub enum TransportConfig {
+    /// Send requests to remote, the normal behavior.
+    #[default]
+    Passthrough,
+    /// Send requests to remote, and save responses to the cassette directory.
+    Record(PathBuf),
+    /// Serve responses from the cassette directory only.
+    Replay(PathBuf),
+}
+
+impl TransportConfig {
+    pub fn build(&self, client: reqwest::Client) -> HttpTransport {
+        match self {
+            TransportConfig::Passthrough => HttpTransport::Passthrough(client),
+            TransportConfig::Record(dir) => HttpTransport::Record {
+                client,
+                dir: dir.clone(),
+            },
+            TransportConfig::Replay(dir) => HttpTransport::Replay { dir: dir.clone() },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HttpTransport {
+    Passthrough(reqwest::Client),
+    Record {
+        client: reqwest::Client,
+        dir: PathBuf,
+    },
+    Replay {
+        dir: PathBuf,
+    },
+}
+
+/// A fully read response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpResponse {
+    /// Final url after redirection.
+    pub url: Url,
+    pub status: u16,
+    pub body: String,
+}
+
+/// A request and response pair saved in a cassette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: Url,
+    pub response: HttpResponse,
+}
+
+impl HttpResponse {
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn text(self) -> String {
+        self.body
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        serde_json::from_str(&self.body).map_err(Error::from)
+    }
+}
+
+impl From<reqwest::Client> for HttpTransport {
+    fn from(client: reqwest::Client) -> Self {
+        HttpTransport::Passthrough(client)
+    }
+}
+
+impl HttpTransport {
+    pub async fn get(&self, url: Url) -> Result<HttpResponse, Error> {
+        match self {
+            HttpTransport::Passthrough(client) => send(client, url).await,
+            HttpTransport::Record { client, dir } => {
+                let response = send(client, url.clone()).await?;
+                let interaction = Interaction {
+                    request: url,
+                    response,
+                };
+                save_interaction(dir, &interaction).await?;
+                Ok(interaction.response)
+            }
+            HttpTransport::Replay { dir } => {
+                let path = cassette_path(dir, &url);
+                let json = match tokio::fs::read_to_string(&path).await {
+                    Ok(json) => json,
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                        return Err(Error::CassetteMissError(url))
+                    }
+                    Err(error) => return Err(error.into()),
+                };
+                let interaction: Interaction = serde_json::from_str(&json)?;
+                tracing::trace!("replay {} from {}", url, path.display());
+                Ok(interaction.response)
+            }
+        }
+    }
+}
+
+async fn send(client: &reqwest::Client, url: Url) -> Result<HttpResponse, Error> {
+    let response = client.get(url).send().await?;
+    Ok(HttpResponse {
+        url: response.url().clone(),
+        status: response.status().as_u16(),
+        body: response.text().await?,
+    })
+}
+
+/// Save an interaction to the cassette directory, replacing the old one.
+pub async fn save_interaction(dir: &Path, interaction: &Interaction) -> Result<(), Error> {
+    tokio::fs::create_dir_all(dir).await?;
+    let json = serde_json::to_string_pretty(interaction)?;
+    tokio::fs::write(cassette_path(dir, &interaction.request), json).await?;
+    Ok(())
+}
+
+/// File in the cassette directory for `url`.
+///
+/// The name is the url without scheme, with characters other than
+/// alphanumerics, `.` and `-` replaced by `_`.
+pub fn cassette_path(dir: &Path, url: &Url) -> PathBuf {
+    let url = url.as_str();
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let name: String = url
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    dir.join(format!("{name}.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cassette_path() {
+        let url = Url::parse("http://www.princessconnect.so-net.tw/news?page=2").unwrap();
+        assert_eq!(
+            cassette_path(Path::new("cassettes"), &url),
+            Path::new("cassettes/www.princessconnect.so-net.tw_news_page_2.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("priconne-test-replay");
+        let request = Url::parse("https://api-pc.so-net.tw/information/ajax_announce?offset=0")?;
+        let interaction = Interaction {
+            request: request.clone(),
+            response: HttpResponse {
+                url: request.clone(),
+                status: 200,
+                body: "[1, 2, 3]".to_string(),
+            },
+        };
+        save_interaction(&dir, &interaction).await?;
+
+        let transport = TransportConfig::Replay(dir.clone()).build(reqwest::Client::new());
+        let response = transport.get(request).await?;
+        assert_eq!(response.json::<Vec<i32>>()?, vec![1, 2, 3]);
+
+        let missing = transport
+            .get(Url::parse("https://api-pc.so-net.tw/missing")?)
+            .await;
+        assert!(matches!(missing, Err(Error::CassetteMissError(_))));
+
+        tokio::fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+}
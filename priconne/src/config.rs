@@ -2,7 +2,7 @@
 //!
 //! This module contains configuration for priconne.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -11,11 +11,24 @@ use tracing::info;
 use url::Url;
 
 use crate::{
-    chat::ChatManager,
-    client::FetchStrategy,
+    chat::{
+        ChatManager, DiscordConfig, DiscordPublisher, Publisher, Route, RouteFilter, RouteKind,
+        Router, TelegramPublisher, WebhookConfig, WebhookPublisher,
+    },
+    client::{FetchStrategy, TransportConfig},
+    database::{EmbeddedStore, Storage},
+    digest::DigestConfig,
+    image::{ImageHost, ImageStoreConfig},
     insight::{tagging::RegexTagger, Extractor},
-    resource::{api::ApiServer, ResourceKind},
+    maintenance::MaintenanceConfig,
+    reminder::ReminderConfig,
+    resource::{
+        api::ApiServer, post::PostConfig, sources::AnnouncementSource, Region, ResourceKind,
+    },
+    search::{SearchConfig, SearchIndex},
     service::PriconneService,
+    template::{TemplateConfig, Templates},
+    utils::{Tz, DEFAULT_TIME_ZONE},
 };
 
 /// This is useful for setting values in builder.
@@ -38,13 +51,31 @@ pub struct PriconneConfig {
     pub tags: TaggerConfig,
     /// Client configuration, such as proxy, user agent, etc.
     pub client: ClientConfig,
-    /// MongoDB configuration
-    pub mongo: MongoConfig,
+    /// Storage backend
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// MongoDB configuration, required by the `mongo` storage backend
+    pub mongo: Option<MongoConfig>,
+    /// How long to keep stored data
+    #[serde(default)]
+    pub retention: RetentionConfig,
     /// Telegram bot configuration
     pub telegram: TelegramConfig,
     /// Telegraph configuration
     pub telegraph: TelegraphConfig,
     pub fetch: FetchConfig,
+    /// Reminders before events start or end
+    #[serde(default)]
+    pub reminders: Vec<ReminderConfig>,
+    /// Full-text search index
+    #[serde(default)]
+    pub search: SearchConfig,
+    /// Where images of posts are re-hosted
+    #[serde(default)]
+    pub images: ImageStoreConfig,
+    /// Templates of messages
+    #[serde(default)]
+    pub templates: TemplateConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -58,6 +89,33 @@ pub struct FetchConfig {
     /// Dry run only
     #[serde(default)]
     pub dry_run: bool,
+    /// Directory to write dry run previews, print them to stdout if not set
+    pub preview_dir: Option<PathBuf>,
+    /// HTTP transport for fetching resources, can record or replay responses
+    #[serde(default)]
+    pub transport: TransportConfig,
+    /// How posts are rendered
+    #[serde(default)]
+    pub post: PostConfig,
+}
+
+/// Where resources, announcements and messages are stored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase", tag = "backend")]
+pub enum StorageConfig {
+    /// MongoDB, configured by [`MongoConfig`]
+    #[default]
+    Mongo,
+    /// File-backed store in a local directory, no database server required
+    Embedded { path: PathBuf },
+}
+
+/// Old data removed when the server starts, see [`PriconneConfig::housekeep`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RetentionConfig {
+    /// Days to keep archived pages, forever if not set.
+    /// The latest page of each announcement is always kept for reparsing.
+    pub archive_days: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -85,6 +143,12 @@ pub struct TelegraphConfig {
 pub struct ServerConfig {
     pub news: Url,
     pub api: Vec<ApiServer>,
+    /// Region of the servers
+    #[serde(default)]
+    pub region: Region,
+    /// Time zone of the news site, default to the zone of `region`
+    #[schemars(with = "Option<String>")]
+    pub time_zone: Option<Tz>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -107,16 +171,61 @@ pub struct TelegramConfig {
     // #[schemars(with = "i64")]
     // pub debug_chat: teloxide::types::ChatId,
     pub recipient: RecipientConfig,
+    /// Daily digest, scheduled as `digest` in `fetch.schedule`
+    pub digest: Option<DigestConfig>,
+    /// Maintenance notices, checked as `maintenance` in `fetch.schedule`
+    pub maintenance: Option<MaintenanceConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RecipientConfig {
     #[schemars(with = "RemoteRecipient")]
     pub debug: Recipient,
-    #[schemars(with = "RemoteRecipient")]
-    pub post: Recipient,
-    #[schemars(with = "RemoteRecipient")]
-    pub cartoon: Recipient,
+    /// Receives all announcements
+    pub post: Option<PublisherConfig>,
+    /// Receives all cartoons
+    pub cartoon: Option<PublisherConfig>,
+    /// Additional routes, a publication is sent to every matching route
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Priority of tags, used by `min_priority` of routes
+    #[serde(default)]
+    pub priorities: HashMap<String, i32>,
+    /// Time zone that times are shown in, default to `Asia/Taipei`
+    #[serde(default = "default_time_zone")]
+    #[schemars(with = "String")]
+    pub time_zone: Tz,
+}
+
+fn default_time_zone() -> Tz {
+    DEFAULT_TIME_ZONE
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RouteConfig {
+    pub recipient: PublisherConfig,
+    #[serde(flatten)]
+    pub filter: RouteFilter,
+}
+
+/// Where a recipient's messages are delivered.
+///
+/// A chat id or channel username is sent by the Telegram bot,
+/// otherwise the publisher is selected by `backend`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum PublisherConfig {
+    Telegram(#[schemars(with = "RemoteRecipient")] Recipient),
+    Backend(BackendConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase", tag = "backend")]
+pub enum BackendConfig {
+    /// POST JSON payloads to a url
+    Webhook(WebhookConfig),
+    /// Discord webhook
+    Discord(DiscordConfig),
 }
 
 /// A unique identifier for the target chat or username of the target channel
@@ -148,6 +257,32 @@ impl ServerConfig {
     pub fn api_server_by_id(&self, id: &str) -> Option<&ApiServer> {
         self.api.iter().filter(|x| x.id == id).nth(0)
     }
+
+    /// Time zone of times on pages of `source`, falling back to the zone of
+    /// the region.
+    pub fn time_zone(&self, source: &AnnouncementSource) -> Tz {
+        let zone = match source {
+            AnnouncementSource::Api(id) => self
+                .api_server_by_id(id)
+                .and_then(|server| server.time_zone),
+            AnnouncementSource::Website => self.time_zone,
+        };
+        zone.unwrap_or_else(|| self.region.time_zone())
+    }
+
+    /// Time zones of all sources.
+    pub fn time_zones(&self) -> HashMap<AnnouncementSource, Tz> {
+        let mut sources: Vec<_> = self
+            .api
+            .iter()
+            .map(|server| AnnouncementSource::Api(server.id.clone()))
+            .collect();
+        sources.push(AnnouncementSource::Website);
+        sources
+            .into_iter()
+            .map(|source| (source.clone(), self.time_zone(&source)))
+            .collect()
+    }
 }
 
 impl MongoConfig {
@@ -243,6 +378,57 @@ impl TelegramConfig {
     }
 }
 
+impl PublisherConfig {
+    pub fn build(&self, bot: &teloxide::Bot, client: &reqwest::Client) -> Arc<dyn Publisher> {
+        match self {
+            PublisherConfig::Telegram(recipient) => Arc::new(TelegramPublisher {
+                bot: bot.clone(),
+                recipient: recipient.clone(),
+            }),
+            PublisherConfig::Backend(BackendConfig::Webhook(config)) => {
+                Arc::new(WebhookPublisher {
+                    client: client.clone(),
+                    config: config.clone(),
+                })
+            }
+            PublisherConfig::Backend(BackendConfig::Discord(config)) => {
+                Arc::new(DiscordPublisher {
+                    client: client.clone(),
+                    config: config.clone(),
+                })
+            }
+        }
+    }
+}
+
+impl RecipientConfig {
+    pub fn build_router(&self, bot: &teloxide::Bot, client: &reqwest::Client) -> Router {
+        let defaults = [
+            (&self.post, RouteKind::Announcement),
+            (&self.cartoon, RouteKind::Cartoon),
+        ];
+        let mut routes: Vec<Route> = defaults
+            .into_iter()
+            .filter_map(|(recipient, kind)| {
+                recipient.as_ref().map(|recipient| Route {
+                    publisher: recipient.build(bot, client),
+                    filter: RouteFilter::kind(kind),
+                })
+            })
+            .collect();
+
+        routes.extend(self.routes.iter().map(|route| Route {
+            publisher: route.recipient.build(bot, client),
+            filter: route.filter.clone(),
+        }));
+
+        Router {
+            routes,
+            priorities: self.priorities.clone(),
+        }
+    }
+}
+
 impl StrategyConfig {
     pub fn build_for(&self, kind: ResourceKind) -> FetchStrategy {
         // let name = resource.name().to_owned();
@@ -257,23 +443,81 @@ impl StrategyConfig {
 }
 
 impl PriconneConfig {
+    pub async fn build_storage(&self) -> Result<Storage, crate::Error> {
+        // Dry runs must not touch the database, so everything fetched is new
+        if self.fetch.dry_run {
+            return Ok(Storage::Embedded(EmbeddedStore::in_memory()));
+        }
+
+        let storage = match &self.storage {
+            StorageConfig::Mongo => {
+                let mongo = self
+                    .mongo
+                    .as_ref()
+                    .ok_or(crate::Error::MissingConfigError("mongo"))?;
+                Storage::Mongo(mongo.build().await?)
+            }
+            StorageConfig::Embedded { path } => Storage::Embedded(EmbeddedStore::open(path)?),
+        };
+
+        Ok(storage)
+    }
+
+    /// Create indexes of `storage` and remove data older than the retention.
+    pub async fn housekeep(&self, storage: &Storage) -> Result<(), crate::Error> {
+        storage.ensure_indexes().await?;
+        if let Some(days) = self.retention.archive_days {
+            let time = chrono::Utc::now() - chrono::Duration::days(days.into());
+            let pruned = storage.page_archive()?.prune(time).await?;
+            info!("pruned {pruned} archived page(s) fetched before {time}");
+        }
+        Ok(())
+    }
+
+    pub fn build_search_index(&self) -> Result<SearchIndex, crate::Error> {
+        match &self.search.path {
+            Some(path) if !self.fetch.dry_run => SearchIndex::open(path),
+            _ => Ok(SearchIndex::in_memory()),
+        }
+    }
+
     pub async fn build(&self) -> Result<PriconneService, crate::Error> {
         let client = self.client.build()?;
         let telegraph = self.telegraph.with_client(client.clone()).await?;
-        let database = self.mongo.build().await?;
+        let storage = self.build_storage().await?;
         let bot = self.telegram.with_client(client.clone()).await?;
         let tagger = self.tags.build()?;
-        let extractor = Extractor { tagger };
+        let extractor = Extractor {
+            tagger,
+            time_zones: self.fetch.server.time_zones(),
+        };
         let config = self.fetch.clone();
+        let search = self.build_search_index()?;
+        let images = ImageHost::new(client.clone(), self.images.clone(), storage.images()?);
+        let templates = Templates::new(&self.templates, self.telegram.recipient.time_zone)?;
         let chat_manager = ChatManager {
+            router: self.telegram.recipient.build_router(&bot, &client),
             bot,
             config: self.telegram.clone(),
-            post_recipient: self.telegram.recipient.post.clone(),
-            cartoon_recipient: self.telegram.recipient.cartoon.clone(),
-            messages: database.collection("messages"),
+            messages: storage.messages()?,
+            subscriptions: storage.subscriptions()?,
         };
 
-        PriconneService::new(database, chat_manager, telegraph, client, config, extractor)
+        PriconneService::new(
+            storage,
+            chat_manager,
+            telegraph,
+            client,
+            config,
+            extractor,
+            self.reminders.clone(),
+        )
+        .map(|priconne| {
+            priconne
+                .with_search(search)
+                .with_images(images)
+                .with_templates(templates)
+        })
     }
 }
 
@@ -303,11 +547,13 @@ mod tests {
             bot_config.telegram.listen_addr,
             Some("127.0.0.1:5555".to_string())
         );
+        let mongo = bot_config.mongo.as_ref().unwrap();
         assert_eq!(
-            bot_config.mongo.connection_string,
+            mongo.connection_string,
             "mongodb://localhost:27017".to_string()
         );
-        assert_eq!(bot_config.mongo.database, "test".to_string());
+        assert_eq!(mongo.database, "test".to_string());
+        assert!(matches!(bot_config.storage, StorageConfig::Mongo));
         assert_eq!(bot_config.tags.0.len(), 2);
     }
 
@@ -336,9 +582,68 @@ mod tests {
         let config = File::open("tests/config.yaml").unwrap();
         let bot_config: PriconneConfig = serde_yaml::from_reader(config).unwrap();
         let priconne = bot_config.build().await.unwrap();
-        assert_eq!(
-            priconne.chat_manager.post_recipient,
-            Recipient::ChannelUsername("@pcrtwstat".to_string())
+        assert_eq!(priconne.chat_manager.router.routes.len(), 2)
+    }
+
+    #[test]
+    fn test_deserialize_recipient() {
+        let config: RecipientConfig = serde_yaml::from_str(
+            r#"
+            debug: 0
+            post: "@pcrtwstat"
+            cartoon:
+              backend: discord
+              url: https://discord.com/api/webhooks/1/token
+            "#,
         )
+        .unwrap();
+
+        assert!(matches!(
+            config.post,
+            Some(PublisherConfig::Telegram(Recipient::ChannelUsername(_)))
+        ));
+        let Some(PublisherConfig::Backend(BackendConfig::Discord(discord))) = config.cartoon else {
+            panic!("cartoon should be sent to discord");
+        };
+        assert_eq!(discord.retries, 3);
+        assert!(discord.username.is_none());
+    }
+
+    #[test]
+    fn test_deserialize_routes() {
+        let config: RecipientConfig = serde_yaml::from_str(
+            r#"
+            debug: 0
+            priorities:
+              停機維護: 10
+            routes:
+              - recipient: "@pcrtw_gacha"
+                kinds: [announcement]
+                include_tags: [精選轉蛋, 公主祭典]
+              - recipient: -100123
+                exclude_tags: [補償]
+                sources: [PROD1]
+                regions: [TW]
+                min_priority: 10
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.post.is_none());
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(config.routes[0].filter.kinds, vec![RouteKind::Announcement]);
+        assert_eq!(config.routes[0].filter.include_tags.len(), 2);
+        assert!(matches!(
+            config.routes[1].recipient,
+            PublisherConfig::Telegram(Recipient::Id(ChatId(-100123)))
+        ));
+        assert_eq!(config.routes[1].filter.min_priority, Some(10));
+        assert_eq!(config.priorities["停機維護"], 10);
+
+        let router = config.build_router(
+            &teloxide::Bot::new("123456789:token"),
+            &reqwest::Client::new(),
+        );
+        assert_eq!(router.routes.len(), 2);
     }
 }
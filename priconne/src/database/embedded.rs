@@ -0,0 +1,623 @@
+//! Embedded backend
+//!
+//! A small file-backed document store, so the bot can run without MongoDB.
+//! Each collection is kept in memory as BSON documents. On disk, it is a
+//! snapshot in `<path>/<collection>.json`, and a journal of later changes in
+//! `<path>/<collection>.journal`, one per line, both in canonical extended JSON.
+//! A change only appends to the journal, and the snapshot is rewritten once the
+//! journal is long. When no path is given, nothing is written to disk.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, oid::ObjectId, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use teloxide::types::ChatId;
+
+use super::{
+    announcement_events,
+    migration::{schema_version, to_versioned_document},
+    AnnouncementRepository, DigestRepository, DocumentRepository, EventRepository, ImageRepository,
+    MaintenanceRepository, MessageRepository, MetadataRepository, PageArchiveRepository,
+    PostRepository, SubscriptionRepository,
+};
+use crate::{
+    archive::ArchivedPage,
+    chat::{SendResult, Subscription},
+    digest::DigestMessage,
+    image::HostedImage,
+    maintenance::{Maintenance, MaintenanceStatus},
+    resource::{
+        announcement::sources::AnnouncementSource,
+        event::Event,
+        post::{Post, PostSource, StoredPost},
+        Announcement, ResourceId, ResourceMetadata,
+    },
+    utils::map_title,
+    Error, Result,
+};
+
+/// Changes in a journal before it is merged into the snapshot.
+const COMPACT_AFTER: usize = 1000;
+
+/// A collection shared by its handles.
+#[derive(Debug, Default)]
+struct Documents {
+    documents: Mutex<Vec<Document>>,
+    /// Changes in the journal, held while writing to disk so that changes are
+    /// written in the order they are made
+    journal: tokio::sync::Mutex<usize>,
+}
+
+/// A change to a collection, as written in the journal.
+#[derive(Debug, Clone)]
+enum Change {
+    /// Add a document, unless one with the same `_id` exists
+    Insert(Document),
+    /// Replace the document with the same `_id`, or add it
+    Upsert(Document),
+    /// Remove documents by `_id`
+    Remove(Vec<Bson>),
+}
+
+impl Change {
+    fn apply(self, documents: &mut Vec<Document>) {
+        match self {
+            Change::Insert(document) => {
+                if !documents
+                    .iter()
+                    .any(|d| d.get("_id") == document.get("_id"))
+                {
+                    documents.push(document);
+                }
+            }
+            Change::Upsert(document) => {
+                match documents
+                    .iter_mut()
+                    .find(|d| d.get("_id") == document.get("_id"))
+                {
+                    Some(old) => *old = document,
+                    None => documents.push(document),
+                }
+            }
+            Change::Remove(ids) => {
+                documents.retain(|d| d.get("_id").is_none_or(|id| !ids.contains(id)))
+            }
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Change::Insert(document) => {
+                json!({ "insert": Bson::Document(document.clone()).into_canonical_extjson() })
+            }
+            Change::Upsert(document) => {
+                json!({ "upsert": Bson::Document(document.clone()).into_canonical_extjson() })
+            }
+            Change::Remove(ids) => {
+                json!({ "remove": Bson::Array(ids.clone()).into_canonical_extjson() })
+            }
+        }
+    }
+
+    fn from_json(value: serde_json::Value) -> Option<Self> {
+        let serde_json::Value::Object(object) = value else {
+            return None;
+        };
+        let (kind, value) = object.into_iter().next()?;
+        match (kind.as_str(), Bson::try_from(value).ok()?) {
+            ("insert", Bson::Document(document)) => Some(Change::Insert(document)),
+            ("upsert", Bson::Document(document)) => Some(Change::Upsert(document)),
+            ("remove", Bson::Array(ids)) => Some(Change::Remove(ids)),
+            _ => None,
+        }
+    }
+}
+
+/// A set of collections stored in one directory.
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddedStore {
+    path: Option<PathBuf>,
+    collections: Arc<Mutex<HashMap<String, Arc<Documents>>>>,
+}
+
+/// A typed handle to a collection in [`EmbeddedStore`].
+pub struct EmbeddedCollection<T> {
+    name: String,
+    path: Option<PathBuf>,
+    documents: Arc<Documents>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl EmbeddedStore {
+    /// Open a store in directory `path`, creating it if not exists.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        Ok(Self {
+            path: Some(path.as_ref().to_owned()),
+            collections: Default::default(),
+        })
+    }
+
+    /// A store that lives only in memory.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn collection<T>(&self, name: &str) -> Result<EmbeddedCollection<T>> {
+        let mut collections = self.collections.lock().unwrap();
+        let documents = match collections.get(name) {
+            Some(documents) => documents.clone(),
+            None => {
+                let documents = Arc::new(self.load(name)?);
+                collections.insert(name.to_owned(), documents.clone());
+                documents
+            }
+        };
+
+        Ok(EmbeddedCollection {
+            name: name.to_owned(),
+            path: self.path.clone(),
+            documents,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Read the snapshot of a collection, and replay its journal.
+    fn load(&self, name: &str) -> Result<Documents> {
+        let Some(path) = &self.path else {
+            return Ok(Documents::default());
+        };
+
+        let mut documents = Vec::new();
+        let file = path.join(format!("{name}.json"));
+        if file.exists() {
+            let json: Vec<serde_json::Value> =
+                serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            for value in json {
+                match Bson::try_from(value) {
+                    Ok(Bson::Document(document)) => documents.push(document),
+                    _ => {
+                        return Err(Error::EmbeddedStoreError(format!(
+                            "invalid document in collection {name}"
+                        )))
+                    }
+                }
+            }
+        }
+
+        let mut changes = 0;
+        let journal = path.join(format!("{name}.journal"));
+        if journal.exists() {
+            for line in std::fs::read_to_string(journal)?.lines() {
+                // The last line may be cut by a crash
+                let Some(change) = serde_json::from_str(line).ok().and_then(Change::from_json)
+                else {
+                    tracing::warn!("skip invalid change in the journal of {name}");
+                    continue;
+                };
+                change.apply(&mut documents);
+                changes += 1;
+            }
+        }
+
+        Ok(Documents {
+            documents: Mutex::new(documents),
+            journal: tokio::sync::Mutex::new(changes),
+        })
+    }
+}
+
+impl<T> EmbeddedCollection<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Apply `change` in memory, then append it to the journal, or rewrite the
+    /// snapshot if the journal is long.
+    async fn commit(&self, change: Change) -> Result<()> {
+        let mut journal = self.documents.journal.lock().await;
+        let line = self.path.as_ref().map(|_| change.to_json().to_string());
+        change.apply(&mut self.documents.documents.lock().unwrap());
+        let (Some(path), Some(line)) = (&self.path, line) else {
+            return Ok(());
+        };
+
+        let file = path.join(format!("{}.journal", self.name));
+        if *journal + 1 < COMPACT_AFTER {
+            blocking(move || {
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(file)?;
+                writeln!(file, "{line}")
+            })
+            .await?;
+            *journal += 1;
+            return Ok(());
+        }
+
+        let json: Vec<serde_json::Value> = self
+            .documents
+            .documents
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|document| Bson::Document(document.clone()).into_canonical_extjson())
+            .collect();
+        let json = serde_json::to_string(&json)?;
+        let snapshot = path.join(format!("{}.json", self.name));
+        let temp = path.join(format!("{}.json.tmp", self.name));
+        blocking(move || {
+            std::fs::write(&temp, json)?;
+            std::fs::rename(temp, snapshot)?;
+            // Replaying changes already in the snapshot is harmless
+            std::fs::remove_file(file)
+        })
+        .await?;
+        *journal = 0;
+        Ok(())
+    }
+
+    /// All documents matching `predicate`, in insertion order.
+    pub fn find(&self, predicate: impl Fn(&T) -> bool) -> Result<Vec<T>> {
+        let documents = self.documents.documents.lock().unwrap();
+        let mut result = Vec::new();
+        for document in documents.iter() {
+            let item: T = bson::from_document(document.clone())?;
+            if predicate(&item) {
+                result.push(item);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Find the document with `_id`.
+    pub fn find_by_id(&self, id: impl Into<Bson>) -> Result<Option<T>> {
+        let id = id.into();
+        let documents = self.documents.documents.lock().unwrap();
+        documents
+            .iter()
+            .find(|document| document.get("_id") == Some(&id))
+            .map(|document| bson::from_document(document.clone()))
+            .transpose()
+            .map_err(Error::from)
+    }
+
+    /// Insert a document, generating an `_id` if it doesn't have one.
+    pub async fn insert(&self, item: &T) -> Result<()> {
+        let mut document = to_versioned_document(&self.name, item)?;
+        if !document.contains_key("_id") {
+            document.insert("_id", ObjectId::new());
+        }
+        self.commit(Change::Insert(document)).await
+    }
+
+    /// Replace the document with the same `_id` or insert a new one,
+    /// returns the old document.
+    pub async fn upsert(&self, item: &T) -> Result<Option<T>> {
+        let document = to_versioned_document(&self.name, item)?;
+        let id = document
+            .get("_id")
+            .cloned()
+            .ok_or_else(|| Error::EmbeddedStoreError(format!("no _id in {}", self.name)))?;
+
+        self.replace(id, document).await
+    }
+
+    /// Same as [`upsert`](Self::upsert), but use `id` as `_id`
+    /// when the document doesn't have one.
+    pub async fn upsert_by_id(&self, id: impl Into<Bson>, item: &T) -> Result<Option<T>> {
+        let id = id.into();
+        let mut document = to_versioned_document(&self.name, item)?;
+        if !document.contains_key("_id") {
+            document.insert("_id", id.clone());
+        }
+
+        self.replace(id, document).await
+    }
+
+    async fn replace(&self, id: Bson, document: Document) -> Result<Option<T>> {
+        let old = self.find_by_id(id)?;
+        self.commit(Change::Upsert(document)).await?;
+        Ok(old)
+    }
+
+    /// Remove documents matching `predicate`, returns the removed count.
+    pub async fn remove(&self, predicate: impl Fn(&T) -> bool) -> Result<usize> {
+        let mut ids = Vec::new();
+        for document in self.documents.documents.lock().unwrap().iter() {
+            let item: T = bson::from_document(document.clone())?;
+            if predicate(&item) {
+                ids.extend(document.get("_id").cloned());
+            }
+        }
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let removed = ids.len();
+        self.commit(Change::Remove(ids)).await?;
+        Ok(removed)
+    }
+}
+
+/// Run blocking file operations off the async runtime.
+async fn blocking(f: impl FnOnce() -> std::io::Result<()> + Send + 'static) -> Result<()> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::EmbeddedStoreError(e.to_string()))??;
+    Ok(())
+}
+
+#[async_trait]
+impl<R> MetadataRepository<R> for EmbeddedCollection<R>
+where
+    R: ResourceMetadata,
+{
+    async fn find_by_id(&self, id: i32) -> Result<Option<R>> {
+        EmbeddedCollection::find_by_id(self, id)
+    }
+
+    async fn upsert(&self, resource: &R) -> Result<Option<R>> {
+        EmbeddedCollection::upsert_by_id(self, resource.id(), resource).await
+    }
+
+    async fn all(&self) -> Result<Vec<R>> {
+        EmbeddedCollection::find(self, |_| true)
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool> {
+        let removed = EmbeddedCollection::remove(self, |resource| resource.id() == id).await?;
+        Ok(removed > 0)
+    }
+}
+
+#[async_trait]
+impl AnnouncementRepository for EmbeddedCollection<Announcement> {
+    async fn find(
+        &self,
+        title: &str,
+        id: i32,
+        source: &AnnouncementSource,
+    ) -> Result<Option<Announcement>> {
+        let mapped = map_title(title);
+        let found = EmbeddedCollection::find(self, |post| {
+            let same_title =
+                post.mapped_title == mapped && post.data.iter().all(|data| &data.source != source);
+            let same_resource = post
+                .data
+                .iter()
+                .any(|data| &data.source == source && data.id == id);
+            same_title || same_resource
+        })?;
+        Ok(found.into_iter().last())
+    }
+
+    async fn find_by_source(
+        &self,
+        source: &AnnouncementSource,
+        id: i32,
+    ) -> Result<Option<Announcement>> {
+        let found = EmbeddedCollection::find(self, |post| {
+            post.data
+                .iter()
+                .any(|data| &data.source == source && data.id == id)
+        })?;
+        Ok(found.into_iter().next())
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<Announcement>> {
+        EmbeddedCollection::find_by_id(self, id)
+    }
+
+    async fn search(&self, query: &str, skip: usize, limit: usize) -> Result<Vec<Announcement>> {
+        let terms: Vec<_> = query.split_whitespace().map(str::to_lowercase).collect();
+        let contains = |s: &str, term: &str| s.to_lowercase().contains(term);
+
+        let mut found = EmbeddedCollection::find(self, |post| {
+            terms.iter().all(|term| {
+                contains(&post.mapped_title, term)
+                    || post
+                        .data
+                        .iter()
+                        .any(|data| data.tags.iter().any(|tag| contains(tag, term)))
+                    || post.events.iter().any(|e| contains(&e.title, term))
+            })
+        })?;
+        found.sort_by_key(|post| std::cmp::Reverse(post.id));
+        Ok(found.into_iter().skip(skip).take(limit).collect())
+    }
+
+    async fn all(&self) -> Result<Vec<Announcement>> {
+        EmbeddedCollection::find(self, |_| true)
+    }
+
+    async fn upsert(&self, post: &Announcement) -> Result<()> {
+        let mut document = to_versioned_document(&self.name, post)?;
+        Post::from(post).store_in(&mut document)?;
+        self.replace(post.id.into(), document).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PostRepository for EmbeddedCollection<StoredPost> {
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<Post>> {
+        let found = EmbeddedCollection::find_by_id(self, id)?;
+        Ok(found.map(|stored| stored.post))
+    }
+
+    async fn find_by_source(&self, source: &PostSource) -> Result<Option<Post>> {
+        let found = EmbeddedCollection::find(self, |stored| stored.post.sources.contains(source))?;
+        Ok(found.into_iter().next().map(|stored| stored.post))
+    }
+}
+
+#[async_trait]
+impl EventRepository for EmbeddedCollection<Announcement> {
+    async fn events_end_after(&self, time: DateTime<Utc>) -> Result<Vec<Event>> {
+        let found = EmbeddedCollection::find(self, |post| {
+            post.events.iter().any(|e| e.end > time || !e.has_end())
+        })?;
+        Ok(found.into_iter().flat_map(announcement_events).collect())
+    }
+}
+
+#[async_trait]
+impl MessageRepository for EmbeddedCollection<SendResult> {
+    async fn insert(&self, result: &SendResult) -> Result<()> {
+        EmbeddedCollection::insert(self, result).await
+    }
+
+    async fn find_by_resource(&self, id: &ResourceId) -> Result<Vec<SendResult>> {
+        EmbeddedCollection::find(self, |result| &result.resource_id == id)
+    }
+
+    async fn all(&self) -> Result<Vec<SendResult>> {
+        EmbeddedCollection::find(self, |_| true)
+    }
+
+    async fn delete_by_resource(&self, id: &ResourceId) -> Result<usize> {
+        EmbeddedCollection::remove(self, |result| &result.resource_id == id).await
+    }
+}
+
+#[async_trait]
+impl PageArchiveRepository for EmbeddedCollection<ArchivedPage> {
+    async fn insert(&self, page: &ArchivedPage) -> Result<()> {
+        EmbeddedCollection::insert(self, page).await
+    }
+
+    async fn all(&self) -> Result<Vec<ArchivedPage>> {
+        let mut pages = EmbeddedCollection::find(self, |_| true)?;
+        pages.sort_by_key(|page| page.fetch_time);
+        Ok(pages)
+    }
+
+    async fn find_latest(
+        &self,
+        source: &AnnouncementSource,
+        post_id: i32,
+    ) -> Result<Option<ArchivedPage>> {
+        let pages = EmbeddedCollection::find(self, |page| {
+            &page.source == source && page.post_id == post_id
+        })?;
+        Ok(pages.into_iter().max_by_key(|page| page.fetch_time))
+    }
+
+    async fn prune(&self, time: DateTime<Utc>) -> Result<usize> {
+        let mut latest: HashMap<(AnnouncementSource, i32), ArchivedPage> = HashMap::new();
+        for page in EmbeddedCollection::find(self, |_| true)? {
+            let key = (page.source.clone(), page.post_id);
+            match latest.get(&key) {
+                Some(found) if found.fetch_time >= page.fetch_time => {}
+                _ => {
+                    latest.insert(key, page);
+                }
+            }
+        }
+
+        EmbeddedCollection::remove(self, |page| {
+            page.fetch_time < time
+                && latest
+                    .get(&(page.source.clone(), page.post_id))
+                    .is_none_or(|found| found.id != page.id)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SubscriptionRepository for EmbeddedCollection<Subscription> {
+    async fn subscribe(&self, chat_id: ChatId, keyword: &str) -> Result<bool> {
+        let found =
+            EmbeddedCollection::find(self, |s| s.chat_id == chat_id && s.keyword == keyword)?;
+        if !found.is_empty() {
+            return Ok(false);
+        }
+        EmbeddedCollection::insert(self, &Subscription::new(chat_id, keyword.to_string())).await?;
+        Ok(true)
+    }
+
+    async fn unsubscribe(&self, chat_id: ChatId, keyword: &str) -> Result<bool> {
+        let removed =
+            EmbeddedCollection::remove(self, |s| s.chat_id == chat_id && s.keyword == keyword)
+                .await?;
+        Ok(removed > 0)
+    }
+
+    async fn find_by_chat(&self, chat_id: ChatId) -> Result<Vec<Subscription>> {
+        EmbeddedCollection::find(self, |s| s.chat_id == chat_id)
+    }
+
+    async fn all(&self) -> Result<Vec<Subscription>> {
+        EmbeddedCollection::find(self, |_| true)
+    }
+}
+
+#[async_trait]
+impl DigestRepository for EmbeddedCollection<DigestMessage> {
+    async fn insert(&self, digest: &DigestMessage) -> Result<()> {
+        EmbeddedCollection::insert(self, digest).await
+    }
+
+    async fn last(&self, chat_id: ChatId) -> Result<Option<DigestMessage>> {
+        let found = EmbeddedCollection::find(self, |digest| digest.chat_id == chat_id)?;
+        Ok(found.into_iter().max_by_key(|digest| digest.create_time))
+    }
+}
+
+#[async_trait]
+impl ImageRepository for EmbeddedCollection<HostedImage> {
+    async fn find_by_hash(&self, hash: &str) -> Result<Option<HostedImage>> {
+        EmbeddedCollection::find_by_id(self, hash)
+    }
+
+    async fn upsert(&self, image: &HostedImage) -> Result<()> {
+        EmbeddedCollection::upsert(self, image).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MaintenanceRepository for EmbeddedCollection<Maintenance> {
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<Maintenance>> {
+        EmbeddedCollection::find_by_id(self, id)
+    }
+
+    async fn upsert(&self, maintenance: &Maintenance) -> Result<()> {
+        EmbeddedCollection::upsert(self, maintenance).await?;
+        Ok(())
+    }
+
+    async fn unfinished(&self) -> Result<Vec<Maintenance>> {
+        let mut found = EmbeddedCollection::find(self, |maintenance| {
+            maintenance.status != MaintenanceStatus::Ended
+        })?;
+        found.sort_by_key(|maintenance| maintenance.start);
+        Ok(found)
+    }
+}
+
+#[async_trait]
+impl DocumentRepository for EmbeddedCollection<Document> {
+    async fn find_below(&self, version: u32) -> Result<Vec<Document>> {
+        EmbeddedCollection::find(self, |document| schema_version(document) < version)
+    }
+
+    async fn replace(&self, document: &Document) -> Result<()> {
+        let id = document
+            .get("_id")
+            .cloned()
+            .ok_or_else(|| Error::EmbeddedStoreError(format!("no _id in {}", self.name)))?;
+        EmbeddedCollection::replace(self, id, document.clone()).await?;
+        Ok(())
+    }
+}
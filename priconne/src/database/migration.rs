@@ -0,0 +1,437 @@
+//! Schema migrations
+//!
+//! Every stored document has a `schema_version`, written by the repositories
+//! as the latest version of its collection. Documents written before this
+//! field existed are at version 0.
+//!
+//! When a persisted type changes, add a [`Migration`] to [`migrations`] that
+//! transforms the BSON of older documents, instead of breaking their
+//! deserialization. Steps must be idempotent: they may see a document that is
+//! already partly in the new shape.
+
+use std::fmt::Display;
+
+use mongodb::bson::{self, Bson, Document};
+use serde::Serialize;
+
+use super::Storage;
+use crate::{
+    resource::{post::Post, Announcement},
+    Error, Result,
+};
+
+/// Field holding the version of a document.
+pub const VERSION_FIELD: &str = "schema_version";
+
+/// Collections having versioned documents.
+pub const COLLECTIONS: [&str; 10] = [
+    "announcement",
+    "messages",
+    "subscriptions",
+    "digests",
+    "archive",
+    "information",
+    "news",
+    "cartoon",
+    "images",
+    "maintenance",
+];
+
+/// A step upgrading documents of a collection to `version`.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub collection: &'static str,
+    pub version: u32,
+    pub description: &'static str,
+    pub up: fn(Document) -> Result<Document>,
+}
+
+/// All migrations, in order of version.
+pub fn migrations() -> Vec<Migration> {
+    let mut migrations: Vec<_> = COLLECTIONS
+        .into_iter()
+        .map(|collection| Migration {
+            collection,
+            version: 1,
+            description: "record schema version",
+            up: Ok,
+        })
+        .collect();
+
+    migrations.push(Migration {
+        collection: "messages",
+        version: 2,
+        description: "fill backend and remote id of Telegram messages",
+        up: fill_message_backend,
+    });
+    migrations.push(Migration {
+        collection: "announcement",
+        version: 2,
+        description: "add fields of posts, moved in v4",
+        up: Ok,
+    });
+    migrations.push(Migration {
+        collection: "announcement",
+        version: 3,
+        description: "fill Telegraph pages from the latest URL",
+        up: fill_telegraph_page,
+    });
+    migrations.push(Migration {
+        collection: "announcement",
+        version: 4,
+        description: "move fields of posts under `post`",
+        up: nest_post,
+    });
+
+    migrations
+}
+
+/// Messages sent before other backends were added are all from Telegram.
+fn fill_message_backend(mut document: Document) -> Result<Document> {
+    if !document.contains_key("backend") {
+        document.insert("backend", "telegram");
+    }
+    if !document.contains_key("remote_id") {
+        document.insert("remote_id", Bson::Null);
+    }
+    Ok(document)
+}
+
+/// Fields of a [`Post`] that announcements don't have, written at the top of
+/// their documents before v4.
+const FLAT_POST_FIELDS: [&str; 5] = ["title", "sources", "create_time", "update_time", "tags"];
+
+/// Store the [`Post`] of the announcement under
+/// [`POST_FIELD`](crate::resource::post::POST_FIELD).
+///
+/// Before v4, post fields were written over the announcement, replacing its
+/// Telegraph page with the URL, so the page is filled again.
+fn nest_post(mut document: Document) -> Result<Document> {
+    for key in FLAT_POST_FIELDS {
+        document.remove(key);
+    }
+    let mut document = fill_telegraph_page(document)?;
+    let announcement: Announcement = bson::from_document(document.clone())?;
+    Post::from(&announcement).store_in(&mut document)?;
+    Ok(document)
+}
+
+/// Announcements saved before pages were edited in place only have the URL of
+/// each version. The latest one becomes the page, with an empty hash, so it is
+/// rewritten on the next update.
+fn fill_telegraph_page(mut document: Document) -> Result<Document> {
+    if matches!(document.get("telegraph"), Some(Bson::Document(_))) {
+        return Ok(document);
+    }
+    let latest = document
+        .get_array("data")
+        .into_iter()
+        .flatten()
+        .filter_map(Bson::as_document)
+        .rev()
+        .find_map(|data| match data.get("telegraph_url") {
+            Some(Bson::String(url)) => Some((url.clone(), data)),
+            _ => None,
+        });
+    let telegraph = match latest {
+        Some((url, data)) => {
+            let path = url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            let update_time = ["update_time", "create_time"]
+                .into_iter()
+                .find_map(|key| data.get_datetime(key).ok().copied())
+                .unwrap_or_else(bson::DateTime::now);
+            Bson::Document(bson::doc! {
+                "path": path,
+                "url": &url,
+                "content_hash": "",
+                "update_time": update_time,
+                "parts": [],
+            })
+        }
+        None => Bson::Null,
+    };
+    document.insert("telegraph", telegraph);
+    Ok(document)
+}
+
+/// Latest version of documents in `collection`.
+pub fn latest_version(collection: &str) -> u32 {
+    migrations()
+        .iter()
+        .filter(|migration| migration.collection == collection)
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default()
+}
+
+/// Version of a stored document, 0 if it has none.
+pub fn schema_version(document: &Document) -> u32 {
+    match document.get(VERSION_FIELD) {
+        Some(Bson::Int32(version)) => *version as u32,
+        Some(Bson::Int64(version)) => *version as u32,
+        _ => 0,
+    }
+}
+
+/// Serialize `item` to be stored in `collection`, with the latest version
+/// unless it already has one.
+pub fn to_versioned_document<T: Serialize>(collection: &str, item: &T) -> Result<Document> {
+    let mut document = bson::to_document(item)?;
+    if !document.contains_key(VERSION_FIELD) {
+        document.insert(VERSION_FIELD, latest_version(collection) as i32);
+    }
+    Ok(document)
+}
+
+/// Apply every step above the version of `document`.
+pub fn upgrade(steps: &[Migration], mut document: Document) -> Result<Document> {
+    let version = schema_version(&document);
+    let id = document.get("_id").cloned();
+    for step in steps.iter().filter(|step| step.version > version) {
+        document = (step.up)(document).map_err(|e| {
+            Error::MigrationError(format!(
+                "{} v{} failed on {id:?}: {e}",
+                step.collection, step.version,
+            ))
+        })?;
+        document.insert(VERSION_FIELD, step.version as i32);
+    }
+    Ok(document)
+}
+
+/// Documents of a collection below its latest version.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub collection: &'static str,
+    pub version: u32,
+    pub pending: usize,
+}
+
+impl Display for MigrationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: v{}, {} document(s) pending",
+            self.collection, self.version, self.pending
+        )
+    }
+}
+
+impl Storage {
+    /// Count documents to migrate in each collection.
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        let mut statuses = Vec::new();
+        for collection in COLLECTIONS {
+            let version = latest_version(collection);
+            let pending = self.documents(collection)?.find_below(version).await?;
+            statuses.push(MigrationStatus {
+                collection,
+                version,
+                pending: pending.len(),
+            });
+        }
+        Ok(statuses)
+    }
+
+    /// Upgrade all documents to the latest version, returns the status before.
+    ///
+    /// With `dry_run`, documents are upgraded in memory only, so that broken
+    /// steps are found without writing anything.
+    pub async fn migrate(&self, dry_run: bool) -> Result<Vec<MigrationStatus>> {
+        let migrations = migrations();
+        let mut statuses = Vec::new();
+        for collection in COLLECTIONS {
+            let mut steps: Vec<_> = migrations
+                .iter()
+                .filter(|migration| migration.collection == collection)
+                .copied()
+                .collect();
+            steps.sort_by_key(|migration| migration.version);
+            let version = latest_version(collection);
+
+            let documents = self.documents(collection)?;
+            let pending = documents.find_below(version).await?;
+            for document in &pending {
+                let upgraded = upgrade(&steps, document.clone())?;
+                if !dry_run {
+                    documents.replace(&upgraded).await?;
+                }
+            }
+
+            statuses.push(MigrationStatus {
+                collection,
+                version,
+                pending: pending.len(),
+            });
+        }
+        Ok(statuses)
+    }
+
+    /// Fail if any document is below the latest version.
+    pub async fn ensure_migrated(&self) -> Result<()> {
+        let pending: usize = self
+            .migration_status()
+            .await?
+            .iter()
+            .map(|status| status.pending)
+            .sum();
+        match pending {
+            0 => Ok(()),
+            pending => Err(Error::PendingMigrationError(pending)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+    use crate::{
+        database::EmbeddedStore,
+        resource::{
+            announcement::sources::AnnouncementSource,
+            post::{PostSource, StoredPost},
+        },
+    };
+
+    #[test]
+    fn test_upgrade() {
+        let steps: Vec<_> = migrations()
+            .into_iter()
+            .filter(|migration| migration.collection == "messages")
+            .collect();
+
+        let old = doc! { "_id": 1, "resource_id": { "Cartoon": 1 } };
+        let upgraded = upgrade(&steps, old).unwrap();
+        assert_eq!(schema_version(&upgraded), latest_version("messages"));
+        assert_eq!(upgraded.get_str("backend").unwrap(), "telegram");
+        assert_eq!(upgraded.get("remote_id"), Some(&Bson::Null));
+
+        // Already upgraded
+        assert_eq!(upgrade(&steps, upgraded.clone()).unwrap(), upgraded);
+
+        // Partly in the new shape
+        let discord = doc! { "_id": 2, "backend": "discord", "remote_id": "1" };
+        let upgraded = upgrade(&steps, discord).unwrap();
+        assert_eq!(upgraded.get_str("backend").unwrap(), "discord");
+        assert_eq!(upgraded.get_str("remote_id").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_nest_post() {
+        let steps: Vec<_> = migrations()
+            .into_iter()
+            .filter(|migration| migration.collection == "announcement")
+            .collect();
+
+        let end = chrono::Utc::now();
+        let announcement = Announcement::new(
+            crate::database::tests::insight(AnnouncementSource::Website, 1, "活動", end),
+            None,
+        );
+        let mut old = bson::to_document(&announcement).unwrap();
+        old.remove("message_id");
+
+        let upgraded = upgrade(&steps, old).unwrap();
+        let stored: StoredPost = bson::from_document(upgraded.clone()).unwrap();
+        assert_eq!(stored.post.id, announcement.id);
+        assert_eq!(stored.post.sources, vec![PostSource::News { id: 1 }]);
+        assert_eq!(stored.post.message_id, None);
+        // Already upgraded
+        assert_eq!(upgrade(&steps, upgraded.clone()).unwrap(), upgraded);
+
+        // Post fields written over the announcement, with the page as the URL
+        let insight = crate::database::tests::insight(AnnouncementSource::Website, 2, "活動", end)
+            .with_telegraph_url("https://telegra.ph/活動-01-01".to_string());
+        let announcement = Announcement::new(insight, None);
+        let mut old = bson::to_document(&announcement).unwrap();
+        for (key, value) in bson::to_document(&Post::from(&announcement)).unwrap() {
+            old.insert(key, value);
+        }
+        old.insert("telegraph", "https://telegra.ph/活動-01-01");
+        old.insert(VERSION_FIELD, 3);
+
+        let upgraded = upgrade(&steps, old).unwrap();
+        for key in FLAT_POST_FIELDS {
+            assert!(!upgraded.contains_key(key), "{key}");
+        }
+        let found: Announcement = bson::from_document(upgraded.clone()).unwrap();
+        assert_eq!(
+            found.telegraph.unwrap().url,
+            "https://telegra.ph/活動-01-01"
+        );
+        let stored: StoredPost = bson::from_document(upgraded).unwrap();
+        assert_eq!(stored.post.sources, vec![PostSource::News { id: 2 }]);
+    }
+
+    #[test]
+    fn test_fill_telegraph_page() {
+        let steps: Vec<_> = migrations()
+            .into_iter()
+            .filter(|migration| migration.collection == "announcement")
+            .collect();
+
+        let end = chrono::Utc::now();
+        let insight = crate::database::tests::insight(AnnouncementSource::Website, 1, "活動", end)
+            .with_telegraph_url("https://telegra.ph/活動-01-01".to_string());
+        let announcement = Announcement::new(insight, None);
+        let mut old = bson::to_document(&announcement).unwrap();
+        old.remove("telegraph");
+        old.insert(VERSION_FIELD, 2);
+
+        let upgraded = upgrade(&steps, old).unwrap();
+        let announcement: Announcement = bson::from_document(upgraded.clone()).unwrap();
+        let telegraph = announcement.telegraph.unwrap();
+        assert_eq!(telegraph.path, "活動-01-01");
+        assert_eq!(telegraph.url, "https://telegra.ph/活動-01-01");
+        assert!(!telegraph.is_unchanged("活動", ""));
+
+        // Already upgraded
+        assert_eq!(upgrade(&steps, upgraded.clone()).unwrap(), upgraded);
+
+        // Without any page
+        let mut old = bson::to_document(&Announcement::new(
+            crate::database::tests::insight(AnnouncementSource::Website, 2, "活動", end),
+            None,
+        ))
+        .unwrap();
+        old.remove("telegraph");
+        let upgraded = upgrade(&steps, old).unwrap();
+        assert_eq!(upgraded.get("telegraph"), Some(&Bson::Null));
+    }
+
+    #[tokio::test]
+    async fn test_migrate() -> Result<()> {
+        let storage = Storage::Embedded(EmbeddedStore::in_memory());
+        let messages = storage.documents("messages")?;
+        messages
+            .replace(&doc! { "_id": 1, "resource_id": { "Cartoon": 1 } })
+            .await?;
+        assert_eq!(schema_version(&messages.find_below(u32::MAX).await?[0]), 0);
+        storage
+            .metadata::<crate::resource::news::News>("news")?
+            .upsert(&crate::database::tests::news(1, "new"))
+            .await?;
+
+        assert!(matches!(
+            storage.ensure_migrated().await,
+            Err(Error::PendingMigrationError(1))
+        ));
+
+        let status = storage.migrate(true).await?;
+        assert_eq!(status.iter().map(|s| s.pending).sum::<usize>(), 1);
+        assert!(storage.ensure_migrated().await.is_err());
+
+        storage.migrate(false).await?;
+        storage.ensure_migrated().await?;
+        let found = messages.find_below(u32::MAX).await?;
+        assert_eq!(schema_version(&found[0]), latest_version("messages"));
+        assert_eq!(found[0].get_str("backend").unwrap(), "telegram");
+        Ok(())
+    }
+}
@@ -0,0 +1,615 @@
+//! MongoDB backend
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Document},
+    options::{
+        FindOneAndReplaceOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions,
+        UpdateOptions,
+    },
+    Collection, Database, IndexModel,
+};
+use teloxide::types::ChatId;
+
+use super::{
+    announcement_events,
+    migration::{to_versioned_document, VERSION_FIELD},
+    AnnouncementRepository, DigestRepository, DocumentRepository, EventRepository, ImageRepository,
+    MaintenanceRepository, MessageRepository, MetadataRepository, PageArchiveRepository,
+    PostRepository, SubscriptionRepository,
+};
+use crate::{
+    archive::ArchivedPage,
+    chat::{SendResult, Subscription},
+    digest::DigestMessage,
+    image::HostedImage,
+    insight::PeriodPattern,
+    maintenance::{Maintenance, MaintenanceStatus},
+    resource::{
+        announcement::sources::AnnouncementSource,
+        event::Event,
+        post::{Post, PostSource, StoredPost},
+        Announcement, ResourceId, ResourceMetadata,
+    },
+    utils::map_title,
+    Result,
+};
+
+fn index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
+
+fn unique_index(keys: Document) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(true).build())
+        .build()
+}
+
+/// Indexes of each collection, for the queries in this module.
+///
+/// Metadata collections are only queried by `_id`, which is always indexed.
+fn indexes() -> Vec<(&'static str, Vec<IndexModel>)> {
+    vec![
+        (
+            "announcement",
+            vec![
+                index(doc! { "mapped_title": 1 }),
+                index(doc! { "data.source": 1, "data.id": 1 }),
+                index(doc! { "events.end": 1 }),
+            ],
+        ),
+        (
+            "messages",
+            vec![index(doc! { "resource_id": 1, "update_time": 1 })],
+        ),
+        (
+            "subscriptions",
+            vec![unique_index(doc! { "chat_id": 1, "keyword": 1 })],
+        ),
+        (
+            "digests",
+            vec![index(doc! { "chat_id": 1, "create_time": -1 })],
+        ),
+        ("maintenance", vec![index(doc! { "status": 1, "start": 1 })]),
+        (
+            "archive",
+            vec![
+                index(doc! { "source": 1, "post_id": 1, "fetch_time": -1 }),
+                index(doc! { "fetch_time": 1 }),
+            ],
+        ),
+    ]
+}
+
+/// Create [`indexes`] in `database`.
+pub async fn ensure_indexes(database: &Database) -> Result<()> {
+    for (name, models) in indexes() {
+        tracing::debug!("creating {} index(es) on {name}", models.len());
+        database
+            .collection::<Document>(name)
+            .create_indexes(models, None)
+            .await?;
+    }
+    Ok(())
+}
+
+pub struct AnnouncementCollection(pub Collection<Announcement>);
+
+impl AnnouncementCollection {
+    pub fn posts(&self) -> Collection<Announcement> {
+        self.0.clone()
+    }
+}
+
+#[async_trait]
+impl AnnouncementRepository for AnnouncementCollection {
+    async fn find(
+        &self,
+        title: &str,
+        id: i32,
+        source: &AnnouncementSource,
+    ) -> Result<Option<Announcement>> {
+        let mapped = map_title(title);
+        // it's possible that different announcements have a same title,
+        // espicially for a common event, therefore, we try our best to avoid duplicated re-posting
+        // currently, this case are considered as repost:
+        //   same mapped title, same source not exist
+        // however, on some cases they may actually be two different posts...
+        // we can't know unless comparing full-text
+        let source = bson::to_bson(source)?;
+        let filter = doc! {
+            "$or": [
+                {
+                    "mapped_title": mapped,
+                    "data.source": {
+                        "$ne": &source
+                    },
+                },
+                {
+                    "data": {
+                        "$elemMatch": {
+                            "source": &source,
+                            "id": id,
+                        }
+                    }
+                }
+            ]
+        };
+        tracing::trace!("{filter}");
+
+        let found = self
+            .posts()
+            .find_one(
+                filter,
+                FindOneOptions::builder().sort(doc! {"_id": -1}).build(),
+            )
+            .await?;
+        Ok(found)
+    }
+
+    async fn find_by_source(
+        &self,
+        source: &AnnouncementSource,
+        id: i32,
+    ) -> Result<Option<Announcement>> {
+        let filter = doc! {
+            "data": {
+                "$elemMatch": {
+                    "source": bson::to_bson(source)?,
+                    "id": id,
+                }
+            }
+        };
+
+        Ok(self.posts().find_one(filter, None).await?)
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<Announcement>> {
+        Ok(self.posts().find_one(doc! { "_id": id }, None).await?)
+    }
+
+    async fn search(&self, query: &str, skip: usize, limit: usize) -> Result<Vec<Announcement>> {
+        let terms: Vec<_> = query
+            .split_whitespace()
+            .map(|term| {
+                let regex = bson::Regex {
+                    pattern: regex::escape(term),
+                    options: "i".to_string(),
+                };
+                doc! {
+                    "$or": [
+                        { "mapped_title": &regex },
+                        { "data.tags": &regex },
+                        { "events.title": &regex },
+                    ]
+                }
+            })
+            .collect();
+        let filter = match terms.is_empty() {
+            true => doc! {},
+            false => doc! { "$and": terms },
+        };
+
+        Ok(self
+            .posts()
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! {"_id": -1})
+                    .skip(skip as u64)
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn all(&self) -> Result<Vec<Announcement>> {
+        Ok(self
+            .posts()
+            .find(None, FindOptions::builder().sort(doc! {"_id": 1}).build())
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn upsert(&self, post: &Announcement) -> Result<()> {
+        let mut document = to_versioned_document(self.posts().name(), post)?;
+        Post::from(post).store_in(&mut document)?;
+        self.posts()
+            .clone_with_type::<Document>()
+            .replace_one(
+                doc! {"_id": post.id},
+                document,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventRepository for AnnouncementCollection {
+    async fn events_end_after(&self, time: DateTime<Utc>) -> Result<Vec<Event>> {
+        let open = bson::to_bson(&PeriodPattern::OPEN)?;
+        let announcements = self
+            .posts()
+            .find(
+                doc! {
+                    "$or": [
+                        { "events.end": { "$gt": time } },
+                        // Open ends are stored as the start
+                        {
+                            "$expr": {
+                                "$anyElementTrue": {
+                                    "$map": {
+                                        "input": "$events",
+                                        "as": "event",
+                                        "in": {
+                                            "$and": [
+                                                {
+                                                    "$in": [
+                                                        "$$event.pattern",
+                                                        &open,
+                                                    ]
+                                                },
+                                                { "$eq": ["$$event.end", "$$event.start"] },
+                                            ]
+                                        },
+                                    }
+                                }
+                            }
+                        },
+                    ]
+                },
+                None,
+            )
+            .await?;
+
+        Ok(announcements
+            .map_ok(announcement_events)
+            .try_concat()
+            .await?)
+    }
+}
+
+/// Posts in the announcement collection.
+pub struct PostCollection(pub Collection<StoredPost>);
+
+#[async_trait]
+impl PostRepository for PostCollection {
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<Post>> {
+        let found = self.0.find_one(doc! { "_id": id }, None).await?;
+        Ok(found.map(|stored| stored.post))
+    }
+
+    async fn find_by_source(&self, source: &PostSource) -> Result<Option<Post>> {
+        let found = self
+            .0
+            .find_one(doc! { "post.sources": bson::to_bson(source)? }, None)
+            .await?;
+        Ok(found.map(|stored| stored.post))
+    }
+}
+
+pub struct ResourceMetadataCollection<R: ResourceMetadata>(Collection<R>);
+
+impl<R> ResourceMetadataCollection<R>
+where
+    R: ResourceMetadata,
+{
+    pub fn new(collection: Collection<R>) -> Self {
+        Self(collection)
+    }
+
+    fn inner(&self) -> &Collection<R> {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<R> MetadataRepository<R> for ResourceMetadataCollection<R>
+where
+    R: ResourceMetadata,
+{
+    async fn find_by_id(&self, id: i32) -> Result<Option<R>> {
+        Ok(self.inner().find_one(doc! { "_id": id }, None).await?)
+    }
+
+    async fn upsert(&self, resource: &R) -> Result<Option<R>> {
+        let old = self
+            .inner()
+            .clone_with_type::<Document>()
+            .find_one_and_replace(
+                doc! { "_id": resource.id() },
+                to_versioned_document(self.inner().name(), resource)?,
+                FindOneAndReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(old.map(bson::from_document).transpose()?)
+    }
+
+    async fn all(&self) -> Result<Vec<R>> {
+        Ok(self.inner().find(None, None).await?.try_collect().await?)
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool> {
+        let result = self.inner().delete_one(doc! { "_id": id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+pub struct MessageCollection(pub Collection<SendResult>);
+
+#[async_trait]
+impl MessageRepository for MessageCollection {
+    async fn insert(&self, result: &SendResult) -> Result<()> {
+        self.0
+            .clone_with_type::<Document>()
+            .insert_one(to_versioned_document(self.0.name(), result)?, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_by_resource(&self, id: &ResourceId) -> Result<Vec<SendResult>> {
+        Ok(self
+            .0
+            .find(
+                doc! { "resource_id": bson::to_bson(id)? },
+                FindOptions::builder().sort(doc! {"update_time": 1}).build(),
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn all(&self) -> Result<Vec<SendResult>> {
+        Ok(self.0.find(None, None).await?.try_collect().await?)
+    }
+
+    async fn delete_by_resource(&self, id: &ResourceId) -> Result<usize> {
+        let result = self
+            .0
+            .delete_many(doc! { "resource_id": bson::to_bson(id)? }, None)
+            .await?;
+        Ok(result.deleted_count as usize)
+    }
+}
+
+/// Archive of raw pages, see [`ArchivedPage`].
+pub struct PageArchiveCollection(pub Collection<ArchivedPage>);
+
+#[async_trait]
+impl PageArchiveRepository for PageArchiveCollection {
+    async fn insert(&self, page: &ArchivedPage) -> Result<()> {
+        self.0
+            .clone_with_type::<Document>()
+            .insert_one(to_versioned_document(self.0.name(), page)?, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn all(&self) -> Result<Vec<ArchivedPage>> {
+        Ok(self
+            .0
+            .find(
+                None,
+                FindOptions::builder().sort(doc! {"fetch_time": 1}).build(),
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_latest(
+        &self,
+        source: &AnnouncementSource,
+        post_id: i32,
+    ) -> Result<Option<ArchivedPage>> {
+        Ok(self
+            .0
+            .find_one(
+                doc! { "source": bson::to_bson(source)?, "post_id": post_id },
+                FindOneOptions::builder()
+                    .sort(doc! {"fetch_time": -1})
+                    .build(),
+            )
+            .await?)
+    }
+
+    async fn prune(&self, time: DateTime<Utc>) -> Result<usize> {
+        let latest: Vec<Document> = self
+            .0
+            .aggregate(
+                [
+                    doc! { "$sort": { "fetch_time": -1 } },
+                    doc! {
+                        "$group": {
+                            "_id": { "source": "$source", "post_id": "$post_id" },
+                            "latest": { "$first": "$_id" },
+                        }
+                    },
+                ],
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?;
+        let latest: Vec<ObjectId> = latest
+            .iter()
+            .filter_map(|group| group.get_object_id("latest").ok())
+            .collect();
+
+        let result = self
+            .0
+            .delete_many(
+                doc! {
+                    "fetch_time": { "$lt": time },
+                    "_id": { "$nin": latest },
+                },
+                None,
+            )
+            .await?;
+        Ok(result.deleted_count as usize)
+    }
+}
+
+pub struct SubscriptionCollection(pub Collection<Subscription>);
+
+#[async_trait]
+impl SubscriptionRepository for SubscriptionCollection {
+    async fn subscribe(&self, chat_id: ChatId, keyword: &str) -> Result<bool> {
+        let subscription = Subscription::new(chat_id, keyword.to_string());
+        let result = self
+            .0
+            .update_one(
+                doc! { "chat_id": chat_id.0, "keyword": keyword },
+                doc! { "$setOnInsert": to_versioned_document(self.0.name(), &subscription)? },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    async fn unsubscribe(&self, chat_id: ChatId, keyword: &str) -> Result<bool> {
+        let result = self
+            .0
+            .delete_one(doc! { "chat_id": chat_id.0, "keyword": keyword }, None)
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn find_by_chat(&self, chat_id: ChatId) -> Result<Vec<Subscription>> {
+        Ok(self
+            .0
+            .find(
+                doc! { "chat_id": chat_id.0 },
+                FindOptions::builder().sort(doc! {"create_time": 1}).build(),
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn all(&self) -> Result<Vec<Subscription>> {
+        Ok(self.0.find(None, None).await?.try_collect().await?)
+    }
+}
+
+pub struct ImageCollection(pub Collection<HostedImage>);
+
+#[async_trait]
+impl ImageRepository for ImageCollection {
+    async fn find_by_hash(&self, hash: &str) -> Result<Option<HostedImage>> {
+        Ok(self.0.find_one(doc! { "_id": hash }, None).await?)
+    }
+
+    async fn upsert(&self, image: &HostedImage) -> Result<()> {
+        self.0
+            .clone_with_type::<Document>()
+            .replace_one(
+                doc! { "_id": &image.hash },
+                to_versioned_document(self.0.name(), image)?,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct DigestCollection(pub Collection<DigestMessage>);
+
+#[async_trait]
+impl DigestRepository for DigestCollection {
+    async fn insert(&self, digest: &DigestMessage) -> Result<()> {
+        self.0
+            .clone_with_type::<Document>()
+            .insert_one(to_versioned_document(self.0.name(), digest)?, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn last(&self, chat_id: ChatId) -> Result<Option<DigestMessage>> {
+        Ok(self
+            .0
+            .find_one(
+                doc! { "chat_id": chat_id.0 },
+                FindOneOptions::builder()
+                    .sort(doc! {"create_time": -1})
+                    .build(),
+            )
+            .await?)
+    }
+}
+
+pub struct MaintenanceCollection(pub Collection<Maintenance>);
+
+#[async_trait]
+impl MaintenanceRepository for MaintenanceCollection {
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<Maintenance>> {
+        Ok(self.0.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    async fn upsert(&self, maintenance: &Maintenance) -> Result<()> {
+        self.0
+            .clone_with_type::<Document>()
+            .replace_one(
+                doc! { "_id": maintenance.id },
+                to_versioned_document(self.0.name(), maintenance)?,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn unfinished(&self) -> Result<Vec<Maintenance>> {
+        let ended = bson::to_bson(&MaintenanceStatus::Ended)?;
+        Ok(self
+            .0
+            .find(
+                doc! { "status": { "$ne": ended } },
+                FindOptions::builder().sort(doc! { "start": 1 }).build(),
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+}
+
+/// Raw documents, see [`DocumentRepository`].
+pub struct DocumentCollection(pub Collection<Document>);
+
+#[async_trait]
+impl DocumentRepository for DocumentCollection {
+    async fn find_below(&self, version: u32) -> Result<Vec<Document>> {
+        Ok(self
+            .0
+            .find(
+                doc! {
+                    "$or": [
+                        { VERSION_FIELD: { "$lt": version as i64 } },
+                        { VERSION_FIELD: { "$exists": false } },
+                    ]
+                },
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn replace(&self, document: &Document) -> Result<()> {
+        self.0
+            .replace_one(
+                doc! { "_id": document.get("_id").cloned() },
+                document,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+}
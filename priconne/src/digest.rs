@@ -0,0 +1,406 @@
+//! Daily digest
+//!
+//! A scheduled job, configured as `digest` in [`FetchConfig::schedule`](crate::config::FetchConfig::schedule),
+//! posts one message summarizing today's schedule in its time zone: events starting
+//! today, events ending today or tomorrow, ongoing campaigns and upcoming
+//! maintenance. Each item links to the message of its announcement.
+//!
+//! When [`DigestConfig::pin`] is set, the digest is pinned, and the previous one
+//! is unpinned.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use mongodb::bson::oid::ObjectId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    payloads::{PinChatMessageSetters, SendMessageSetters, UnpinChatMessageSetters},
+    requests::Requester,
+    types::{ChatId, MessageId, ParseMode, Recipient},
+};
+use url::Url;
+
+use crate::{
+    chat::{MessageText, PublisherKind, Sendable, TEXT_LIMIT},
+    config::RemoteRecipient,
+    resource::{event::Event, ResourceId},
+    utils::{midnight, Tz, DEFAULT_TIME_ZONE},
+    Error, PriconneService, Result,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DigestConfig {
+    /// Chat to post the digest
+    #[schemars(with = "RemoteRecipient")]
+    pub recipient: Recipient,
+    /// Pin the digest, and unpin the previous one
+    #[serde(default)]
+    pub pin: bool,
+    /// Keywords in titles of campaigns, like `2倍`
+    #[serde(default = "default_campaign_keywords")]
+    pub campaign_keywords: Vec<String>,
+    /// Keywords in titles of maintenance
+    #[serde(default = "default_maintenance_keywords")]
+    pub maintenance_keywords: Vec<String>,
+    /// Time zone of the schedule, default to `Asia/Taipei`
+    #[serde(default = "default_time_zone")]
+    #[schemars(with = "String")]
+    pub time_zone: Tz,
+}
+
+fn default_time_zone() -> Tz {
+    DEFAULT_TIME_ZONE
+}
+
+fn default_campaign_keywords() -> Vec<String> {
+    vec!["倍".to_string()]
+}
+
+fn default_maintenance_keywords() -> Vec<String> {
+    vec!["維護".to_string()]
+}
+
+/// A posted digest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestMessage {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub date: NaiveDate,
+    pub chat_id: ChatId,
+    pub message_id: MessageId,
+    pub pinned: bool,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub create_time: DateTime<Utc>,
+}
+
+/// An event in the digest.
+#[derive(Debug, Clone)]
+pub struct DigestItem {
+    pub event: Event,
+    /// Link to the announcement message
+    pub url: Option<Url>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Digest {
+    pub date: NaiveDate,
+    /// Zone that dates and times are in
+    pub time_zone: Tz,
+    /// Events starting today
+    pub starting: Vec<DigestItem>,
+    /// Events ending today or tomorrow
+    pub ending: Vec<DigestItem>,
+    /// Ongoing campaigns not in the lists above
+    pub campaigns: Vec<DigestItem>,
+    /// Ongoing or upcoming maintenance
+    pub maintenance: Vec<DigestItem>,
+}
+
+impl Digest {
+    /// Sort `events` into sections, as of `now`.
+    pub fn new(events: Vec<Event>, config: &DigestConfig, now: DateTime<Utc>) -> Self {
+        let date_of = |time: DateTime<Utc>| time.with_timezone(&config.time_zone).date_naive();
+        let today = date_of(now);
+        let tomorrow = today + Duration::days(1);
+        let matches = |event: &Event, keywords: &[String]| {
+            keywords.iter().any(|keyword| {
+                event.title.contains(keyword) || event.announcement_title.contains(keyword)
+            })
+        };
+
+        let mut digest = Digest {
+            date: today,
+            time_zone: config.time_zone,
+            ..Default::default()
+        };
+        for event in events {
+            // Open ends are stored as the start, the event goes on
+            if event.has_end && event.end <= now {
+                continue;
+            }
+            let item = DigestItem { event, url: None };
+            let event = &item.event;
+            let open_before_today = !event.has_end && date_of(event.start) < today;
+
+            if matches(event, &config.maintenance_keywords) && !open_before_today {
+                digest.maintenance.push(item);
+            } else if date_of(event.start) == today {
+                digest.starting.push(item);
+            } else if event.has_end && [today, tomorrow].contains(&date_of(event.end)) {
+                digest.ending.push(item);
+            } else if event.start <= now && matches(event, &config.campaign_keywords) {
+                digest.campaigns.push(item);
+            }
+        }
+
+        for items in [
+            &mut digest.starting,
+            &mut digest.ending,
+            &mut digest.campaigns,
+            &mut digest.maintenance,
+        ] {
+            items.sort_by_key(|item| (item.event.start, item.event.end));
+        }
+        digest
+    }
+
+    fn items_mut(&mut self) -> impl Iterator<Item = &mut DigestItem> {
+        self.starting
+            .iter_mut()
+            .chain(self.ending.iter_mut())
+            .chain(self.campaigns.iter_mut())
+            .chain(self.maintenance.iter_mut())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starting.is_empty()
+            && self.ending.is_empty()
+            && self.campaigns.is_empty()
+            && self.maintenance.is_empty()
+    }
+
+    /// The digest message, in Telegram HTML.
+    pub fn text(&self) -> String {
+        self.message().render()
+    }
+
+    fn message(&self) -> MessageText {
+        let mut message = MessageText::default();
+        message
+            .text("📅 ")
+            .bold(format!("{} 每日行程", self.date.format("%Y/%m/%d")))
+            .text(format!(
+                " (UTC{})",
+                midnight(self.date, self.time_zone).format("%:z")
+            ))
+            .text("\n");
+        for (title, items) in [
+            ("今日開始", &self.starting),
+            ("即將結束", &self.ending),
+            ("進行中的活動", &self.campaigns),
+            ("維護預告", &self.maintenance),
+        ] {
+            if items.is_empty() {
+                continue;
+            }
+            message.text("\n").bold(title).text("\n");
+            for item in items {
+                item.write(&mut message, self.time_zone);
+                message.text("\n");
+            }
+        }
+        message
+    }
+}
+
+impl DigestItem {
+    fn write(&self, message: &mut MessageText, zone: Tz) {
+        let event = &self.event;
+        message.text("- ");
+        match &self.url {
+            Some(url) => message.link(&event.announcement_title, url.as_str()),
+            None => message.text(&event.announcement_title),
+        };
+        let format = |time: DateTime<Utc>| time.with_timezone(&zone).format("%m/%d %H:%M");
+        if event.has_end {
+            message.text(format!(
+                "\n   {}: {} - {}",
+                event.title,
+                format(event.start),
+                format(event.end)
+            ));
+        } else {
+            message.text(format!("\n   {}: {} 起", event.title, format(event.start)));
+        }
+    }
+}
+
+impl PriconneService {
+    /// Post the daily digest to the configured chat.
+    pub async fn send_digest(&self) -> Result<()> {
+        let config = self
+            .chat_manager
+            .config
+            .digest
+            .as_ref()
+            .ok_or(Error::MissingConfigError("telegram.digest"))?;
+
+        let mut digest = Digest::new(self.incomming_events().await?, config, Utc::now());
+        if digest.is_empty() {
+            tracing::info!("nothing to digest on {}", digest.date);
+            return Ok(());
+        }
+        self.link_announcements(&mut digest).await?;
+
+        // A long digest is sent in parts, the first one is pinned
+        let bot = &self.chat_manager.bot;
+        let mut first = None;
+        for text in digest.message().split(TEXT_LIMIT) {
+            let sent = bot
+                .send_message(config.recipient.clone(), text)
+                .parse_mode(ParseMode::Html)
+                .disable_web_page_preview(true)
+                .await?;
+            first.get_or_insert(sent);
+        }
+        let Some(sent) = first else {
+            return Ok(());
+        };
+
+        let digests = self.storage.digests()?;
+        if config.pin {
+            let last = digests.last(sent.chat.id).await?;
+            bot.pin_chat_message(sent.chat.id, sent.id)
+                .disable_notification(true)
+                .await?;
+            if let Some(last) = last.filter(|last| last.pinned) {
+                if let Err(e) = bot
+                    .unpin_chat_message(last.chat_id)
+                    .message_id(last.message_id)
+                    .await
+                {
+                    tracing::warn!("failed to unpin digest of {}: {e}", last.date);
+                }
+            }
+        }
+
+        digests
+            .insert(&DigestMessage {
+                id: ObjectId::new(),
+                date: digest.date,
+                chat_id: sent.chat.id,
+                message_id: sent.id,
+                pinned: config.pin,
+                create_time: Utc::now(),
+            })
+            .await
+    }
+
+    /// Link items to the message of their announcement, or the announcement
+    /// itself when it has no public message.
+    async fn link_announcements(&self, digest: &mut Digest) -> Result<()> {
+        let messages = &self.chat_manager.messages;
+        let announcements = self.storage.announcements()?;
+
+        let mut urls: HashMap<ObjectId, Option<Url>> = HashMap::new();
+        for item in digest.items_mut() {
+            let id = item.event.announcement_id;
+            if let Some(url) = urls.get(&id) {
+                item.url = url.clone();
+                continue;
+            }
+
+            let sent = messages
+                .find_by_resource(&ResourceId::Announcement(id))
+                .await?
+                .into_iter()
+                .filter(|result| result.backend == PublisherKind::Telegram)
+                .find_map(|result| result.url);
+            item.url = match sent {
+                Some(url) => Some(url),
+                None => announcements
+                    .find_by_id(id)
+                    .await?
+                    .and_then(|announcement| announcement.publication().url),
+            };
+            urls.insert(id, item.url.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::resource::event::EventKind;
+
+    fn config() -> DigestConfig {
+        serde_yaml::from_str("recipient: '@channel'").unwrap()
+    }
+
+    fn event(announcement_title: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Event {
+        Event {
+            start,
+            end,
+            has_end: true,
+            title: "活動期間".to_string(),
+            announcement_title: announcement_title.to_string(),
+            announcement_id: ObjectId::new(),
+            kind: EventKind::Other,
+        }
+    }
+
+    #[test]
+    fn test_digest() {
+        let time = |d, h| {
+            DEFAULT_TIME_ZONE
+                .with_ymd_and_hms(2023, 1, d, h, 0, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let now = time(10, 8);
+        let events = vec![
+            event("【轉蛋】精選轉蛋", time(10, 12), time(20, 12)),
+            event("【活動】戰隊競賽", time(1, 5), time(11, 23)),
+            event("【活動】N2倍掉落", time(5, 5), time(15, 5)),
+            event("【活動】露娜之塔", time(5, 5), time(15, 5)),
+            event("【維護】停機維護", time(12, 10), time(12, 16)),
+            event("【活動】已結束", time(1, 5), time(9, 5)),
+        ];
+
+        let mut digest = Digest::new(events, &config(), now);
+        assert_eq!(digest.date, NaiveDate::from_ymd_opt(2023, 1, 10).unwrap());
+        let titles = |items: &[DigestItem]| {
+            items
+                .iter()
+                .map(|item| item.event.announcement_title.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(&digest.starting), vec!["【轉蛋】精選轉蛋"]);
+        assert_eq!(titles(&digest.ending), vec!["【活動】戰隊競賽"]);
+        assert_eq!(titles(&digest.campaigns), vec!["【活動】N2倍掉落"]);
+        assert_eq!(titles(&digest.maintenance), vec!["【維護】停機維護"]);
+
+        digest.starting[0].url = Some(Url::parse("https://t.me/channel/1").unwrap());
+        let text = digest.text();
+        assert!(text.starts_with("📅 <b>2023/01/10 每日行程</b> (UTC+08:00)\n"));
+        assert!(text.contains(
+            "- <a href=\"https://t.me/channel/1\">【轉蛋】精選轉蛋</a>\n   活動期間: 01/10 12:00 - 01/20 12:00"
+        ));
+        assert!(!text.contains("露娜之塔"));
+
+        // Open-ended events are not ending, and go on after they start
+        let open = |title, start| Event {
+            has_end: false,
+            ..event(title, start, start)
+        };
+        let events = vec![
+            open("【活動】今早開放", time(10, 5)),
+            open("【活動】今午開放", time(10, 12)),
+            open("【活動】N2倍掉落", time(5, 5)),
+            open("【活動】常駐開放", time(5, 5)),
+            open("【維護】維護後開放", time(5, 5)),
+        ];
+        let digest = Digest::new(events, &config(), now);
+        assert!(digest.ending.is_empty());
+        assert_eq!(
+            titles(&digest.starting),
+            vec!["【活動】今早開放", "【活動】今午開放"]
+        );
+        assert_eq!(titles(&digest.campaigns), vec!["【活動】N2倍掉落"]);
+        assert!(digest.maintenance.is_empty());
+        assert!(digest.text().contains("活動期間: 01/10 12:00 起"));
+
+        // Already tomorrow in Tokyo
+        let mut config = config();
+        config.time_zone = chrono_tz::Asia::Tokyo;
+        let digest = Digest::new(vec![], &config, time(10, 23));
+        assert_eq!(digest.date, NaiveDate::from_ymd_opt(2023, 1, 11).unwrap());
+        assert!(digest
+            .text()
+            .starts_with("📅 <b>2023/01/11 每日行程</b> (UTC+09:00)\n"));
+    }
+}
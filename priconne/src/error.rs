@@ -40,10 +40,36 @@ pub enum Error {
     NoApiServer,
     #[error("the article has no title")]
     EmptyTitleError,
+    #[error("the telegraph content has no page")]
+    EmptyContentError,
     #[error("source is invalid")]
     InvalidSource,
     #[error("failed to parse string to resource kind {0}")]
     ParseResourceKindsError(String),
+    #[error("no recorded response for {0}")]
+    CassetteMissError(url::Url),
+    #[error("failed to serialize bson")]
+    BsonSerError(#[from] mongodb::bson::ser::Error),
+    #[error("failed to deserialize bson")]
+    BsonDeError(#[from] mongodb::bson::de::Error),
+    #[error("embedded store error: {0}")]
+    EmbeddedStoreError(String),
+    #[error("missing configuration `{0}`")]
+    MissingConfigError(&'static str),
+    #[error("migration error: {0}")]
+    MigrationError(String),
+    #[error("{0} document(s) need migration, run `migrate` first")]
+    PendingMigrationError(usize),
+    #[error("failed to upload image: {0}")]
+    ImageUploadError(String),
+    #[error("dialogue storage error: {0}")]
+    DialogueError(String),
+    #[error("search index error: {0}")]
+    SearchIndexError(String),
+    #[error("template error: {0}")]
+    TemplateError(#[from] minijinja::Error),
+    #[error("invalid object id")]
+    ObjectIdError(#[from] mongodb::bson::oid::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
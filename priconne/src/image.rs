@@ -0,0 +1,280 @@
+//! Image re-hosting
+//!
+//! News pages are still served over plain HTTP, so their images fail to load
+//! in Telegraph. [`ImageHost`] downloads the images of a page, uploads them to
+//! the [configured store](ImageStoreConfig) and rewrites their `src`.
+//!
+//! Uploads are cached by the SHA-256 of their content in [`ImageRepository`],
+//! so an image shared by many posts is uploaded only once. An image failed to
+//! download or upload keeps its original URL.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use telegraph_rs::{Node, NodeElement, Telegraph};
+use url::Url;
+
+use crate::{database::ImageRepository, Error, Result};
+
+/// Where images are re-hosted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase", tag = "store")]
+pub enum ImageStoreConfig {
+    /// Telegraph upload endpoint
+    #[default]
+    Telegraph,
+    /// Files in a local directory, served at `url`
+    Directory { path: PathBuf, url: Url },
+    /// Keep the original URLs
+    Disabled,
+}
+
+/// An image uploaded to the store.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostedImage {
+    /// SHA-256 of the content, in hex
+    #[serde(rename = "_id")]
+    pub hash: String,
+    /// URL the image was first downloaded from
+    pub source: String,
+    /// URL in the store
+    pub url: String,
+    #[serde_as(as = "mongodb::bson::DateTime")]
+    pub upload_time: DateTime<Utc>,
+}
+
+/// Re-hosts images of Telegraph content.
+pub struct ImageHost {
+    client: reqwest::Client,
+    store: ImageStoreConfig,
+    images: Arc<dyn ImageRepository>,
+}
+
+impl ImageHost {
+    pub fn new(
+        client: reqwest::Client,
+        store: ImageStoreConfig,
+        images: Arc<dyn ImageRepository>,
+    ) -> Self {
+        Self {
+            client,
+            store,
+            images,
+        }
+    }
+
+    /// Re-host images in `content`, serialized Telegraph nodes of the page at `base`.
+    pub async fn rehost_content(&self, content: &str, base: &Url) -> Result<String> {
+        if let ImageStoreConfig::Disabled = self.store {
+            return Ok(content.to_string());
+        }
+
+        let mut nodes: Vec<Node> = serde_json::from_str(content)?;
+        let mut sources = Vec::new();
+        image_sources(&nodes, &mut sources);
+
+        let mut replacements = HashMap::new();
+        for src in sources {
+            if replacements.contains_key(&src) {
+                continue;
+            }
+            let url = match base.join(&src) {
+                Ok(url) => url,
+                Err(e) => {
+                    tracing::warn!("invalid image source {src}: {e}");
+                    continue;
+                }
+            };
+            let hosted = match self.rehost(&url).await {
+                Ok(hosted) => hosted,
+                Err(e) => {
+                    tracing::warn!("failed to re-host {url}, keep the original: {e}");
+                    url.to_string()
+                }
+            };
+            replacements.insert(src, hosted);
+        }
+
+        replace_sources(&mut nodes, &replacements);
+        Ok(serde_json::to_string(&nodes)?)
+    }
+
+    /// Upload the image at `url`, or find it uploaded before, returns the new URL.
+    pub async fn rehost(&self, url: &Url) -> Result<String> {
+        let response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        let mime = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| guess_mime(url).to_string());
+        let bytes = response.bytes().await?;
+
+        let hash = hex::encode(Sha256::digest(&bytes));
+        if let Some(image) = self.images.find_by_hash(&hash).await? {
+            tracing::debug!("{url} already uploaded as {}", image.url);
+            return Ok(image.url);
+        }
+
+        let hosted = self.upload(&hash, &bytes, &mime).await?;
+        tracing::info!("uploaded {url} as {hosted}");
+        self.images
+            .upsert(&HostedImage {
+                hash,
+                source: url.to_string(),
+                url: hosted.clone(),
+                upload_time: Utc::now(),
+            })
+            .await?;
+        Ok(hosted)
+    }
+
+    async fn upload(&self, hash: &str, bytes: &[u8], mime: &str) -> Result<String> {
+        match &self.store {
+            ImageStoreConfig::Telegraph => {
+                let uploaded = Telegraph::upload_with(&[(bytes, mime)], &self.client).await?;
+                let src = uploaded
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| Error::ImageUploadError("nothing uploaded".to_string()))?
+                    .src;
+                Ok(format!("https://telegra.ph{src}"))
+            }
+            ImageStoreConfig::Directory { path, url } => {
+                let name = format!("{hash}.{}", extension(mime));
+                tokio::fs::create_dir_all(path).await?;
+                tokio::fs::write(path.join(&name), bytes).await?;
+                Ok(url.join(&name)?.to_string())
+            }
+            ImageStoreConfig::Disabled => Err(Error::ImageUploadError(
+                "image store is disabled".to_string(),
+            )),
+        }
+    }
+}
+
+/// Collect `src` of all `<img>` in `nodes`.
+fn image_sources(nodes: &[Node], sources: &mut Vec<String>) {
+    for node in nodes {
+        let Node::NodeElement(element) = node else {
+            continue;
+        };
+        if element.tag == "img" {
+            if let Some(Some(src)) = element.attrs.as_ref().and_then(|attrs| attrs.get("src")) {
+                sources.push(src.clone());
+            }
+        }
+        if let Some(children) = &element.children {
+            image_sources(children, sources);
+        }
+    }
+}
+
+/// Rewrite `src` of `<img>` in `nodes` by `replacements`.
+fn replace_sources(nodes: &mut [Node], replacements: &HashMap<String, String>) {
+    for node in nodes {
+        let Node::NodeElement(NodeElement {
+            tag,
+            attrs,
+            children,
+        }) = node
+        else {
+            continue;
+        };
+        if tag == "img" {
+            if let Some(Some(src)) = attrs.as_mut().and_then(|attrs| attrs.get_mut("src")) {
+                if let Some(replacement) = replacements.get(src) {
+                    *src = replacement.clone();
+                }
+            }
+        }
+        if let Some(children) = children {
+            replace_sources(children, replacements);
+        }
+    }
+}
+
+fn guess_mime(url: &Url) -> &'static str {
+    let path = url.path().to_lowercase();
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "jpg",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::database::{EmbeddedStore, Storage};
+
+    #[tokio::test]
+    async fn test_rehost_content() -> Result<()> {
+        let app = Router::new().route(
+            "/uploads/banner.png",
+            get(|| async { ([(CONTENT_TYPE, "image/png")], "not really a png") }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let base = Url::parse(&format!(
+            "http://{}/news/newsDetail/1774",
+            listener.local_addr()?
+        ))?;
+        tokio::spawn(axum::Server::from_tcp(listener)?.serve(app.into_make_service()));
+
+        let path = std::env::temp_dir().join("priconne-test-images");
+        let _ = std::fs::remove_dir_all(&path);
+        let store = ImageStoreConfig::Directory {
+            path: path.clone(),
+            url: Url::parse("https://example.com/images/")?,
+        };
+        let storage = Storage::Embedded(EmbeddedStore::in_memory());
+        let host = ImageHost::new(reqwest::Client::new(), store, storage.images()?);
+
+        let content = r#"[{"tag":"figure","children":[{"tag":"img","attrs":{"src":"/uploads/banner.png"}}]},{"tag":"img","attrs":{"src":"/uploads/missing.png"}},"text"]"#;
+        let rehosted = host.rehost_content(content, &base).await?;
+
+        let hash = hex::encode(Sha256::digest(b"not really a png"));
+        let hosted = format!("https://example.com/images/{hash}.png");
+        assert!(rehosted.contains(&format!(r#""src":"{hosted}""#)));
+        assert!(rehosted.contains(&format!(
+            r#""src":"{}""#,
+            base.join("/uploads/missing.png")?
+        )));
+        assert!(path.join(format!("{hash}.png")).exists());
+
+        // Cached by content
+        std::fs::remove_dir_all(&path)?;
+        let url = base.join("/uploads/banner.png?v=2")?;
+        assert_eq!(host.rehost(&url).await?, hosted);
+        assert!(!path.exists());
+
+        let image = storage.images()?.find_by_hash(&hash).await?.unwrap();
+        assert_eq!(image.source, base.join("/uploads/banner.png")?.to_string());
+        Ok(())
+    }
+}
@@ -1,8 +1,26 @@
-use crate::utils::HOUR;
-use chrono::{DateTime, FixedOffset, TimeZone, Utc};
-use kuchikiki::{traits::NodeIterator, ElementData, NodeDataRef};
+//! Events in announcements
+//!
+//! Events are periods under headings ending in `期間`, like
+//!
+//! ```text
+//! ■舉辦期間
+//! 2021/12/27 05:00 ～ 2021/12/31 23:59
+//! ```
+//!
+//! or on the same line as the heading, after `：`. Periods may have full-width
+//! digits, dates without a year or a time, weekdays like `(一)`, and open or
+//! maintenance bounds, see [`PeriodPattern`].
+
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc};
+use kuchikiki::{ElementData, NodeDataRef};
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
 
+use super::summary::get_lines;
+use crate::utils::{local_to_fixed, Tz};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct EventInAnnouncement {
     #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
@@ -10,51 +28,630 @@ pub struct EventInAnnouncement {
     #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub end: DateTime<Utc>,
     pub title: String,
+    /// Pattern of the period, `None` for events extracted before it was recorded
+    #[serde(default)]
+    pub pattern: Option<PeriodPattern>,
+}
+
+impl EventInAnnouncement {
+    /// Whether the end is known. Open ends, as in [`PeriodPattern::Since`] or
+    /// maintenance bounds that cannot be resolved, are stored as the start.
+    pub fn has_end(&self) -> bool {
+        let open = self
+            .pattern
+            .is_some_and(|pattern| PeriodPattern::OPEN.contains(&pattern));
+        !(open && self.end == self.start)
+    }
 }
 
-fn parse_period(period_str: &str) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
-    let offset = FixedOffset::east_opt(8 * HOUR).unwrap();
-    // 2021/12/27 05:00
-    let fmt = "%Y/%m/%d %H:%M";
+/// Shape of a period.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeriodPattern {
+    /// `2021/12/27 05:00 ～ 2021/12/31 23:59`
+    Range,
+    /// `2021/12/27 05:00起`, without an end
+    Since,
+    /// `～ 2021/12/31 23:59` or `2021/12/31 23:59前`, without a start
+    Until,
+    /// `維護後 ～ 2021/12/31 23:59`
+    AfterMaintenance,
+    /// `2021/12/27 05:00 ～ 維護前`, or `～ 維護後`
+    UntilMaintenance,
+}
 
-    let period_str = period_str.trim();
-    let (start, end) = period_str.split_once(&['～', '~'][..])?;
-    let start = start.trim();
-    let end = end.trim();
+impl PeriodPattern {
+    /// Patterns that may have no end.
+    pub const OPEN: [Self; 3] = [Self::Since, Self::AfterMaintenance, Self::UntilMaintenance];
+}
 
-    let start = offset
-        .datetime_from_str(start, fmt)
-        .ok()?
-        .with_timezone(&Utc);
-    let end = offset.datetime_from_str(end, fmt).ok()?.with_timezone(&Utc);
+/// A bound of a period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Time(DateTime<Utc>),
+    /// When the maintenance begins, `維護前`
+    MaintenanceStart,
+    /// When the maintenance ends, `維護後`
+    MaintenanceEnd,
+}
 
-    Some((start, end))
+/// A parsed period, with `None` for open bounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Period {
+    pub start: Option<Bound>,
+    pub end: Option<Bound>,
+    pub pattern: PeriodPattern,
 }
 
-pub fn get_events(content_node: &NodeDataRef<ElementData>) -> Vec<EventInAnnouncement> {
-    let mut periods = Vec::new();
+/// A bound before its year is known.
+#[derive(Clone, Copy)]
+enum LocalBound {
+    Open,
+    Maintenance(Bound),
+    Date {
+        year: Option<i32>,
+        month: u32,
+        day: u32,
+        time: Option<NaiveTime>,
+    },
+}
+
+/// Parser of periods in the local time of a zone.
+pub struct PeriodParser {
+    zone: Tz,
+    /// Local date that dates without a year are near
+    reference: NaiveDate,
+}
+
+/// Days a date without a year can be from the reference.
+const HALF_YEAR: i64 = 183;
+
+impl PeriodParser {
+    pub fn new(zone: Tz, reference: NaiveDate) -> Self {
+        Self { zone, reference }
+    }
+
+    /// A date with an optional year, weekday and time, in ASCII digits.
+    fn date_regex() -> &'static Regex {
+        static DATE: OnceLock<Regex> = OnceLock::new();
+        DATE.get_or_init(|| {
+            Regex::new(
+                r"^(?:([0-9]{4})/)?([0-9]{1,2})/([0-9]{1,2})(?:\s*\([^)]{1,3}\))?(?:\s*([0-9]{1,2}):([0-9]{2}))?$",
+            )
+            .unwrap()
+        })
+    }
+
+    /// Parse a whole line as a period.
+    pub fn parse(&self, text: &str) -> Option<Period> {
+        let text = normalize(text);
+        let text = text.trim();
+        let (start, end) = match text.split_once('～') {
+            Some((start, end)) => (start, end),
+            None => {
+                if let Some(start) = strip_suffix(text, &["起", "開始", "以後"]) {
+                    (start, "")
+                } else {
+                    ("", strip_suffix(text, &["為止", "以前", "前"])?)
+                }
+            }
+        };
+        let start = strip_suffix(start, &["起"]).unwrap_or(start);
+        let end = strip_suffix(end, &["為止", "止"]).unwrap_or(end);
+
+        let start = self.local_bound(start.trim())?;
+        let end = self.local_bound(end.trim())?;
+        let (start, end) = self.resolve_years(start, end)?;
+
+        let pattern = match (start, end) {
+            (Some(Bound::Time(_)), Some(Bound::Time(_))) => PeriodPattern::Range,
+            (Some(Bound::MaintenanceStart | Bound::MaintenanceEnd), _) => {
+                PeriodPattern::AfterMaintenance
+            }
+            (_, Some(Bound::MaintenanceStart | Bound::MaintenanceEnd)) => {
+                PeriodPattern::UntilMaintenance
+            }
+            (Some(_), None) => PeriodPattern::Since,
+            (None, Some(_)) => PeriodPattern::Until,
+            (None, None) => return None,
+        };
+        Some(Period {
+            start,
+            end,
+            pattern,
+        })
+    }
+
+    fn local_bound(&self, text: &str) -> Option<LocalBound> {
+        match text {
+            "" => return Some(LocalBound::Open),
+            "維護前" | "維護開始前" => {
+                return Some(LocalBound::Maintenance(Bound::MaintenanceStart))
+            }
+            "維護後" | "維護結束後" => {
+                return Some(LocalBound::Maintenance(Bound::MaintenanceEnd))
+            }
+            _ => {}
+        }
+
+        let captures = Self::date_regex().captures(text)?;
+        let number = |captures: &Captures, index| -> Option<Option<u32>> {
+            match captures.get(index) {
+                Some(number) => Some(Some(number.as_str().parse().ok()?)),
+                None => Some(None),
+            }
+        };
+        let time = match (number(&captures, 4)?, number(&captures, 5)?) {
+            (Some(hour), Some(minute)) => Some(NaiveTime::from_hms_opt(hour, minute, 0)?),
+            _ => None,
+        };
+        Some(LocalBound::Date {
+            year: number(&captures, 1)?.map(|year| year as i32),
+            month: number(&captures, 2)??,
+            day: number(&captures, 3)??,
+            time,
+        })
+    }
+
+    /// Fill in years missing from the other bound, or the year putting the
+    /// date within half a year of the reference. A bound without a year is
+    /// moved by a year if the end is before the start.
+    fn resolve_years(
+        &self,
+        start: LocalBound,
+        end: LocalBound,
+    ) -> Option<(Option<Bound>, Option<Bound>)> {
+        let year_of = |bound: &LocalBound| match bound {
+            LocalBound::Date { year, .. } => *year,
+            _ => None,
+        };
+        let end_time = NaiveTime::from_hms_opt(23, 59, 0).unwrap();
+        let year = year_of(&start)
+            .or(year_of(&end))
+            .or_else(|| self.nearest_year(start).or(self.nearest_year(end)))
+            .unwrap_or(self.reference.year());
+        let end_year = year_of(&end).unwrap_or(year);
+
+        let mut start_bound = self.bound(start, year, NaiveTime::MIN)?;
+        let mut end_bound = self.bound(end, end_year, end_time)?;
+        if let (Some(Bound::Time(start_time)), Some(Bound::Time(end_time_utc))) =
+            (start_bound, end_bound)
+        {
+            if end_time_utc < start_time {
+                if year_of(&end).is_none() {
+                    end_bound = self.bound(end, year + 1, end_time)?;
+                } else if year_of(&start).is_none() {
+                    start_bound = self.bound(start, year - 1, NaiveTime::MIN)?;
+                }
+            }
+        }
+        Some((start_bound, end_bound))
+    }
+
+    /// Year of a date without one, so that it is within half a year of the
+    /// reference, as in a post of December about January.
+    fn nearest_year(&self, bound: LocalBound) -> Option<i32> {
+        let LocalBound::Date { month, day, .. } = bound else {
+            return None;
+        };
+        let year = self.reference.year();
+        let date = NaiveDate::from_ymd_opt(year, month, day)?;
+        Some(match (date - self.reference).num_days() {
+            days if days < -HALF_YEAR => year + 1,
+            days if days > HALF_YEAR => year - 1,
+            _ => year,
+        })
+    }
+
+    /// The bound in `year`, or `None` if the date is invalid.
+    fn bound(&self, bound: LocalBound, year: i32, default: NaiveTime) -> Option<Option<Bound>> {
+        match bound {
+            LocalBound::Open => Some(None),
+            LocalBound::Maintenance(bound) => Some(Some(bound)),
+            LocalBound::Date {
+                month, day, time, ..
+            } => {
+                let date = NaiveDate::from_ymd_opt(year, month, day)?;
+                let local = date.and_time(time.unwrap_or(default));
+                let time = local_to_fixed(local, self.zone).with_timezone(&Utc);
+                Some(Some(Bound::Time(time)))
+            }
+        }
+    }
+}
+
+/// Whether `text` is a period, in any zone.
+pub fn is_period(text: &str) -> bool {
+    PeriodParser::new(chrono_tz::UTC, NaiveDate::from_ymd_opt(2000, 1, 1).unwrap())
+        .parse(text)
+        .is_some()
+}
+
+/// Convert full-width digits and punctuation, and all kinds of tildes to `～`.
+fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap(),
+            '／' => '/',
+            '：' => ':',
+            '（' => '(',
+            '）' => ')',
+            '　' => ' ',
+            '~' | '〜' | '∼' => '～',
+            c => c,
+        })
+        .collect()
+}
+
+fn strip_suffix<'a>(text: &'a str, suffixes: &[&str]) -> Option<&'a str> {
+    suffixes
+        .iter()
+        .find_map(|suffix| text.strip_suffix(suffix))
+        .map(str::trim_end)
+}
 
-    let iter = content_node.as_node().descendants().text_nodes();
-    let iter = iter.clone().zip(iter.skip(1));
-    for (name, time) in iter {
-        let name = name.borrow();
-        let name = name.trim();
-        let time = time.borrow();
-        let time = time.trim();
+/// Split a line into a heading ending in `期間` and the rest.
+fn split_heading(line: &str) -> Option<(&str, &str)> {
+    let index = line.find("期間")? + "期間".len();
+    let (name, rest) = line.split_at(index);
+    let name = name.trim_start_matches(['■', '・', '◆', '●']).trim();
+    let name = match name.split_once('.') {
+        Some((number, name)) if number.chars().all(|c| c.is_ascii_digit()) => name.trim(),
+        _ => name,
+    };
+    // Sentences mentioning a period are not headings
+    if name.is_empty() || name.contains(['，', '。', '、', ',']) {
+        return None;
+    }
+    let rest = rest.trim_start_matches([':', '：', ' ', '　']);
+    Some((name, rest))
+}
+
+/// Events in the content, with times local to `zone`.
+///
+/// Dates without a year are within half a year of `reference`, the time of the
+/// post.
+/// An open start is the reference, and an open end is stored as the start, see
+/// [`EventInAnnouncement::has_end`]. Maintenance bounds are resolved with a
+/// maintenance period in the same content if they are in order with the other
+/// bound, otherwise they are of another maintenance and treated as open.
+pub fn get_events(
+    content_node: &NodeDataRef<ElementData>,
+    zone: Tz,
+    reference: DateTime<Utc>,
+) -> Vec<EventInAnnouncement> {
+    let parser = PeriodParser::new(zone, reference.with_timezone(&zone).date_naive());
+    let lines = get_lines(content_node.as_node());
 
-        if !name.ends_with("期間") {
+    let mut periods = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let Some((title, rest)) = split_heading(line) else {
             continue;
+        };
+        let period = match rest {
+            "" => lines.get(index + 1).and_then(|next| parser.parse(next)),
+            rest => parser.parse(rest),
+        };
+        if let Some(period) = period {
+            periods.push((title.to_string(), period));
         }
-        let name = name.trim_start_matches('■');
+    }
 
-        if let Some((start, end)) = parse_period(time) {
-            periods.push(EventInAnnouncement {
-                start,
-                end,
-                title: name.to_string(),
+    let maintenance = periods.iter().find_map(|(title, period)| {
+        match (title.contains("維護"), period.start, period.end) {
+            (true, Some(Bound::Time(start)), Some(Bound::Time(end))) => Some((start, end)),
+            _ => None,
+        }
+    });
+    let time = |bound: Bound| match bound {
+        Bound::Time(time) => Some(time),
+        Bound::MaintenanceStart => maintenance.map(|(start, _)| start),
+        Bound::MaintenanceEnd => maintenance.map(|(_, end)| end),
+    };
+
+    periods
+        .into_iter()
+        .map(|(title, period)| {
+            let is_maintenance = |bound: Option<Bound>| {
+                matches!(bound, Some(Bound::MaintenanceStart | Bound::MaintenanceEnd))
+            };
+            let start = period.start.and_then(time);
+            let end = period.end.and_then(time).filter(|end| {
+                !is_maintenance(period.end) || start.is_none_or(|start| *end > start)
             });
+            let start = start
+                .filter(|start| !is_maintenance(period.start) || end.is_none_or(|end| *start < end))
+                .unwrap_or_else(|| end.map_or(reference, |end| end.min(reference)));
+            EventInAnnouncement {
+                start,
+                end: end.unwrap_or(start).max(start),
+                title,
+                pattern: Some(period.pattern),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use chrono::TimeZone;
+    use kuchikiki::traits::TendrilSink;
+
+    use super::*;
+    use crate::{resource::news::NewsPage, utils::DEFAULT_TIME_ZONE, Page};
+
+    fn time(text: &str) -> DateTime<Utc> {
+        crate::utils::string_to_date(text, "%Y/%m/%d %H:%M", DEFAULT_TIME_ZONE)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn extract(html: &str) -> Vec<EventInAnnouncement> {
+        let document = kuchikiki::parse_html().one(html);
+        let body = document.select_first("body").unwrap();
+        get_events(&body, DEFAULT_TIME_ZONE, time("2021/12/20 12:00"))
+    }
+
+    #[test]
+    fn test_parse_period() {
+        let parser = PeriodParser::new(
+            DEFAULT_TIME_ZONE,
+            NaiveDate::from_ymd_opt(2021, 12, 20).unwrap(),
+        );
+        let period = |text| {
+            let period = parser.parse(text)?;
+            let bound = |bound: Option<Bound>| match bound {
+                Some(Bound::Time(time)) => time
+                    .with_timezone(&DEFAULT_TIME_ZONE)
+                    .format("%Y/%m/%d %H:%M")
+                    .to_string(),
+                Some(Bound::MaintenanceStart) => "維護前".to_string(),
+                Some(Bound::MaintenanceEnd) => "維護後".to_string(),
+                None => "-".to_string(),
+            };
+            Some((bound(period.start), bound(period.end), period.pattern))
+        };
+        let expect =
+            |start: &str, end: &str, pattern| Some((start.to_string(), end.to_string(), pattern));
+
+        use PeriodPattern::*;
+        let cases = [
+            (
+                "2021/12/27 05:00 ～ 2021/12/31 23:59",
+                expect("2021/12/27 05:00", "2021/12/31 23:59", Range),
+            ),
+            (
+                "2021/12/27 05:00~2021/12/31 23:59",
+                expect("2021/12/27 05:00", "2021/12/31 23:59", Range),
+            ),
+            (
+                "２０２１／１２／２７ ０５：００ 〜 ２０２１／１２／３１ ２３：５９",
+                expect("2021/12/27 05:00", "2021/12/31 23:59", Range),
+            ),
+            (
+                "2021/12/27(一) 05:00 ～ 2021/12/31（五）23:59",
+                expect("2021/12/27 05:00", "2021/12/31 23:59", Range),
+            ),
+            (
+                "12/27 05:00 ～ 12/31 23:59",
+                expect("2021/12/27 05:00", "2021/12/31 23:59", Range),
+            ),
+            (
+                "2021/12/28 ～ 1/3",
+                expect("2021/12/28 00:00", "2022/01/03 23:59", Range),
+            ),
+            (
+                "12/28 05:00 ～ 2022/1/3 23:59",
+                expect("2021/12/28 05:00", "2022/01/03 23:59", Range),
+            ),
+            // Posted in December, about January
+            (
+                "1/1 05:00 ～ 1/7 23:59",
+                expect("2022/01/01 05:00", "2022/01/07 23:59", Range),
+            ),
+            ("2021/12/27 05:00起", expect("2021/12/27 05:00", "-", Since)),
+            (
+                "2021/12/27 05:00 ～",
+                expect("2021/12/27 05:00", "-", Since),
+            ),
+            (
+                "～ 2021/12/31 23:59",
+                expect("-", "2021/12/31 23:59", Until),
+            ),
+            ("11/10前", expect("-", "2021/11/10 23:59", Until)),
+            (
+                "2021/12/31 23:59為止",
+                expect("-", "2021/12/31 23:59", Until),
+            ),
+            (
+                "維護後 ～ 2021/12/31 23:59",
+                expect("維護後", "2021/12/31 23:59", AfterMaintenance),
+            ),
+            (
+                "2021/12/27 05:00 ～ 維護前",
+                expect("2021/12/27 05:00", "維護前", UntilMaintenance),
+            ),
+            (
+                "2021/12/27 05:00～維護後",
+                expect("2021/12/27 05:00", "維護後", UntilMaintenance),
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(period(text), expected, "{text}");
+        }
+
+        for text in [
+            "",
+            "～",
+            "2021/12/27 05:00",
+            "活動期間內",
+            "2021/13/27 ～ 2021/12/31",
+            "維護前",
+            "١٢/٢٧ ～ 12/31",
+        ] {
+            assert_eq!(period(text), None, "{text}");
         }
+
+        // Posted in January, about December
+        let parser = PeriodParser::new(
+            DEFAULT_TIME_ZONE,
+            NaiveDate::from_ymd_opt(2022, 1, 3).unwrap(),
+        );
+        let period = parser.parse("12/28 ～ 1/5").unwrap();
+        assert_eq!(period.start, Some(Bound::Time(time("2021/12/28 00:00"))));
+        assert_eq!(period.end, Some(Bound::Time(time("2022/01/05 23:59"))));
     }
 
-    periods
+    #[test]
+    fn test_get_events() {
+        let events = extract(
+            "<p>■維護期間<br>12/21 10:00 ～ 12/21 15:00</p>\
+             <p>■舉辦期間：維護後 ～ 2021/12/31 23:59</p>\
+             <p><span>■兌換期間</span></p><p><b>2021/12/27 05:00</b> ～ 維護前</p>\
+             <p>■開放期間</p><p>12/22 12:00起</p>\
+             <p>■報名期間：～12/25</p>\
+             <p>活動期間內，2021/12/27 05:00 ～ 2021/12/31 23:59</p>",
+        );
+        let expected = [
+            (
+                "維護期間",
+                "2021/12/21 10:00",
+                "2021/12/21 15:00",
+                PeriodPattern::Range,
+            ),
+            (
+                "舉辦期間",
+                "2021/12/21 15:00",
+                "2021/12/31 23:59",
+                PeriodPattern::AfterMaintenance,
+            ),
+            // The maintenance is before the start, so the end is the next
+            // maintenance, which is unknown
+            (
+                "兌換期間",
+                "2021/12/27 05:00",
+                "2021/12/27 05:00",
+                PeriodPattern::UntilMaintenance,
+            ),
+            (
+                "開放期間",
+                "2021/12/22 12:00",
+                "2021/12/22 12:00",
+                PeriodPattern::Since,
+            ),
+            (
+                "報名期間",
+                "2021/12/20 12:00",
+                "2021/12/25 23:59",
+                PeriodPattern::Until,
+            ),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(title, start, end, pattern)| EventInAnnouncement {
+                start: time(start),
+                end: time(end),
+                title: title.to_string(),
+                pattern: Some(pattern),
+            })
+            .collect();
+        assert_eq!(events, expected);
+        let has_end: Vec<_> = events.iter().map(EventInAnnouncement::has_end).collect();
+        assert_eq!(has_end, [true, true, false, false, true]);
+
+        // Without a maintenance period, the start is the reference
+        let events = extract("<p>■舉辦期間</p><p>維護後～2021/12/31 23:59</p>");
+        assert_eq!(events[0].start, time("2021/12/20 12:00"));
+        assert_eq!(events[0].end, time("2021/12/31 23:59"));
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let expected: HashMap<&str, Vec<(&str, &str, &str, PeriodPattern)>> = HashMap::from([
+            (
+                "news_page",
+                vec![(
+                    "精選轉蛋舉辦期間",
+                    "2021/08/25 16:00",
+                    "2021/09/01 15:59",
+                    PeriodPattern::Range,
+                )],
+            ),
+            (
+                "news_1376",
+                vec![(
+                    "舉辦期間",
+                    "2021/10/27 05:00",
+                    "2021/10/31 23:59",
+                    PeriodPattern::Range,
+                )],
+            ),
+            (
+                "news_1460",
+                vec![
+                    (
+                        "模式變更期間",
+                        "2021/12/20 12:00",
+                        "2021/12/25 11:59",
+                        PeriodPattern::Range,
+                    ),
+                    (
+                        "訓練模式解放期間",
+                        "2021/12/24 12:00",
+                        "2021/12/31 23:59",
+                        PeriodPattern::Range,
+                    ),
+                    (
+                        "戰隊競賽舉辦期間",
+                        "2021/12/27 05:00",
+                        "2021/12/31 23:59",
+                        PeriodPattern::Range,
+                    ),
+                ],
+            ),
+            ("news_list", vec![]),
+        ]);
+
+        let mut fixtures: Vec<_> = std::fs::read_dir("tests")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("news_") && name.ends_with(".html")
+            })
+            .collect();
+        fixtures.sort();
+        assert!(!fixtures.is_empty());
+
+        for path in fixtures {
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let expected = expected
+                .get(name.as_str())
+                .unwrap_or_else(|| panic!("no expected events for {name}"));
+            let html = std::fs::read_to_string(Path::new(&path)).unwrap();
+
+            // The list page has no content, so the whole document is searched
+            let content = match NewsPage::from_html(html.clone()) {
+                Ok(page) => page.content_node.clone().into_element_ref().unwrap(),
+                Err(_) => kuchikiki::parse_html()
+                    .one(html)
+                    .select_first("body")
+                    .unwrap(),
+            };
+            let reference = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+            let events: Vec<_> = get_events(&content, DEFAULT_TIME_ZONE, reference)
+                .into_iter()
+                .map(|event| (event.title, event.start, event.end, event.pattern.unwrap()))
+                .collect();
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(title, start, end, pattern)| {
+                    (title.to_string(), time(start), time(end), *pattern)
+                })
+                .collect();
+            assert_eq!(events, expected, "{name}");
+        }
+    }
 }
@@ -1,20 +1,21 @@
 mod event;
+pub mod summary;
 pub mod tagging;
 
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug};
 
-pub use event::{get_events, EventInAnnouncement};
+pub use event::{get_events, EventInAnnouncement, PeriodPattern};
 
 use chrono::{DateTime, FixedOffset, Utc};
 use linked_hash_set::LinkedHashSet;
 
-use mongodb::bson::{self, Bson};
+use mongodb::bson::Bson;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::serde_as;
 
-use crate::resource::{
-    announcement::{sources::AnnouncementSource, AnnouncementResponse},
-    Announcement,
+use crate::{
+    resource::announcement::{sources::AnnouncementSource, AnnouncementResponse},
+    utils::{Tz, DEFAULT_TIME_ZONE},
 };
 
 use self::tagging::RegexTagger;
@@ -71,65 +72,11 @@ where
     }
 }
 
-impl AnnouncementInsight<bson::Bson> {
-    pub fn build_message(&self, post: &Announcement) -> String {
-        // let (title, tags) = tags(&page, &self.tagger);
-        let link = self.telegraph_url.as_ref().map_or("#NOURL", |s| s.as_str());
-        let id = self.id;
-        let create_time = self.create_time.map_or("".to_string(), |t| t.to_string());
-        let events = &post.events;
-        let tags = &self.tags;
-        let title = if self.title.starts_with('【') {
-            if let Some((_, title)) = self.title.split_once('】') {
-                title
-            } else {
-                &self.title
-            }
-        } else {
-            &self.title
-        };
-
-        let mut tag_str = String::new();
-
-        for tag in tags {
-            tag_str.push('#');
-            tag_str.push_str(tag);
-            tag_str.push(' ');
-        }
-
-        if !tag_str.is_empty() {
-            tag_str.pop();
-            tag_str.push('\n');
-        }
-
-        let mut event_str = String::new();
-
-        for event in events {
-            event_str.push_str("- ");
-            event_str.push_str(&event.title);
-            event_str.push_str(": \n   ");
-            event_str.push_str(event.start.format("%m/%d %H:%M").to_string().as_str());
-            event_str.push_str(" - ");
-            event_str.push_str(event.end.format("%m/%d %H:%M").to_string().as_str());
-            event_str.push('\n');
-        }
-        if !event_str.is_empty() {
-            event_str.insert(0, '\n');
-            event_str.push('\n');
-        }
-
-        let head = format!("{tag_str}<b>{title}</b>\n");
-
-        let tail = format!("{link}\n{create_time} <code>#{id}</code>");
-
-        let message = format!("{head}{event_str}{tail}");
-        message
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Extractor {
     pub tagger: RegexTagger,
+    /// Time zone of each source, [`DEFAULT_TIME_ZONE`] if not listed
+    pub time_zones: HashMap<AnnouncementSource, Tz>,
 }
 
 pub trait AnnouncementPage {
@@ -137,23 +84,36 @@ pub trait AnnouncementPage {
 
     fn title(&self) -> String;
     fn content(&self) -> kuchikiki::NodeRef;
-    fn create_time(&self) -> Option<DateTime<FixedOffset>>;
+    /// Time the page is posted, with local times in `zone`
+    fn create_time(&self, zone: Tz) -> Option<DateTime<FixedOffset>>;
     fn extra(&self) -> Self::ExtraData;
     fn tags(&self, tagger: &RegexTagger) -> LinkedHashSet<String> {
         tagger.tag_title(&self.title())
     }
-    fn events(&self) -> Vec<EventInAnnouncement> {
-        get_events(&self.content().into_element_ref().unwrap())
+    fn events(&self, zone: Tz) -> Vec<EventInAnnouncement> {
+        let reference = self
+            .create_time(zone)
+            .map_or_else(Utc::now, |time| time.with_timezone(&Utc));
+        get_events(&self.content().into_element_ref().unwrap(), zone, reference)
     }
 }
 
 impl Extractor {
+    /// Time zone of pages from `source`.
+    pub fn time_zone(&self, source: &AnnouncementSource) -> Tz {
+        self.time_zones
+            .get(source)
+            .copied()
+            .unwrap_or(DEFAULT_TIME_ZONE)
+    }
+
     /// Extract announcement insight and events from the response.
     pub fn extract_announcement<P: AnnouncementPage>(
         &self,
         response: &AnnouncementResponse<P>,
     ) -> AnnouncementInsight<P::ExtraData> {
         let page = &response.page;
+        let zone = self.time_zone(&response.source);
 
         AnnouncementInsight::<P::ExtraData> {
             id: response.post_id,
@@ -161,10 +121,10 @@ impl Extractor {
             source: response.source.clone(),
             tags: page.tags(&self.tagger),
             title: page.title(),
-            create_time: page.create_time().map(|t| t.with_timezone(&Utc)),
-            update_time: page.create_time().map(|t| t.with_timezone(&Utc)),
+            create_time: page.create_time(zone).map(|t| t.with_timezone(&Utc)),
+            update_time: page.create_time(zone).map(|t| t.with_timezone(&Utc)),
             telegraph_url: None,
-            events: page.events(),
+            events: page.events(zone),
             extra: page.extra(),
         }
     }
@@ -0,0 +1,280 @@
+//! Summary from section headers
+//!
+//! So-net posts are split into sections by `■` headings, like `■贈品內容`.
+//! The summary lists the key facts of each section as bullets, leaving out
+//! periods, which are shown as events, and boilerplate like `■注意事項`.
+
+use kuchikiki::{iter::NodeEdge, NodeRef};
+
+use super::event::is_period;
+
+/// Headings of sections left out of the summary.
+const BOILERPLATE: [&str; 4] = ["注意事項", "方法", "參考範例", "詳細"];
+
+/// Maximum length of a bullet, in characters.
+const ITEM_LIMIT: usize = 80;
+
+/// A `■` section of a post.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Heading without `■`, `None` for the text before the first heading
+    pub heading: Option<String>,
+    /// Non-empty lines of the section
+    pub lines: Vec<String>,
+}
+
+impl Section {
+    fn is_boilerplate(&self) -> bool {
+        self.heading
+            .as_ref()
+            .is_some_and(|heading| BOILERPLATE.iter().any(|word| heading.contains(word)))
+    }
+
+    /// Periods are already listed as events.
+    fn is_period(&self) -> bool {
+        self.heading
+            .as_ref()
+            .is_some_and(|heading| heading.ends_with("期間"))
+            && self.lines.first().is_some_and(|line| is_period(line))
+    }
+
+    /// Key facts of the section: list items and names if any, otherwise the
+    /// first line. Notes starting with `※` are skipped.
+    fn facts(&self) -> Vec<&str> {
+        let lines: Vec<&str> = self
+            .lines
+            .iter()
+            .map(|line| line.as_str())
+            .filter(|line| !line.starts_with('※'))
+            .collect();
+        let items: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| {
+                line.strip_prefix('・')
+                    .or_else(|| (line.starts_with('《') && line.ends_with('》')).then_some(*line))
+            })
+            .collect();
+
+        if items.is_empty() {
+            lines.into_iter().take(1).collect()
+        } else {
+            items
+        }
+    }
+
+    /// The section as a bullet, `None` if it shouldn't be summarized.
+    fn bullet(&self) -> Option<String> {
+        let heading = self.heading.as_ref()?;
+        if self.is_boilerplate() || self.is_period() {
+            return None;
+        }
+        let facts = self.facts();
+        if facts.is_empty() {
+            return None;
+        }
+        Some(truncate(
+            &format!("・{heading}：{}", facts.join("、")),
+            ITEM_LIMIT,
+        ))
+    }
+}
+
+/// Elements starting a new line.
+pub const BLOCKS: [&str; 9] = ["br", "div", "p", "li", "ul", "ol", "tr", "table", "h3"];
+
+/// Lines of text as displayed, with list items starting with `・`.
+///
+/// Text nodes can't be used directly, since a line may be split into styled
+/// `<span>`s.
+pub(super) fn get_lines(content: &NodeRef) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for edge in content.traverse() {
+        match edge {
+            NodeEdge::Start(node) => {
+                if let Some(text) = node.as_text() {
+                    push_text(lines.last_mut().unwrap(), &text.borrow());
+                    continue;
+                }
+                let Some(element) = node.as_element() else {
+                    continue;
+                };
+                let name = &*element.name.local;
+                if BLOCKS.contains(&name) {
+                    lines.push(String::new());
+                }
+                if name == "li" {
+                    lines.last_mut().unwrap().push('・');
+                }
+            }
+            NodeEdge::End(node) => {
+                let is_block = node
+                    .as_element()
+                    .is_some_and(|element| BLOCKS.contains(&&*element.name.local));
+                if is_block {
+                    lines.push(String::new());
+                }
+            }
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty() && line != "・")
+        .collect()
+}
+
+/// Append `text` to `line` with whitespace collapsed, as displayed in HTML.
+pub fn push_text(line: &mut String, text: &str) {
+    let mut space = text.starts_with(char::is_whitespace);
+    for word in text.split_whitespace() {
+        if space && !line.is_empty() && !line.ends_with('\n') {
+            line.push(' ');
+        }
+        line.push_str(word);
+        space = true;
+    }
+}
+
+/// Split the content into sections by `■` headings.
+pub fn get_sections(content: &NodeRef) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut current = Section {
+        heading: None,
+        lines: Vec::new(),
+    };
+
+    for line in get_lines(content) {
+        match line.strip_prefix('■') {
+            Some(heading) => {
+                let section = Section {
+                    heading: Some(heading.trim().to_string()),
+                    lines: Vec::new(),
+                };
+                sections.push(std::mem::replace(&mut current, section));
+            }
+            None => current.lines.push(line),
+        }
+    }
+    sections.push(current);
+
+    sections.retain(|section| section.heading.is_some() || !section.lines.is_empty());
+    sections
+}
+
+/// Summarize `sections` in at most `limit` characters.
+///
+/// Bullets are added in order until the next one doesn't fit. Posts without
+/// headings are summarized by their first line.
+pub fn summarize(sections: &[Section], limit: usize) -> Option<String> {
+    if limit == 0 {
+        return None;
+    }
+
+    let bullets: Vec<String> = sections.iter().filter_map(Section::bullet).collect();
+    if bullets.is_empty() {
+        let line = sections.first()?.lines.first()?;
+        return Some(truncate(line, limit));
+    }
+
+    let mut summary = String::new();
+    let mut length = 0;
+    for bullet in bullets {
+        let added = bullet.chars().count() + usize::from(length > 0);
+        if length + added > limit {
+            break;
+        }
+        if length > 0 {
+            summary.push('\n');
+        }
+        summary.push_str(&bullet);
+        length += added;
+    }
+
+    (!summary.is_empty()).then_some(summary)
+}
+
+/// Cut `text` to `limit` characters, ending with `…` if cut.
+pub fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let mut text: String = text.chars().take(limit.saturating_sub(1)).collect();
+    text.push('…');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{insight::AnnouncementPage, resource::news::NewsPage, Page};
+
+    fn sections(path: &str) -> Vec<Section> {
+        let html = std::fs::read_to_string(path).unwrap();
+        let page = NewsPage::from_html(html).unwrap();
+        get_sections(&page.content())
+    }
+
+    #[test]
+    fn test_get_sections() {
+        let sections = sections("tests/news_page.html");
+        let headings: Vec<_> = sections
+            .iter()
+            .filter_map(|section| section.heading.as_deref())
+            .collect();
+        assert_eq!(
+            headings,
+            vec![
+                "角色交換Pt共通的轉蛋",
+                "精選轉蛋舉辦期間",
+                "可獲得贈品的對象角色",
+                "贈品內容",
+                "注意事項"
+            ]
+        );
+        assert!(sections[0].heading.is_none());
+        assert_eq!(sections[3].lines, vec!["克蘿依（聖學祭）"]);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summary = summarize(&sections("tests/news_page.html"), 500).unwrap();
+        assert_eq!(
+            summary,
+            "・角色交換Pt共通的轉蛋：白金轉蛋、新手衝刺轉蛋、★3必中白金轉蛋\n\
+             ・可獲得贈品的對象角色：克蘿依（聖學祭）\n\
+             ・贈品內容：克蘿依（聖學祭）的記憶碎片×100"
+        );
+
+        // Only the bullets that fit
+        let summary = summarize(&sections("tests/news_page.html"), 50).unwrap();
+        assert_eq!(
+            summary,
+            "・角色交換Pt共通的轉蛋：白金轉蛋、新手衝刺轉蛋、★3必中白金轉蛋"
+        );
+        assert_eq!(summarize(&sections("tests/news_page.html"), 10), None);
+
+        let summary = summarize(&sections("tests/news_1376.html"), 500).unwrap();
+        let bullets: Vec<_> = summary.lines().collect();
+        assert!(bullets[0].starts_with("・活動內容："));
+        assert!(bullets[2].starts_with("・活動獎勵：《戰隊限定自選箱》、《公主紫裝自選箱A》"));
+        assert!(bullets
+            .iter()
+            .all(|bullet| bullet.chars().count() <= ITEM_LIMIT));
+        assert!(!summary.contains("注意事項"));
+        assert!(!summary.contains("舉辦期間"));
+    }
+
+    #[test]
+    fn test_summarize_without_headings() {
+        let sections = vec![Section {
+            heading: None,
+            lines: vec!["感謝各位騎士君的支持。".to_string()],
+        }];
+        assert_eq!(
+            summarize(&sections, 100).as_deref(),
+            Some("感謝各位騎士君的支持。")
+        );
+        assert_eq!(summarize(&sections, 5).as_deref(), Some("感謝各位…"));
+    }
+}
@@ -0,0 +1,282 @@
+//! Database integrity check
+//!
+//! Writes to different collections are not atomic, so an interrupted run may
+//! leave records pointing to nothing. [`check`] finds them, and repairs them
+//! when asked.
+
+use std::{collections::HashSet, fmt::Display};
+
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+    database::Storage,
+    resource::{
+        announcement::sources::AnnouncementSource, cartoon::Thumbnail, information::Announce,
+        news::News, ResourceId, ResourceKind, ResourceMetadata,
+    },
+    Error, PriconneService, Result,
+};
+
+/// A problem found in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// Messages were sent for a resource that doesn't exist.
+    /// Repaired by deleting the messages.
+    DanglingMessage(ResourceId),
+    /// Metadata was saved, but not the announcement of it.
+    /// Repaired by deleting the metadata, so that it is fetched again.
+    OrphanMetadata {
+        kind: ResourceKind,
+        source: AnnouncementSource,
+        id: i32,
+        title: String,
+    },
+    /// Events of the announcement differ from those of its latest insight.
+    /// Repaired by copying events from the insight.
+    StaleEvents { id: ObjectId, title: String },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::DanglingMessage(id) => write!(f, "dangling messages of {id:?}"),
+            Issue::OrphanMetadata {
+                kind,
+                source,
+                id,
+                title,
+            } => write!(
+                f,
+                "{kind} metadata without announcement: ({source} #{id}) {title}"
+            ),
+            Issue::StaleEvents { id, title } => {
+                write!(f, "events differ from the latest insight: {id} {title}")
+            }
+        }
+    }
+}
+
+/// Result of [`check`].
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub issues: Vec<Issue>,
+    /// Whether the issues are repaired
+    pub repaired: bool,
+}
+
+impl Display for IntegrityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{issue}")?;
+        }
+        let action = if self.repaired { "repaired" } else { "found" };
+        write!(f, "{} issue(s) {action}", self.issues.len())
+    }
+}
+
+/// Metadata collections holding announcements, with the source of them.
+pub type AnnouncementMetadata = (ResourceKind, AnnouncementSource);
+
+/// Check `storage`, and repair the issues if `repair` is true.
+///
+/// `sources` lists which source each announcement metadata collection is fetched from.
+pub async fn check(
+    storage: &Storage,
+    sources: &[AnnouncementMetadata],
+    repair: bool,
+) -> Result<IntegrityReport> {
+    let announcements = storage.announcements()?;
+    let all = announcements.all().await?;
+    let ids: HashSet<ObjectId> = all.iter().map(|post| post.id).collect();
+    let fetched: HashSet<(AnnouncementSource, i32)> = all
+        .iter()
+        .flat_map(|post| post.data.iter().map(|data| (data.source.clone(), data.id)))
+        .collect();
+
+    let mut issues = Vec::new();
+
+    let messages = storage.messages()?;
+    let cartoons = storage.metadata::<Thumbnail>(&ResourceKind::Cartoon.to_string())?;
+    let mut checked = HashSet::new();
+    for message in messages.all().await? {
+        let id = message.resource_id;
+        if !checked.insert(id.clone()) {
+            continue;
+        }
+        let exists = match &id {
+            ResourceId::Announcement(id) => ids.contains(id),
+            ResourceId::Cartoon(id) => cartoons.find_by_id(*id).await?.is_some(),
+        };
+        if !exists {
+            if repair {
+                messages.delete_by_resource(&id).await?;
+            }
+            issues.push(Issue::DanglingMessage(id));
+        }
+    }
+
+    for (kind, source) in sources {
+        let orphans = match kind {
+            ResourceKind::Information => {
+                orphan_metadata::<Announce>(storage, kind, source, &fetched, repair).await?
+            }
+            ResourceKind::News => {
+                orphan_metadata::<News>(storage, kind, source, &fetched, repair).await?
+            }
+            _ => return Err(Error::ParseResourceKindsError(format!("{kind:?}"))),
+        };
+        issues.extend(orphans);
+    }
+
+    for mut post in all {
+        let Some(data) = post.data.last() else {
+            continue;
+        };
+        if data.events == post.events {
+            continue;
+        }
+        issues.push(Issue::StaleEvents {
+            id: post.id,
+            title: data.title.clone(),
+        });
+        if repair {
+            post.events = data.events.clone();
+            announcements.upsert(&post).await?;
+        }
+    }
+
+    Ok(IntegrityReport {
+        issues,
+        repaired: repair,
+    })
+}
+
+async fn orphan_metadata<M: ResourceMetadata + 'static>(
+    storage: &Storage,
+    kind: &ResourceKind,
+    source: &AnnouncementSource,
+    fetched: &HashSet<(AnnouncementSource, i32)>,
+    repair: bool,
+) -> Result<Vec<Issue>> {
+    let repository = storage.metadata::<M>(&kind.to_string())?;
+
+    let mut issues = Vec::new();
+    for metadata in repository.all().await? {
+        if fetched.contains(&(source.clone(), metadata.id())) {
+            continue;
+        }
+        if repair {
+            repository.delete(metadata.id()).await?;
+        }
+        issues.push(Issue::OrphanMetadata {
+            kind: kind.clone(),
+            source: source.clone(),
+            id: metadata.id(),
+            title: metadata.title().to_string(),
+        });
+    }
+    Ok(issues)
+}
+
+impl PriconneService {
+    /// Check the database, and repair the issues if `repair` is true.
+    pub async fn check_database(&self, repair: bool) -> Result<IntegrityReport> {
+        let api = self.config.server.api.first().ok_or(Error::NoApiServer)?;
+        let sources = [
+            (
+                ResourceKind::Information,
+                AnnouncementSource::Api(api.id.clone()),
+            ),
+            (ResourceKind::News, AnnouncementSource::Website),
+        ];
+        check(&self.storage, &sources, repair).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::{
+        chat::SendResult,
+        database::{
+            tests::{insight, news},
+            EmbeddedStore,
+        },
+        resource::Announcement,
+    };
+
+    fn message(resource_id: ResourceId) -> SendResult {
+        SendResult {
+            url: None,
+            backend: Default::default(),
+            recipient: None,
+            chat_id: None,
+            message_id: None,
+            remote_id: None,
+            resource_id,
+            update_time: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check() -> Result<()> {
+        let storage = Storage::Embedded(EmbeddedStore::in_memory());
+        let sources = [(ResourceKind::News, AnnouncementSource::Website)];
+        let end = Utc.with_ymd_and_hms(2023, 1, 10, 0, 0, 0).unwrap();
+
+        let fine = Announcement::new(insight(AnnouncementSource::Website, 1, "正常", end), None);
+        let mut stale = Announcement::new(
+            insight(AnnouncementSource::Website, 2, "活動延長", end),
+            None,
+        );
+        stale.data[0].events[0].end = end + Duration::days(3);
+        let announcements = storage.announcements()?;
+        announcements.upsert(&fine).await?;
+        announcements.upsert(&stale).await?;
+
+        let metadata = storage.metadata::<News>("news")?;
+        for (id, title) in [(1, "正常"), (2, "活動延長"), (3, "中斷")] {
+            metadata.upsert(&news(id, title)).await?;
+        }
+
+        let missing = ResourceId::Announcement(ObjectId::new());
+        let messages = storage.messages()?;
+        messages
+            .insert(&message(ResourceId::Announcement(fine.id)))
+            .await?;
+        messages.insert(&message(missing.clone())).await?;
+        messages.insert(&message(missing.clone())).await?;
+
+        let report = check(&storage, &sources, false).await?;
+        assert!(!report.repaired);
+        assert_eq!(
+            report.issues,
+            vec![
+                Issue::DanglingMessage(missing),
+                Issue::OrphanMetadata {
+                    kind: ResourceKind::News,
+                    source: AnnouncementSource::Website,
+                    id: 3,
+                    title: "中斷".to_string(),
+                },
+                Issue::StaleEvents {
+                    id: stale.id,
+                    title: "活動延長".to_string(),
+                },
+            ]
+        );
+        assert!(report.to_string().ends_with("3 issue(s) found"));
+
+        let report = check(&storage, &sources, true).await?;
+        assert_eq!(report.issues.len(), 3);
+        assert!(check(&storage, &sources, false).await?.issues.is_empty());
+
+        assert_eq!(messages.all().await?.len(), 1);
+        assert!(metadata.find_by_id(3).await?.is_none());
+        let repaired = announcements.find_by_id(stale.id).await?.unwrap();
+        assert_eq!(repaired.events[0].end, end + Duration::days(3));
+        Ok(())
+    }
+}
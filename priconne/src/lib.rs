@@ -5,13 +5,22 @@ mod error;
 mod page;
 mod utils;
 
+pub mod api;
+pub mod archive;
 pub mod database;
 
 pub mod chat;
 pub mod client;
 pub mod config;
+pub mod digest;
+pub mod image;
 pub mod insight;
+pub mod integrity;
+pub mod maintenance;
+pub mod reminder;
+pub mod search;
 pub mod service;
+pub mod template;
 
 pub mod resource;
 
@@ -1,10 +1,14 @@
-use std::{pin::Pin, sync::Arc};
+use std::{path::PathBuf, pin::Pin, sync::Arc};
 
 use axum::{routing::get, Router};
 
 use clap::{Parser, Subcommand};
 use futures::future::Either;
-use priconne::config::PriconneConfig;
+use mongodb::bson::oid::ObjectId;
+use priconne::{
+    client::TransportConfig, config::PriconneConfig, resource::ResourceKind,
+    template::TemplateSource,
+};
 use schemars::schema_for;
 use teloxide::prelude::LoggingErrorHandler;
 use tokio_cron_scheduler::JobScheduler;
@@ -25,6 +29,54 @@ enum Commands {
     Serve,
     /// Incoming events
     Events,
+    /// Run a resource service once
+    Run {
+        /// Resource kind, like `news`
+        kind: String,
+        /// Record responses to this cassette directory
+        #[arg(long, conflicts_with = "replay")]
+        record: Option<PathBuf>,
+        /// Replay responses from this cassette directory
+        #[arg(long)]
+        replay: Option<PathBuf>,
+        /// Run the pipeline without publishing or writing to the database
+        #[arg(long)]
+        dry_run: bool,
+        /// Write dry run previews to this directory, implies `--dry-run`
+        #[arg(long)]
+        preview: Option<PathBuf>,
+    },
+    /// Re-extract tags and events from archived pages
+    Reparse {
+        /// Write the results back instead of only showing the difference
+        #[arg(long)]
+        write: bool,
+    },
+    /// Rebuild the full-text search index from archived pages
+    RebuildIndex,
+    /// Upgrade stored documents to the latest schema
+    Migrate {
+        /// Only show how many documents need migration
+        #[arg(long)]
+        status: bool,
+        /// Upgrade documents in memory without writing them back
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check the database for dangling or inconsistent records
+    CheckDb {
+        /// Repair the issues found
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Preview the message of a stored announcement
+    Render {
+        /// Announcement ID
+        id: String,
+        /// Render with this template instead of the configured one
+        #[arg(long)]
+        template: Option<PathBuf>,
+    },
 }
 
 fn init_logging() {
@@ -49,22 +101,95 @@ async fn main() -> priconne::Result<()> {
             }
             Commands::Serve => serve().await?,
             Commands::Events => {
-                let config = std::fs::File::open("config.yaml")?;
-                let config: PriconneConfig = serde_yaml::from_reader(config)?;
-                let priconne = config.build().await?;
+                let priconne = load_config()?.build().await?;
                 let events = priconne.incomming_events().await?;
                 println!("{:#?}", events);
             }
+            Commands::Run {
+                kind,
+                record,
+                replay,
+                dry_run,
+                preview,
+            } => {
+                let kind: ResourceKind = kind.parse()?;
+                let mut config = load_config()?;
+                if let Some(dir) = record {
+                    config.fetch.transport = TransportConfig::Record(dir);
+                }
+                if let Some(dir) = replay {
+                    config.fetch.transport = TransportConfig::Replay(dir);
+                }
+                if dry_run || preview.is_some() {
+                    config.fetch.dry_run = true;
+                    config.fetch.preview_dir = preview.or(config.fetch.preview_dir);
+                }
+                let priconne = config.build().await?;
+                priconne.storage.ensure_migrated().await?;
+                priconne.run_service(kind).await?;
+            }
+            Commands::Reparse { write } => {
+                let priconne = load_config()?.build().await?;
+                let diffs = priconne.reparse(write).await?;
+                for diff in &diffs {
+                    println!("{diff}");
+                }
+                println!("{} announcement(s) changed", diffs.len());
+            }
+            Commands::RebuildIndex => {
+                let priconne = load_config()?.build().await?;
+                let count = priconne.rebuild_search_index().await?;
+                println!("{count} page(s) indexed");
+            }
+            Commands::Migrate { status, dry_run } => {
+                let storage = load_config()?.build_storage().await?;
+                let statuses = match status {
+                    true => storage.migration_status().await?,
+                    false => storage.migrate(dry_run).await?,
+                };
+                for status in &statuses {
+                    println!("{status}");
+                }
+                let pending: usize = statuses.iter().map(|status| status.pending).sum();
+                match (status, dry_run) {
+                    (true, _) => println!("{pending} document(s) pending"),
+                    (false, true) => println!("{pending} document(s) would be migrated"),
+                    (false, false) => println!("{pending} document(s) migrated"),
+                }
+            }
+            Commands::CheckDb { repair } => {
+                let priconne = load_config()?.build().await?;
+                let report = priconne.check_database(repair).await?;
+                println!("{report}");
+            }
+            Commands::Render { id, template } => {
+                let mut config = load_config()?;
+                if let Some(file) = template {
+                    config.templates.announcement = Some(TemplateSource::File { file });
+                }
+                let priconne = config.build().await?;
+                let id = ObjectId::parse_str(&id)?;
+                match priconne.render_announcement(id).await? {
+                    Some(message) => println!("{}", message.text),
+                    None => println!("no announcement {id}"),
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-async fn serve() -> priconne::Result<()> {
+fn load_config() -> priconne::Result<PriconneConfig> {
     let config = std::fs::File::open("config.yaml")?;
-    let config: PriconneConfig = serde_yaml::from_reader(config)?;
+    Ok(serde_yaml::from_reader(config)?)
+}
+
+async fn serve() -> priconne::Result<()> {
+    let config = load_config()?;
     let priconne = Arc::new(config.build().await?);
+    priconne.storage.ensure_migrated().await?;
+    config.housekeep(&priconne.storage).await?;
 
     let mut dispatcher = priconne::chat::dispatcher(&priconne, &priconne.chat_manager.bot);
 
@@ -101,6 +226,7 @@ async fn serve() -> priconne::Result<()> {
         None => Either::Right(dispatcher.dispatch()),
     };
 
+    let app = app.merge(priconne::api::router(priconne.clone()));
     let server = axum::Server::bind(&"127.0.0.1:5555".parse()?)
         .serve(app.into_make_service())
         .with_graceful_shutdown(stop_flag);
@@ -0,0 +1,428 @@
+//! Maintenance tracking
+//!
+//! Announcements tagged as maintenance, like those with [`Icon::Maintaince`]
+//! or tagged `停機維護`, are tracked with their maintenance window when they
+//! are saved. A scheduled job, configured as `maintenance` in
+//! [`FetchConfig::schedule`](crate::config::FetchConfig::schedule), posts a
+//! notice when the maintenance starts and when it ends. Notices are late by up
+//! to the interval of the job.
+//!
+//! While a maintenance is running, the job also fetches information from the
+//! API, so an updated announcement that extends the window is noticed as soon
+//! as the job runs. The job does not poll more often by itself, so schedule it
+//! more often than `information` for that.
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ParseMode, Recipient},
+};
+
+use crate::{
+    chat::{MessageText, PublisherKind},
+    config::RemoteRecipient,
+    resource::{information::Icon, Announcement, ResourceId, ResourceKind},
+    utils::{format_time, Tz},
+    Error, PriconneService, Result,
+};
+
+/// Where notices are posted, and which announcements are maintenance.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MaintenanceConfig {
+    /// Chat to post notices
+    #[schemars(with = "RemoteRecipient")]
+    pub recipient: Recipient,
+    /// Tags of maintenance announcements
+    #[serde(default = "default_tags")]
+    pub tags: Vec<String>,
+    /// Keywords in titles of the maintenance window, the first event is used
+    /// if none matches
+    #[serde(default = "default_keywords")]
+    pub keywords: Vec<String>,
+}
+
+fn default_tags() -> Vec<String> {
+    vec![
+        "停機維護".to_string(),
+        Icon::Maintaince.to_tag().to_string(),
+    ]
+}
+
+fn default_keywords() -> Vec<String> {
+    vec!["維護".to_string()]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceStatus {
+    Scheduled,
+    Ongoing,
+    Ended,
+}
+
+/// A maintenance window, stored with the id of its announcement.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Maintenance {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub title: String,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub start: DateTime<Utc>,
+    /// Expected end, moved when the announcement is updated
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub end: DateTime<Utc>,
+    pub status: MaintenanceStatus,
+}
+
+/// A change of a maintenance worth telling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceNotice {
+    Started,
+    Extended { previous_end: DateTime<Utc> },
+    Ended,
+}
+
+impl Maintenance {
+    /// The maintenance window of the announcement, if it is a maintenance
+    /// announcement with any event. Windows without an end, as in `維護後`,
+    /// are not tracked, since they would end as soon as they start.
+    pub fn from_announcement(
+        announcement: &Announcement,
+        config: &MaintenanceConfig,
+    ) -> Option<Self> {
+        let data = announcement.data.last()?;
+        if !config.tags.iter().any(|tag| data.tags.contains(tag)) {
+            return None;
+        }
+
+        let events = &announcement.events;
+        let window = events
+            .iter()
+            .find(|event| {
+                config
+                    .keywords
+                    .iter()
+                    .any(|keyword| event.title.contains(keyword))
+            })
+            .or_else(|| events.first())
+            .filter(|window| window.has_end())?;
+        Some(Self {
+            id: announcement.id,
+            title: data.title.clone(),
+            start: window.start,
+            end: window.end,
+            status: MaintenanceStatus::Scheduled,
+        })
+    }
+
+    /// Take the window of an updated announcement. A running or ended
+    /// maintenance ending later than expected is extended.
+    pub fn update(&mut self, updated: &Self, now: DateTime<Utc>) -> Option<MaintenanceNotice> {
+        self.title.clone_from(&updated.title);
+        if self.status == MaintenanceStatus::Scheduled {
+            self.start = updated.start;
+        }
+
+        let previous_end = self.end;
+        self.end = updated.end;
+        match self.status {
+            MaintenanceStatus::Ongoing if updated.end > previous_end => {
+                Some(MaintenanceNotice::Extended { previous_end })
+            }
+            MaintenanceStatus::Ended if updated.end > now => {
+                self.status = MaintenanceStatus::Ongoing;
+                Some(MaintenanceNotice::Extended { previous_end })
+            }
+            _ => None,
+        }
+    }
+
+    /// Move to the status at `now`.
+    pub fn advance(&mut self, now: DateTime<Utc>) -> Option<MaintenanceNotice> {
+        let (status, notice) = match self.status {
+            // Missed the whole window, as when the bot is down
+            MaintenanceStatus::Scheduled if self.end <= now => {
+                (MaintenanceStatus::Ended, MaintenanceNotice::Ended)
+            }
+            MaintenanceStatus::Scheduled if self.start <= now => {
+                (MaintenanceStatus::Ongoing, MaintenanceNotice::Started)
+            }
+            MaintenanceStatus::Ongoing if self.end <= now => {
+                (MaintenanceStatus::Ended, MaintenanceNotice::Ended)
+            }
+            _ => return None,
+        };
+        self.status = status;
+        Some(notice)
+    }
+}
+
+impl MaintenanceNotice {
+    /// Notice text in Telegram HTML, linking to `url` if any.
+    pub fn text(&self, maintenance: &Maintenance, url: Option<&str>, zone: Tz) -> String {
+        let mut message = MessageText::default();
+        let heading = match self {
+            MaintenanceNotice::Started => "🔧 維護開始",
+            MaintenanceNotice::Extended { .. } => "⏳ 維護延長",
+            MaintenanceNotice::Ended => "✅ 維護結束",
+        };
+        message.bold(heading).text("\n");
+        match url {
+            Some(url) => message.link(&maintenance.title, url),
+            None => message.text(&maintenance.title),
+        };
+
+        match self {
+            MaintenanceNotice::Started => {
+                message.text(format!(
+                    "\n預計結束：{}",
+                    format_time(maintenance.end, zone)
+                ));
+            }
+            MaintenanceNotice::Extended { previous_end } => {
+                message.text(format!(
+                    "\n預計結束：{} → {}",
+                    format_time(*previous_end, zone),
+                    format_time(maintenance.end, zone)
+                ));
+            }
+            MaintenanceNotice::Ended => {}
+        }
+        message.render()
+    }
+}
+
+impl PriconneService {
+    /// Track the maintenance window of a saved announcement, and post a notice
+    /// if it is extended.
+    pub async fn track_maintenance(&self, announcement: &Announcement) -> Result<()> {
+        let Some(config) = &self.chat_manager.config.maintenance else {
+            return Ok(());
+        };
+        let Some(updated) = Maintenance::from_announcement(announcement, config) else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        let repository = self.storage.maintenance()?;
+        let (maintenance, notice) = match repository.find_by_id(updated.id).await? {
+            Some(mut maintenance) => {
+                let notice = maintenance.update(&updated, now);
+                (maintenance, notice)
+            }
+            // Announced after the window, nothing to tell
+            None if updated.end <= now => return Ok(()),
+            None => (updated, None),
+        };
+        // Saved first, so a failed notice is not sent again for the same update
+        repository.upsert(&maintenance).await?;
+        if let Some(notice) = notice {
+            self.send_maintenance_notice(config, &maintenance, notice)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Post notices of maintenance that started or ended. While any
+    /// maintenance is running, information is fetched first, so that an
+    /// extension is not taken as the end.
+    pub async fn check_maintenance(&self) -> Result<()> {
+        let config = self
+            .chat_manager
+            .config
+            .maintenance
+            .as_ref()
+            .ok_or(Error::MissingConfigError("telegram.maintenance"))?;
+        let repository = self.storage.maintenance()?;
+
+        let now = Utc::now();
+        let unfinished = repository.unfinished().await?;
+        if unfinished
+            .iter()
+            .any(|maintenance| maintenance.start <= now)
+        {
+            if let Err(e) = self.run_service(ResourceKind::Information).await {
+                tracing::warn!("failed to fetch information during maintenance: {e}");
+            }
+        }
+
+        let now = Utc::now();
+        for mut maintenance in repository.unfinished().await? {
+            let Some(notice) = maintenance.advance(now) else {
+                continue;
+            };
+            self.send_maintenance_notice(config, &maintenance, notice)
+                .await?;
+            repository.upsert(&maintenance).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_maintenance_notice(
+        &self,
+        config: &MaintenanceConfig,
+        maintenance: &Maintenance,
+        notice: MaintenanceNotice,
+    ) -> Result<()> {
+        tracing::info!("maintenance {}: {notice:?}", maintenance.title);
+        let url = self
+            .chat_manager
+            .messages
+            .find_by_resource(&ResourceId::Announcement(maintenance.id))
+            .await?
+            .into_iter()
+            .filter(|result| result.backend == PublisherKind::Telegram)
+            .find_map(|result| result.url);
+        let zone = self.chat_manager.config.recipient.time_zone;
+        let text = notice.text(maintenance, url.as_ref().map(|url| url.as_str()), zone);
+
+        self.chat_manager
+            .bot
+            .send_message(config.recipient.clone(), text)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::{
+        database::tests::insight, insight::PeriodPattern, resource::sources::AnnouncementSource,
+    };
+
+    fn config() -> MaintenanceConfig {
+        serde_yaml::from_str("recipient: '@channel'").unwrap()
+    }
+
+    fn time(hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 1, 1, hour, min, 0).unwrap()
+    }
+
+    fn tagged(tag: &str, end: DateTime<Utc>) -> Announcement {
+        let mut insight = insight(AnnouncementSource::Website, 1, "【維護】停機維護", end);
+        insight.tags.insert(tag.to_string());
+        Announcement::new(insight, None)
+    }
+
+    #[test]
+    fn test_from_announcement() {
+        let config = config();
+        let announcement = tagged("停機維護", time(8, 0));
+        let maintenance = Maintenance::from_announcement(&announcement, &config).unwrap();
+        assert_eq!(maintenance.id, announcement.id);
+        assert_eq!(maintenance.title, "【維護】停機維護");
+        assert_eq!(maintenance.start, time(8, 0) - Duration::days(7));
+        assert_eq!(maintenance.end, time(8, 0));
+        assert_eq!(maintenance.status, MaintenanceStatus::Scheduled);
+
+        // Tagged by the icon of information
+        let announcement = tagged(Icon::Maintaince.to_tag(), time(8, 0));
+        assert!(Maintenance::from_announcement(&announcement, &config).is_some());
+
+        let announcement = tagged("活動", time(8, 0));
+        assert!(Maintenance::from_announcement(&announcement, &config).is_none());
+
+        // Without an end
+        let mut announcement = tagged("停機維護", time(8, 0));
+        announcement.events[0].start = time(8, 0);
+        announcement.events[0].pattern = Some(PeriodPattern::Since);
+        assert!(Maintenance::from_announcement(&announcement, &config).is_none());
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut maintenance = Maintenance {
+            id: ObjectId::new(),
+            title: "停機維護".to_string(),
+            start: time(3, 0),
+            end: time(8, 0),
+            status: MaintenanceStatus::Scheduled,
+        };
+
+        assert_eq!(maintenance.advance(time(2, 59)), None);
+        assert_eq!(
+            maintenance.advance(time(3, 0)),
+            Some(MaintenanceNotice::Started)
+        );
+        assert_eq!(maintenance.advance(time(3, 1)), None);
+
+        // Extended by an updated announcement
+        let updated = Maintenance {
+            end: time(10, 0),
+            ..maintenance.clone()
+        };
+        assert_eq!(
+            maintenance.update(&updated, time(7, 0)),
+            Some(MaintenanceNotice::Extended {
+                previous_end: time(8, 0)
+            })
+        );
+        assert_eq!(maintenance.advance(time(8, 0)), None);
+        assert_eq!(maintenance.update(&updated, time(8, 0)), None);
+
+        assert_eq!(
+            maintenance.advance(time(10, 0)),
+            Some(MaintenanceNotice::Ended)
+        );
+        assert_eq!(maintenance.status, MaintenanceStatus::Ended);
+
+        // Extended after it ended
+        let updated = Maintenance {
+            end: time(12, 0),
+            ..maintenance.clone()
+        };
+        assert_eq!(
+            maintenance.update(&updated, time(10, 30)),
+            Some(MaintenanceNotice::Extended {
+                previous_end: time(10, 0)
+            })
+        );
+        assert_eq!(maintenance.status, MaintenanceStatus::Ongoing);
+
+        // Missed the whole window
+        let mut missed = Maintenance {
+            status: MaintenanceStatus::Scheduled,
+            ..maintenance.clone()
+        };
+        assert_eq!(missed.advance(time(13, 0)), Some(MaintenanceNotice::Ended));
+    }
+
+    #[test]
+    fn test_text() {
+        let maintenance = Maintenance {
+            id: ObjectId::new(),
+            title: "<停機維護>".to_string(),
+            start: time(3, 0),
+            end: time(8, 0),
+            status: MaintenanceStatus::Ongoing,
+        };
+        let zone = crate::utils::DEFAULT_TIME_ZONE;
+
+        assert_eq!(
+            MaintenanceNotice::Started.text(&maintenance, Some("https://t.me/c/1"), zone),
+            "<b>🔧 維護開始</b>\n<a href=\"https://t.me/c/1\">&lt;停機維護&gt;</a>\n\
+             預計結束：2023/01/01 16:00 (UTC+08:00)"
+        );
+        let notice = MaintenanceNotice::Extended {
+            previous_end: time(7, 0),
+        };
+        assert_eq!(
+            notice.text(&maintenance, None, zone),
+            "<b>⏳ 維護延長</b>\n&lt;停機維護&gt;\n\
+             預計結束：2023/01/01 15:00 (UTC+08:00) → 2023/01/01 16:00 (UTC+08:00)"
+        );
+        assert_eq!(
+            MaintenanceNotice::Ended.text(&maintenance, None, zone),
+            "<b>✅ 維護結束</b>\n&lt;停機維護&gt;"
+        );
+    }
+}
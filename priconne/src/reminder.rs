@@ -0,0 +1,438 @@
+//! Event reminders
+//!
+//! For every event of an announcement, a one-shot job is added to the
+//! [`JobScheduler`] at each configured offset before the event starts or ends.
+//! The reminder is sent as a reply to the messages of the announcement, in
+//! channels or in direct messages to [subscribers](crate::chat::subscription).
+//!
+//! Jobs only live in memory. They are rebuilt from stored events when the
+//! scheduler starts, and replaced whenever an announcement is updated, so that
+//! a moved event period moves its reminders too.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    sync::{Arc, OnceLock},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::bson::oid::ObjectId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, MessageId, ParseMode},
+    utils::html::escape,
+};
+use tokio::sync::Mutex;
+use tokio_cron_scheduler::{Job, JobScheduler};
+use uuid::Uuid;
+
+use crate::{
+    chat::{ChatManager, PublisherKind, Sendable},
+    database::Storage,
+    insight::EventInAnnouncement,
+    resource::{Announcement, ResourceId},
+    Result,
+};
+
+/// Interval between direct messages, same as subscription notifications.
+const MESSAGE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Boundary of an event period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EventBoundary {
+    Start,
+    End,
+}
+
+/// Who receives a reminder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReminderTarget {
+    /// Channels and groups the announcement was sent to
+    Channel,
+    /// Users subscribing to the announcement
+    Subscribers,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ReminderConfig {
+    /// Remind before the event starts or ends
+    pub at: EventBoundary,
+    /// How long before, like `30m`, `1h` or `1d12h`
+    #[serde(
+        serialize_with = "serialize_offset",
+        deserialize_with = "deserialize_offset"
+    )]
+    #[schemars(with = "String")]
+    pub before: Duration,
+    /// Who to remind
+    #[serde(default = "default_targets")]
+    pub targets: Vec<ReminderTarget>,
+}
+
+fn default_targets() -> Vec<ReminderTarget> {
+    vec![ReminderTarget::Channel]
+}
+
+/// Parse an offset like `1h30m`, with units `d`, `h`, `m` and `s`.
+pub fn parse_offset(s: &str) -> Option<Duration> {
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: i64 = number.parse().ok()?;
+        number.clear();
+        total += match c {
+            'd' => Duration::days(n),
+            'h' => Duration::hours(n),
+            'm' => Duration::minutes(n),
+            's' => Duration::seconds(n),
+            _ => return None,
+        };
+    }
+    // A trailing number without unit
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+/// Format an offset as parsed by [`parse_offset`].
+pub fn format_offset(offset: Duration) -> String {
+    let mut seconds = offset.num_seconds();
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let mut s = String::new();
+    for (unit, size) in [('d', 86400), ('h', 3600), ('m', 60), ('s', 1)] {
+        if seconds >= size {
+            s.push_str(&format!("{}{unit}", seconds / size));
+            seconds %= size;
+        }
+    }
+    s
+}
+
+fn serialize_offset<S: Serializer>(
+    offset: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_offset(*offset))
+}
+
+fn deserialize_offset<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Duration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_offset(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid offset `{s}`")))
+}
+
+/// A reminder of an event, to be sent at `time`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reminder {
+    pub announcement_id: ObjectId,
+    pub event: EventInAnnouncement,
+    pub config: ReminderConfig,
+    pub time: DateTime<Utc>,
+}
+
+impl Reminder {
+    /// Reminders of all events in the announcement that are still due after `now`.
+    pub fn for_announcement(
+        announcement: &Announcement,
+        configs: &[ReminderConfig],
+        now: DateTime<Utc>,
+    ) -> Vec<Reminder> {
+        let mut reminders = Vec::new();
+        for event in &announcement.events {
+            for config in configs {
+                let boundary = match config.at {
+                    EventBoundary::Start => event.start,
+                    EventBoundary::End if event.has_end() => event.end,
+                    EventBoundary::End => continue,
+                };
+                let time = boundary - config.before;
+                if time > now {
+                    reminders.push(Reminder {
+                        announcement_id: announcement.id,
+                        event: event.clone(),
+                        config: config.clone(),
+                        time,
+                    });
+                }
+            }
+        }
+        reminders
+    }
+
+    /// Reminder text in Telegram HTML.
+    pub fn text(&self, announcement_title: &str) -> String {
+        let verb = match self.config.at {
+            EventBoundary::Start => "starts",
+            EventBoundary::End => "ends",
+        };
+        format!(
+            "⏰ <b>{}</b> {verb} in {}\n{}",
+            escape(&self.event.title),
+            format_offset(self.config.before),
+            escape(announcement_title)
+        )
+    }
+}
+
+impl Display for Reminder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} of {} in {}",
+            self.config.at,
+            self.event.title,
+            format_offset(self.config.before)
+        )
+    }
+}
+
+/// Schedules [`Reminder`]s as one-shot jobs.
+///
+/// Before [`start`](Self::start) is called, as in one-off runs, nothing is scheduled.
+pub struct ReminderScheduler {
+    configs: Vec<ReminderConfig>,
+    storage: Storage,
+    chat_manager: Arc<ChatManager>,
+    scheduler: OnceLock<JobScheduler>,
+    /// Jobs of each announcement
+    jobs: Mutex<HashMap<ObjectId, Vec<Uuid>>>,
+}
+
+impl ReminderScheduler {
+    pub fn new(
+        configs: Vec<ReminderConfig>,
+        storage: Storage,
+        chat_manager: Arc<ChatManager>,
+    ) -> Self {
+        Self {
+            configs,
+            storage,
+            chat_manager,
+            scheduler: OnceLock::new(),
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add jobs to `scheduler`, starting with reminders of stored events.
+    pub async fn start(self: &Arc<Self>, scheduler: JobScheduler) -> Result<()> {
+        if self.configs.is_empty() || self.scheduler.set(scheduler).is_err() {
+            return Ok(());
+        }
+
+        let events = self.storage.events()?.events_end_after(Utc::now()).await?;
+        let ids: BTreeSet<ObjectId> = events.iter().map(|e| e.announcement_id).collect();
+
+        let announcements = self.storage.announcements()?;
+        for id in ids {
+            if let Some(announcement) = announcements.find_by_id(id).await? {
+                self.schedule(&announcement).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the reminders of the announcement.
+    pub async fn schedule(self: &Arc<Self>, announcement: &Announcement) -> Result<()> {
+        let Some(scheduler) = self.scheduler.get() else {
+            return Ok(());
+        };
+
+        let mut jobs = self.jobs.lock().await;
+        for uuid in jobs.remove(&announcement.id).unwrap_or_default() {
+            scheduler.remove(&uuid).await?;
+        }
+
+        let now = Utc::now();
+        let mut uuids = Vec::new();
+        for reminder in Reminder::for_announcement(announcement, &self.configs, now) {
+            tracing::debug!("schedule reminder {reminder} at {}", reminder.time);
+            let delay = (reminder.time - now).to_std().unwrap_or_default();
+            let this = self.clone();
+            let job = Job::new_one_shot_async(delay, move |_uuid, _lock| {
+                let this = this.clone();
+                let reminder = reminder.clone();
+                Box::pin(async move {
+                    if let Err(e) = this.remind(&reminder).await {
+                        tracing::error!("failed to send reminder {reminder}: {e}");
+                    }
+                })
+            })?;
+            uuids.push(scheduler.add(job).await?);
+        }
+        if !uuids.is_empty() {
+            jobs.insert(announcement.id, uuids);
+        }
+
+        Ok(())
+    }
+
+    /// Send the reminder as replies to messages of the announcement.
+    async fn remind(&self, reminder: &Reminder) -> Result<()> {
+        let Some(announcement) = self
+            .storage
+            .announcements()?
+            .find_by_id(reminder.announcement_id)
+            .await?
+        else {
+            return Ok(());
+        };
+        // The event has moved, and a new reminder is scheduled
+        if !announcement.events.contains(&reminder.event) {
+            return Ok(());
+        }
+
+        let publication = announcement.publication();
+        let text = reminder.text(&publication.title);
+
+        // Telegram messages of the announcement, by chat
+        let sent: HashMap<ChatId, MessageId> = self
+            .chat_manager
+            .messages
+            .find_by_resource(&ResourceId::Announcement(announcement.id))
+            .await?
+            .into_iter()
+            .filter(|result| result.backend == PublisherKind::Telegram)
+            .filter_map(|result| Some((result.chat_id?, result.message_id?)))
+            .collect();
+
+        let mut chats = BTreeSet::new();
+        for target in &reminder.config.targets {
+            match target {
+                ReminderTarget::Channel => {
+                    chats.extend(sent.keys().filter(|chat_id| !chat_id.is_user()));
+                }
+                ReminderTarget::Subscribers => {
+                    let subscriptions = self.chat_manager.subscriptions.all().await?;
+                    chats.extend(
+                        subscriptions
+                            .into_iter()
+                            .filter(|subscription| subscription.matches(&publication))
+                            .map(|subscription| subscription.chat_id),
+                    );
+                }
+            }
+        }
+
+        let mut interval = tokio::time::interval(MESSAGE_INTERVAL);
+        for chat_id in chats {
+            interval.tick().await;
+            let mut request = self
+                .chat_manager
+                .bot
+                .send_message(chat_id, &text)
+                .parse_mode(ParseMode::Html);
+            if let Some(message_id) = sent.get(&chat_id) {
+                request = request
+                    .reply_to_message_id(*message_id)
+                    .allow_sending_without_reply(true);
+            }
+            if let Err(e) = request.await {
+                tracing::warn!("failed to send reminder to {chat_id}: {e}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::insight::PeriodPattern;
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("1h"), Some(Duration::hours(1)));
+        assert_eq!(
+            parse_offset("1d12h30m"),
+            Some(Duration::hours(36) + Duration::minutes(30))
+        );
+        assert_eq!(parse_offset("90"), None);
+        assert_eq!(parse_offset("1w"), None);
+        assert_eq!(format_offset(Duration::minutes(90)), "1h30m");
+        assert_eq!(format_offset(Duration::days(1)), "1d");
+    }
+
+    #[test]
+    fn test_deserialize_config() {
+        let config: ReminderConfig = serde_yaml::from_str("at: end\nbefore: 24h").unwrap();
+        assert_eq!(config.at, EventBoundary::End);
+        assert_eq!(config.before, Duration::hours(24));
+        assert_eq!(config.targets, vec![ReminderTarget::Channel]);
+        assert!(serde_yaml::from_str::<ReminderConfig>("at: end\nbefore: soon").is_err());
+    }
+
+    #[test]
+    fn test_reminders() {
+        let start = Utc.with_ymd_and_hms(2023, 1, 1, 4, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 1, 8, 15, 59, 0).unwrap();
+        let mut announcement = Announcement {
+            id: ObjectId::new(),
+            mapped_title: String::new(),
+            region: crate::resource::Region::TW,
+            events: vec![EventInAnnouncement {
+                start,
+                end,
+                title: "活動期間".to_string(),
+                pattern: None,
+            }],
+            history: None,
+            latest_version: 0,
+            data: vec![],
+            message_id: None,
+            telegraph: None,
+        };
+        let configs = vec![
+            ReminderConfig {
+                at: EventBoundary::Start,
+                before: Duration::hours(1),
+                targets: vec![ReminderTarget::Channel],
+            },
+            ReminderConfig {
+                at: EventBoundary::End,
+                before: Duration::hours(24),
+                targets: vec![ReminderTarget::Subscribers],
+            },
+        ];
+
+        let reminders =
+            Reminder::for_announcement(&announcement, &configs, start - Duration::days(1));
+        let times: Vec<_> = reminders.iter().map(|r| r.time).collect();
+        assert_eq!(
+            times,
+            vec![start - Duration::hours(1), end - Duration::hours(24)]
+        );
+        assert_eq!(
+            reminders[1].text("<公告>"),
+            "⏰ <b>活動期間</b> ends in 1d\n&lt;公告&gt;"
+        );
+
+        // Reminders in the past are skipped
+        let reminders = Reminder::for_announcement(&announcement, &configs, start);
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].config.at, EventBoundary::End);
+
+        // Open-ended events have no end reminders
+        announcement.events[0].end = start;
+        announcement.events[0].pattern = Some(PeriodPattern::Since);
+        let reminders =
+            Reminder::for_announcement(&announcement, &configs, start - Duration::days(1));
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].config.at, EventBoundary::Start);
+    }
+}
@@ -35,6 +35,8 @@ pub struct Event {
     pub start: DateTime<Utc>,
     #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub end: DateTime<Utc>,
+    /// Whether the end is known, open ends are stored as the start
+    pub has_end: bool,
     pub title: String,
     pub announcement_title: String,
     pub announcement_id: bson::oid::ObjectId,
@@ -1,5 +1,5 @@
-use crate::utils::api_date_format;
-use chrono::{DateTime, FixedOffset, TimeZone};
+use crate::utils::{api_date_format, local_to_fixed, Tz};
+use chrono::{DateTime, NaiveDateTime, TimeZone};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,10 +21,12 @@ pub struct AjaxAnnounce {
     pub status: i32,
     pub platform: i32,
     pub slider_flag: i32,
+    /// Local time of the server
     #[serde(with = "api_date_format")]
-    pub from_date: DateTime<FixedOffset>,
+    pub from_date: NaiveDateTime,
+    /// Local time of the server
     #[serde(with = "api_date_format")]
-    pub to_date: DateTime<FixedOffset>,
+    pub to_date: NaiveDateTime,
     pub replace_time: i64,
     pub priority: i32,
     pub end_date_slider_image: Option<String>,
@@ -61,8 +63,10 @@ pub struct Announce {
     link_num: i32,
 }
 
-impl From<AjaxAnnounce> for Announce {
-    fn from(announce: AjaxAnnounce) -> Self {
+impl Announce {
+    /// Convert from the list of a server in `time_zone`.
+    pub fn new(announce: AjaxAnnounce, time_zone: Tz) -> Self {
+        let utc = |time| local_to_fixed(time, time_zone).with_timezone(&chrono::Utc);
         Self {
             announce_id: announce.announce_id,
             language: announce.language,
@@ -70,8 +74,8 @@ impl From<AjaxAnnounce> for Announce {
             status: announce.status,
             platform: announce.platform,
             slider_flag: announce.slider_flag,
-            from_date: announce.from_date.with_timezone(&chrono::offset::Utc),
-            to_date: announce.to_date.with_timezone(&chrono::offset::Utc),
+            from_date: utc(announce.from_date),
+            to_date: utc(announce.to_date),
             replace_time: chrono::Utc.timestamp_opt(announce.replace_time, 0).unwrap(),
             priority: announce.priority,
             end_date_slider_image: announce.end_date_slider_image,
@@ -1,7 +1,7 @@
 mod ajax;
 mod icon;
 
-use crate::{insight::AnnouncementPage, Error, Page};
+use crate::{insight::AnnouncementPage, utils::Tz, Error, Page};
 pub use ajax::{AjaxAnnounce, AjaxAnnounceList, Announce, AnnounceTitle};
 use chrono::{DateTime, FixedOffset};
 pub use icon::Icon;
@@ -57,8 +57,8 @@ impl AnnouncementPage for InformationPage {
         self.content_node.clone()
     }
 
-    fn create_time(&self) -> Option<DateTime<FixedOffset>> {
-        get_date(&self.date_node)
+    fn create_time(&self, zone: Tz) -> Option<DateTime<FixedOffset>> {
+        get_date(&self.date_node, zone)
     }
 
     fn extra(&self) -> Self::ExtraData {
@@ -97,11 +97,11 @@ fn get_icon(date_node: &NodeDataRef<ElementData>) -> Option<Icon> {
         .and_then(Icon::from_classname)
 }
 
-fn get_date(date_node: &NodeRef) -> Option<DateTime<FixedOffset>> {
+fn get_date(date_node: &NodeRef, zone: Tz) -> Option<DateTime<FixedOffset>> {
     let date_text_node = &date_node.first_child()?;
     let date_text = date_text_node.as_text()?.borrow();
 
-    crate::utils::string_to_date(date_text.trim(), "%Y/%m/%d %H:%M").ok()
+    crate::utils::string_to_date(date_text.trim(), "%Y/%m/%d %H:%M", zone).ok()
 }
 
 // #[cfg(test)]
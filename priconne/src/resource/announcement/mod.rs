@@ -6,11 +6,14 @@ pub mod event;
 pub mod information;
 pub mod news;
 pub mod service;
+pub mod telegraph;
 
+use self::telegraph::TelegraphPage;
 use crate::{
     chat::Sendable,
     client::ResourceResponse,
     insight::{AnnouncementInsight, AnnouncementPage, EventInAnnouncement},
+    template::Templates,
     utils::map_title,
 };
 
@@ -18,7 +21,10 @@ use mongodb::bson;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::serde_as;
 
-use super::Region;
+use super::{
+    post::{Post, PostConfig},
+    Region,
+};
 
 /// Announcement resource
 #[serde_as]
@@ -48,6 +54,12 @@ pub struct Announcement {
     pub latest_version: usize,
     /// Data in this announcement
     pub data: Vec<AnnouncementInsight<bson::Bson>>,
+    /// Message ID in the post channel, see [`Post::message_id`](super::post::Post::message_id)
+    #[serde(default)]
+    pub message_id: Option<i32>,
+    /// Telegraph page, edited when the announcement is updated
+    #[serde(default)]
+    pub telegraph: Option<TelegraphPage>,
 }
 
 impl Announcement {
@@ -68,6 +80,8 @@ impl Announcement {
                 latest_version: 0,
                 events: insight.events.clone(),
                 data: vec![insight.into_bson()],
+                message_id: None,
+                telegraph: None,
             },
         }
     }
@@ -76,19 +90,52 @@ impl Announcement {
     where
         E: Serialize + DeserializeOwned,
     {
+        self.events = insight.events.clone();
         self.data.push(insight.into_bson());
     }
 }
 
+impl Announcement {
+    /// Same as [`publication`](Sendable::publication), with the message
+    /// rendered by `templates`, and the page `html` summarized, or in full if
+    /// short.
+    pub fn publication_with_page(
+        &self,
+        html: Option<&str>,
+        config: &PostConfig,
+        templates: &Templates,
+    ) -> crate::chat::Publication {
+        let mut publication = self.publication();
+        let url = self.data.last().map(|data| data.url.clone());
+        publication.message = Post::from(self).render(url, html, config, templates);
+        publication
+    }
+}
+
 impl Sendable for Announcement {
     fn message(&self) -> crate::chat::Message {
-        let data = self.data.last().unwrap();
-        let text = data.build_message(self);
+        let url = self.data.last().map(|data| data.url.clone());
+        Post::from(self).render(url, None, &PostConfig::default(), &Templates::default())
+    }
 
-        crate::chat::Message {
-            silent: false,
-            text,
-            image_src: None,
+    fn publication(&self) -> crate::chat::Publication {
+        let data = self.data.last().unwrap();
+        let url = data
+            .telegraph_url
+            .as_ref()
+            .and_then(|url| url::Url::parse(url).ok())
+            .unwrap_or_else(|| data.url.clone());
+
+        crate::chat::Publication {
+            resource_id: super::ResourceId::Announcement(self.id),
+            title: data.title.clone(),
+            url: Some(url),
+            tags: data.tags.iter().cloned().collect(),
+            events: self.events.clone(),
+            image: None,
+            source: Some(data.source.clone()),
+            region: self.region.clone(),
+            message: self.message(),
         }
     }
 }
@@ -100,7 +147,7 @@ pub mod sources {
 
     // When change serde representations,
     // also change convert::From impl
-    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
     #[serde(rename_all = "lowercase")]
     pub enum AnnouncementSource {
         Api(String),
@@ -140,10 +187,15 @@ pub struct AnnouncementResponse<T: AnnouncementPage> {
     pub source: sources::AnnouncementSource,
     pub url: url::Url,
     pub page: T,
+    /// Raw HTML of the page, kept for [archiving](crate::archive).
+    pub html: String,
 }
 
 impl<T: AnnouncementPage> ResourceResponse for AnnouncementResponse<T> {
-    fn telegraph_content(&self, extra: Option<String>) -> Result<Option<String>, crate::Error> {
+    fn telegraph_content(
+        &self,
+        extra: Option<String>,
+    ) -> Result<Option<Vec<String>>, crate::Error> {
         let content_node = self.page.content();
 
         let attrs = content_node.as_element().unwrap().clone().attributes;
@@ -169,8 +221,14 @@ impl<T: AnnouncementPage> ResourceResponse for AnnouncementResponse<T> {
             }));
         }
 
-        let content = serde_json::to_string(&content)?;
+        let pages = telegraph::split_content(content, telegraph::PAGE_LIMIT)
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        if pages.len() > 1 {
+            tracing::debug!("telegraph content split into {} pages", pages.len());
+        }
 
-        Ok(Some(content))
+        Ok(Some(pages))
     }
 }
@@ -6,7 +6,7 @@ pub use news::News;
 
 use crate::{
     insight::AnnouncementPage,
-    utils::{trim_leading_whitespace, HOUR},
+    utils::{midnight, trim_leading_whitespace, Tz},
     Error, Page,
 };
 use chrono::{FixedOffset, NaiveDate};
@@ -117,12 +117,8 @@ impl AnnouncementPage for NewsPage {
         self.content_node.clone()
     }
 
-    fn create_time(&self) -> Option<chrono::DateTime<FixedOffset>> {
-        let offset = FixedOffset::east_opt(8 * HOUR).unwrap();
-        self.date
-            .and_hms_opt(0, 0, 0)?
-            .and_local_timezone(offset)
-            .latest()
+    fn create_time(&self, zone: Tz) -> Option<chrono::DateTime<FixedOffset>> {
+        Some(midnight(self.date, zone))
     }
 
     fn extra(&self) -> Self::ExtraData {
@@ -159,6 +155,7 @@ fn get_content(section_node: &NodeDataRef<ElementData>) -> Result<&NodeRef, Erro
 mod tests {
     use super::*;
 
+    use crate::utils::DEFAULT_TIME_ZONE;
     use kuchikiki::traits::TendrilSink;
     use std::path::Path;
 
@@ -173,7 +170,7 @@ mod tests {
             page.title,
             "【轉蛋】《精選轉蛋》新角色「克蘿依（聖學祭）」登場！機率UP活動舉辦預告！".to_owned()
         );
-        assert_eq!(page.events().len(), 1);
+        assert_eq!(page.events(DEFAULT_TIME_ZONE).len(), 1);
     }
 
     #[test]
@@ -188,6 +185,26 @@ mod tests {
             page.title,
             "【活動】《10月戰隊競賽》限定加碼！特別排名活動".to_owned()
         );
-        assert_eq!(page.events().len(), 1);
+        assert_eq!(page.events(DEFAULT_TIME_ZONE).len(), 1);
+    }
+
+    #[test]
+    fn test_time_zone() {
+        let path = Path::new("tests/news_1376.html");
+        let document = kuchikiki::parse_html().from_utf8().from_file(path).unwrap();
+        let page = NewsPage::from_document(document).unwrap();
+
+        // The same local times are an hour earlier in Tokyo
+        let tokyo = chrono_tz::Asia::Tokyo;
+        let taipei = page.events(DEFAULT_TIME_ZONE);
+        let events = page.events(tokyo);
+        assert_eq!(
+            taipei[0].start - events[0].start,
+            chrono::Duration::hours(1)
+        );
+        assert_eq!(
+            page.create_time(tokyo).unwrap().to_rfc3339(),
+            "2021-10-26T00:00:00+09:00"
+        );
     }
 }
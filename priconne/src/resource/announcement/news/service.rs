@@ -2,22 +2,25 @@ use async_trait::async_trait;
 use futures::{stream::BoxStream, Stream, TryStreamExt};
 
 use html5ever::tendril::TendrilSink;
-use reqwest::{Response, Url};
+use reqwest::Url;
 
 use crate::{
-    client::ResourceClient,
+    client::{HttpResponse, HttpTransport, ResourceClient},
     resource::{
         announcement::{sources::AnnouncementSource, AnnouncementResponse},
         news::{News, NewsList, NewsPage},
         service::AnnouncementClient,
     },
+    utils::Tz,
     Error, Page,
 };
 
 #[derive(Debug, Clone)]
 pub struct NewsClient {
-    pub client: reqwest::Client,
+    pub client: HttpTransport,
     pub server: Url,
+    /// Time zone of dates in the news list
+    pub time_zone: Tz,
 }
 
 impl NewsClient {
@@ -29,9 +32,9 @@ impl NewsClient {
         self.server().join(href).map_err(Error::from)
     }
 
-    async fn get_raw(&self, href: &str) -> Result<Response, Error> {
+    async fn get_raw(&self, href: &str) -> Result<HttpResponse, Error> {
         let url = self.url(href)?;
-        self.client.get(url).send().await.map_err(Error::from)
+        self.client.get(url).await
     }
 
     fn list_href(&self, page: i32) -> String {
@@ -46,13 +49,14 @@ impl NewsClient {
         let href = self.href(news_id);
         let response = self.get_raw(&href).await?;
         let url = response.url().clone();
-        let html = response.text().await?;
+        let html = response.text();
 
         let response = AnnouncementResponse {
             post_id: news_id,
             source: AnnouncementSource::Website,
             url,
-            page: NewsPage::from_html(html)?,
+            page: NewsPage::from_html(html.clone())?,
+            html,
         };
 
         Ok(response)
@@ -60,7 +64,7 @@ impl NewsClient {
 
     async fn list(&self, page: i32) -> Result<NewsList, Error> {
         let href = self.list_href(page);
-        let html = self.get_raw(&href).await?.text().await?;
+        let html = self.get_raw(&href).await?.text();
 
         NewsList::from_html(html)
     }
@@ -82,6 +86,9 @@ impl ResourceClient<News> for NewsClient {
     fn try_stream(&self) -> BoxStream<Result<News, Error>> {
         Box::pin(self.try_stream())
     }
+    fn time_zone(&self) -> Tz {
+        self.time_zone
+    }
     async fn get_by_id(&self, id: i32) -> Result<Self::Response, Error> {
         self.get(id).await
     }
@@ -108,7 +115,7 @@ async fn try_next_news_list(
     };
 
     let response = client.get_raw(&href).await?;
-    let text = response.text().await?;
+    let text = response.text();
     let document = kuchikiki::parse_html().one(text);
     let news_list = NewsList::from_document(document)?;
     let next_href = news_list.next_href.clone();
@@ -118,17 +125,81 @@ async fn try_next_news_list(
 
 #[cfg(test)]
 mod tests {
-    use crate::client::FetchStrategy;
+    use crate::{
+        client::{save_interaction, FetchStrategy, Interaction, TransportConfig},
+        insight::{tagging::RegexTagger, Extractor},
+        utils::DEFAULT_TIME_ZONE,
+    };
+    use futures::StreamExt;
     use reqwest::Url;
 
     use super::*;
 
+    async fn record_fixture(dir: &std::path::Path, url: &str, fixture: &str) {
+        let url = Url::parse(url).unwrap();
+        let interaction = Interaction {
+            request: url.clone(),
+            response: HttpResponse {
+                url,
+                status: 200,
+                body: std::fs::read_to_string(fixture).unwrap(),
+            },
+        };
+        save_interaction(dir, &interaction).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_pipeline() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("priconne-test-news-pipeline");
+        let server = "http://www.princessconnect.so-net.tw";
+        record_fixture(
+            &dir,
+            &format!("{server}/news?page=1"),
+            "tests/news_list.html",
+        )
+        .await;
+        record_fixture(
+            &dir,
+            &format!("{server}/news/newsDetail/1287"),
+            "tests/news_1460.html",
+        )
+        .await;
+
+        let client = NewsClient {
+            client: TransportConfig::Replay(dir.clone()).build(reqwest::Client::new()),
+            server: Url::parse(server)?,
+            time_zone: DEFAULT_TIME_ZONE,
+        };
+
+        let list: Vec<News> = ResourceClient::try_stream(&client)
+            .take(10)
+            .map(|news| news.unwrap())
+            .collect()
+            .await;
+        assert_eq!(list.len(), 10);
+        let news = list.iter().find(|news| news.id == 1287).unwrap();
+
+        let response = client.fetch(news).await?;
+        let extractor = Extractor {
+            tagger: RegexTagger { tag_rules: vec![] },
+            time_zones: Default::default(),
+        };
+        let insight = extractor.extract_announcement(&response);
+        assert_eq!(insight.title, "【活動】「12月戰隊競賽」模式變更開始預告！");
+        assert!(insight.tags.contains("活動"));
+
+        tokio::fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_latest_news() -> Result<(), Box<dyn std::error::Error>> {
-        let collection = crate::database::tests::init_db().await?.collection("news");
+        let collection = crate::database::Storage::Mongo(crate::database::tests::init_db().await?)
+            .metadata("news")?;
         let client = NewsClient {
-            client: reqwest::Client::new(),
+            client: reqwest::Client::new().into(),
             server: Url::parse("http://www.princessconnect.so-net.tw")?,
+            time_zone: DEFAULT_TIME_ZONE,
         };
         let strategy = FetchStrategy {
             fuse_limit: Some(5),
@@ -1,17 +1,28 @@
 use crate::{
+    archive::ArchivedPage,
+    chat::{Preview, PublisherKind},
     client::{MemorizedResourceClient, MetadataFindResult, ResourceClient, ResourceResponse},
-    database::AnnouncementCollection,
+    database::AnnouncementRepository,
     insight::AnnouncementPage,
     resource::{sources::AnnouncementSource, Announcement, ResourceMetadata},
+    search::SearchDocument,
     service::{PriconneService, ResourceService},
+    utils::Tz,
     Error,
 };
 use async_trait::async_trait;
+use mongodb::bson;
 
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 use tracing::{debug, instrument, trace};
 
-use crate::resource::announcement::AnnouncementResponse;
+use crate::resource::announcement::{
+    telegraph::{
+        join_pages, part_title, with_footer, with_navigation, ChangelogEntry, TelegraphPage,
+        TelegraphPart,
+    },
+    AnnouncementResponse,
+};
 
 /// Clients that can fetch [`AnnouncementResponse`] need to implement this trait
 /// to privide what source they are fetching from.
@@ -39,8 +50,11 @@ where
     type Page: AnnouncementPage;
 
     fn source(&self) -> AnnouncementSource;
-    fn announcement_collection(&self, priconne: &PriconneService) -> AnnouncementCollection {
-        AnnouncementCollection(priconne.database.collection("announcement"))
+    fn announcement_collection(
+        &self,
+        priconne: &PriconneService,
+    ) -> Result<Arc<dyn AnnouncementRepository>, Error> {
+        priconne.storage.announcements()
     }
     /// Fetch latest announcements and compare with metadata collection
     async fn collect_latest_metadatas(&self) -> Result<Vec<MetadataFindResult<M>>, Error>;
@@ -106,13 +120,13 @@ where
         M: 'async_trait,
     {
         let source = self.source();
-        let announcements = self.announcement_collection(priconne);
+        let announcements = self.announcement_collection(priconne)?;
 
-        let found = announcements
-            .find_resource(metadata.item(), &source)
-            .await?;
+        let item = metadata.item();
+        let found = announcements.find(item.title(), item.id(), &source).await?;
 
-        let decision = AnnouncementDecision::new(&source, &metadata, &found);
+        let zone = priconne.extractor.time_zone(&source);
+        let decision = AnnouncementDecision::new(&source, &metadata, &found, zone);
 
         if !decision.should_request() {
             return Ok(());
@@ -120,52 +134,220 @@ where
 
         // ask client to get full article
         // maybe other things like thumbnail for cartoon, todo
-        let (mut insight, content) = {
+        let (mut insight, content, archived, document, html) = {
             let response = self.fetch_response(metadata.item()).await?;
             let insight = priconne.extractor.extract_announcement(&response);
             let extra = Some(serde_json::to_string_pretty(&insight.extra)?);
-
-            (insight, response.telegraph_content(extra)?)
+            let archived = ArchivedPage::new(&response)?;
+            let document = SearchDocument::new(&response, None, archived.fetch_time);
+
+            (
+                insight,
+                response.telegraph_content(extra)?,
+                archived,
+                document,
+                response.html,
+            )
         };
+        priconne.page_archive()?.insert(&archived).await?;
 
         // extract data
-        if decision.should_telegraph() {
-            // TODO: telegraph patch in utils
-            let telegraph = priconne
-                .telegraph
-                .create_page(&insight.title, &content.unwrap(), false)
-                .await?;
-
-            insight.telegraph_url = Some(telegraph.url);
+        let mut telegraph = found.as_ref().and_then(|found| found.telegraph.clone());
+        if let (true, Some(content)) = (decision.should_telegraph(), content) {
+            let mut changelog: Vec<ChangelogEntry> = found
+                .iter()
+                .flat_map(|found| found.data.iter().map(ChangelogEntry::from))
+                .collect();
+            changelog.push(ChangelogEntry::from(&insight));
+
+            let page = publish_telegraph(
+                priconne,
+                telegraph.as_ref(),
+                &insight.title,
+                &insight.url,
+                &content,
+                priconne.config.post.changelog.then_some(&changelog[..]),
+            )
+            .await?;
+            insight.telegraph_url = Some(page.url.clone());
+            telegraph = Some(page);
         }
 
         trace!("{insight:?}");
-        let announcement = Announcement::new(insight, found);
+        let mut announcement = Announcement::new(insight, found);
+        announcement.telegraph = telegraph;
 
         if decision.send_post_and_continue() {
-            let message = priconne
+            let results = priconne
                 .chat_manager
-                .send_announcement(&announcement)
-                .await?;
-            trace!("message sent: {:?}", message.url());
+                .send_announcement(
+                    &announcement,
+                    Some(&html),
+                    &priconne.config.post,
+                    &priconne.templates,
+                )
+                .await?
+                .into_results()?;
+            trace!("message sent to {} recipient(s)", results.len());
+            announcement.message_id = results
+                .iter()
+                .filter(|result| result.backend == PublisherKind::Telegram)
+                .find_map(|result| result.message_id)
+                .map(|id| id.0);
         };
 
+        if decision.should_notify() {
+            match priconne
+                .chat_manager
+                .notify_subscribers(&announcement.publication_with_page(
+                    Some(&html),
+                    &priconne.config.post,
+                    &priconne.templates,
+                ))
+                .await
+            {
+                Ok(results) => trace!("{} subscriber(s) notified", results.len()),
+                Err(e) => tracing::error!("failed to notify subscribers: {e}"),
+            }
+        }
+
         // TODO: Graceful Shutdown
         self.upsert_metadata(metadata.item()).await?;
         announcements.upsert(&announcement).await?;
+        priconne.reminders.schedule(&announcement).await?;
+        if let Err(e) = priconne.track_maintenance(&announcement).await {
+            tracing::error!("failed to track maintenance: {e}");
+        }
+
+        priconne.search.add(SearchDocument {
+            announcement_id: Some(announcement.id.to_hex()),
+            ..document
+        });
 
         Ok(())
     }
 
-    fn dry_work(&self, metadata: MetadataFindResult<M>) {
-        tracing::info!(
-            "dry_run: work {} {}",
-            metadata.item().kind(),
-            metadata.item().title()
-        )
+    #[instrument(skip_all, fields(
+        source = %self.source(),
+        metadata.id = metadata.item().id(),
+        metadata.title = metadata.item().title()))]
+    async fn dry_work(
+        &self,
+        priconne: &PriconneService,
+        metadata: MetadataFindResult<M>,
+    ) -> Result<(), Error>
+    where
+        M: 'async_trait,
+    {
+        let item = metadata.item();
+        let (insight, content, html) = {
+            let response = self.fetch_response(item).await?;
+            let insight = priconne.extractor.extract_announcement(&response);
+            let extra = Some(serde_json::to_string_pretty(&insight.extra)?);
+
+            (
+                insight.into_bson(),
+                response.telegraph_content(extra)?,
+                response.html,
+            )
+        };
+
+        let preview = Preview {
+            name: format!("{}-{}", self.source().name(), item.id()),
+            title: insight.title.clone(),
+            insight: Some(bson::to_bson(&insight)?.into_relaxed_extjson()),
+            message: Announcement::new(insight, None)
+                .publication_with_page(Some(&html), &priconne.config.post, &priconne.templates)
+                .message,
+            telegraph: content.as_deref().map(join_pages).transpose()?,
+        };
+        priconne.preview_writer().write(&preview).await
     }
 }
 
+/// Create the Telegraph pages, or edit `previous` in place.
+/// Nothing is uploaded if the title and content are unchanged.
+///
+/// Pages of `previous` are reused in order. Pages are linked to their
+/// neighbours, and the footer is added to the last one.
+async fn publish_telegraph(
+    priconne: &PriconneService,
+    previous: Option<&TelegraphPage>,
+    title: &str,
+    url: &url::Url,
+    pages: &[String],
+    changelog: Option<&[ChangelogEntry]>,
+) -> Result<TelegraphPage, Error> {
+    let content = pages.concat();
+    if let Some(previous) = previous.filter(|page| page.is_unchanged(title, &content)) {
+        debug!("telegraph page {} is unchanged", previous.path);
+        return Ok(previous.clone());
+    }
+
+    let update_time = chrono::Utc::now();
+    let mut rehosted = Vec::with_capacity(pages.len());
+    for page in pages {
+        rehosted.push(priconne.images.rehost_content(page, url).await?);
+    }
+
+    let count = rehosted.len();
+    let mut links = previous.map(TelegraphPage::links).unwrap_or_default();
+    links.truncate(count);
+    let existing = links.len();
+
+    // Pages are uploaded in order, so a new page is only linked from the
+    // previous one in a second pass
+    let second_pass = existing.saturating_sub(1)..count.saturating_sub(1);
+    let passes = (0..count).chain(second_pass.filter(|_| existing < count));
+    for index in passes {
+        let previous_url = index
+            .checked_sub(1)
+            .and_then(|index| links.get(index))
+            .map(|link| link.url.as_str());
+        let next_url = links.get(index + 1).map(|link| link.url.as_str());
+        let mut page_content = with_navigation(&rehosted[index], previous_url, next_url)?;
+        if index + 1 == count {
+            page_content = with_footer(
+                &page_content,
+                update_time,
+                changelog,
+                priconne.chat_manager.config.recipient.time_zone,
+            )?;
+        }
+
+        let page_title = part_title(title, index, count);
+        match links.get(index) {
+            Some(link) => {
+                debug!("editing telegraph page {}", link.path);
+                priconne
+                    .telegraph
+                    .edit_page(&link.path, &page_title, &page_content, false)
+                    .await?;
+            }
+            None => {
+                let page = priconne
+                    .telegraph
+                    .create_page(&page_title, &page_content, false)
+                    .await?;
+                links.push(TelegraphPart {
+                    path: page.path,
+                    url: page.url,
+                });
+            }
+        }
+    }
+
+    let mut links = links.into_iter();
+    let first = links.next().ok_or(Error::EmptyContentError)?;
+    Ok(TelegraphPage {
+        path: first.path,
+        url: first.url,
+        content_hash: TelegraphPage::content_hash(title, &content),
+        update_time,
+        parts: links.collect(),
+    })
+}
+
 /// Use information about a resource to find action to take
 #[derive(Debug)]
 pub struct AnnouncementDecision {
@@ -197,20 +379,31 @@ impl AnnouncementDecision {
         matches!(self.action, Action::Send)
     }
 
+    /// Whether subscribers should know about the new or updated post
+    pub fn should_notify(&self) -> bool {
+        matches!(self.action, Action::Send | Action::Edit)
+    }
+
     pub fn should_telegraph(&self) -> bool {
-        !matches!(self.action, Action::Send)
+        matches!(
+            self.action,
+            Action::Send | Action::Edit | Action::UpdateOnly
+        )
     }
 }
 
 // TODO: random write, may all wrong
 impl AnnouncementDecision {
+    /// Decide by times in `zone`, the time zone of the source, the same as
+    /// times of the stored insight.
     pub fn new<R: ResourceMetadata + Debug>(
         source: &AnnouncementSource,
         find_result: &MetadataFindResult<R>,
         found: &Option<Announcement>,
+        zone: Tz,
     ) -> Self {
         Self {
-            action: Self::get_action(source, find_result, found),
+            action: Self::get_action(source, find_result, found, zone),
             source: source.clone(),
         }
     }
@@ -220,6 +413,7 @@ impl AnnouncementDecision {
         source: &AnnouncementSource,
         resource: &MetadataFindResult<R>,
         post: &Option<Announcement>,
+        zone: Tz,
     ) -> Action {
         let resource = resource.item();
         if post.is_none() {
@@ -248,7 +442,7 @@ impl AnnouncementDecision {
         let found_resource = found_resource.unwrap();
 
         let update_time = found_resource.update_time.or(found_resource.create_time);
-        if update_time.is_some() && resource.update_time() > update_time.unwrap() {
+        if update_time.is_some() && resource.update_time(zone) > update_time.unwrap() {
             debug!("old post has same source, but current one is newer. edit the old message");
             return Action::Edit;
         }
@@ -257,3 +451,36 @@ impl AnnouncementDecision {
         Action::None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::{
+        database::tests::{insight, news},
+        utils::midnight,
+    };
+
+    #[test]
+    fn test_news_decision_in_zone() {
+        let zone = chrono_tz::Asia::Tokyo;
+        let source = AnnouncementSource::Website;
+        let news = news(1, "公告");
+
+        // Stored with the create time of the page, midnight in the zone
+        let mut insight = insight(source.clone(), 1, "公告", Utc::now());
+        insight.create_time = Some(midnight(news.date, zone).with_timezone(&Utc));
+        let found = Some(Announcement::new(insight, None));
+
+        let polled = MetadataFindResult::from_found(news.clone(), news.clone());
+        let decision = AnnouncementDecision::new(&source, &polled, &found, zone);
+        assert!(matches!(decision.action, Action::None));
+
+        let mut updated = news.clone();
+        updated.date += Duration::days(1);
+        let polled = MetadataFindResult::from_found(updated, news);
+        let decision = AnnouncementDecision::new(&source, &polled, &found, zone);
+        assert!(matches!(decision.action, Action::Edit));
+    }
+}
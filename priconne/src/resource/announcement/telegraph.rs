@@ -0,0 +1,409 @@
+//! Telegraph pages of announcements
+//!
+//! Each announcement has one Telegraph page. It is created for the first
+//! version, and edited in place when the announcement is updated, so that
+//! links in older messages show the latest content. Pages are not touched when
+//! the content is unchanged, see [`TelegraphPage::content_hash`].
+//!
+//! Telegraph rejects pages over 64 KB, so long content is split into
+//! [parts](split_content) at section boundaries, linked to each other by
+//! "previous/next page" links. The first page is the one linked in messages.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use telegraph_rs::{Node, NodeElement};
+
+use crate::{
+    insight::AnnouncementInsight,
+    utils::{format_time, Tz},
+    Result,
+};
+
+/// A Telegraph page created for an announcement.
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TelegraphPage {
+    /// Path of the page, used to edit it
+    pub path: String,
+    pub url: String,
+    /// Hash of the title and content, without the footer
+    pub content_hash: String,
+    #[serde_as(as = "mongodb::bson::DateTime")]
+    pub update_time: DateTime<Utc>,
+    /// Pages following this one when the content is split, in order
+    #[serde(default)]
+    pub parts: Vec<TelegraphPart>,
+}
+
+/// A page following the first one, when the content is split.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TelegraphPart {
+    pub path: String,
+    pub url: String,
+}
+
+impl TelegraphPage {
+    /// All pages, starting from this one.
+    pub fn links(&self) -> Vec<TelegraphPart> {
+        let first = TelegraphPart {
+            path: self.path.clone(),
+            url: self.url.clone(),
+        };
+        std::iter::once(first).chain(self.parts.clone()).collect()
+    }
+
+    /// Hash of a page with `title` and serialized `content`.
+    pub fn content_hash(title: &str, content: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(title);
+        hasher.update([0]);
+        hasher.update(content);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Whether the page already has `title` and `content`.
+    pub fn is_unchanged(&self, title: &str, content: &str) -> bool {
+        self.content_hash == Self::content_hash(title, content)
+    }
+}
+
+/// A version of the announcement in the changelog.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangelogEntry {
+    pub time: Option<DateTime<Utc>>,
+    pub title: String,
+}
+
+impl<E> From<&AnnouncementInsight<E>> for ChangelogEntry {
+    fn from(insight: &AnnouncementInsight<E>) -> Self {
+        Self {
+            time: insight.update_time.or(insight.create_time),
+            title: insight.title.clone(),
+        }
+    }
+}
+
+/// Append the footer to serialized `content`: a "last updated" line, and
+/// the changelog if given, with times in `zone`.
+pub fn with_footer(
+    content: &str,
+    update_time: DateTime<Utc>,
+    changelog: Option<&[ChangelogEntry]>,
+    zone: Tz,
+) -> Result<String> {
+    let mut nodes: Vec<Node> = serde_json::from_str(content)?;
+
+    nodes.push(element("hr", None));
+    nodes.push(element(
+        "p",
+        Some(vec![element(
+            "em",
+            Some(vec![Node::Text(format!(
+                "最後更新：{}",
+                format_time(update_time, zone)
+            ))]),
+        )]),
+    ));
+
+    if let Some(changelog) = changelog.filter(|changelog| !changelog.is_empty()) {
+        nodes.push(element(
+            "h4",
+            Some(vec![Node::Text("更新紀錄".to_string())]),
+        ));
+        let items = changelog
+            .iter()
+            .map(|entry| {
+                let time = entry
+                    .time
+                    .map_or("-".to_string(), |time| format_time(time, zone));
+                element(
+                    "li",
+                    Some(vec![Node::Text(format!("{time} {}", entry.title))]),
+                )
+            })
+            .collect();
+        nodes.push(element("ul", Some(items)));
+    }
+
+    Ok(serde_json::to_string(&nodes)?)
+}
+
+/// Maximum size of the serialized content of a page, in bytes.
+///
+/// Telegraph accepts 64 KB, the rest is left for the navigation, the footer
+/// and re-hosted image URLs.
+pub const PAGE_LIMIT: usize = 56 * 1024;
+
+/// Split `nodes` into pages of at most `limit` bytes when serialized.
+///
+/// Pages are split before a `■` heading or a `<hr>`. A section too large for
+/// a page is split between its nodes, descending into elements if needed, so
+/// a large `<p>` becomes several `<p>`s.
+pub fn split_content(nodes: Vec<Node>, limit: usize) -> Vec<Vec<Node>> {
+    let mut pages = Vec::new();
+    let mut current = Vec::new();
+    let mut current_size = size(&current);
+
+    for section in sections(nodes) {
+        for part in fit(section, limit) {
+            // Joining two arrays saves a bracket and adds a comma
+            let part_size = size(&part);
+            if !current.is_empty() && current_size + part_size - 1 > limit {
+                pages.push(std::mem::take(&mut current));
+                current_size = size(&current);
+            }
+            current_size += part_size - if current.is_empty() { 2 } else { 1 };
+            current.extend(part);
+        }
+    }
+    if !current.is_empty() || pages.is_empty() {
+        pages.push(current);
+    }
+    pages
+}
+
+/// Group `nodes` into sections, each starting with a `■` heading or a `<hr>`.
+fn sections(nodes: Vec<Node>) -> Vec<Vec<Node>> {
+    let mut sections: Vec<Vec<Node>> = Vec::new();
+    for node in nodes {
+        match sections.last_mut() {
+            Some(section) if !starts_section(&node) => section.push(node),
+            _ => sections.push(vec![node]),
+        }
+    }
+    sections
+}
+
+fn starts_section(node: &Node) -> bool {
+    match node {
+        Node::NodeElement(element) if element.tag == "hr" => true,
+        _ => first_text(node).is_some_and(|text| text.trim_start().starts_with('■')),
+    }
+}
+
+/// The first non-blank text in `node`.
+fn first_text(node: &Node) -> Option<&str> {
+    match node {
+        Node::Text(text) => (!text.trim().is_empty()).then_some(text.as_str()),
+        Node::NodeElement(element) => element.children.iter().flatten().find_map(first_text),
+    }
+}
+
+/// Break `section` into parts fitting in `limit`, if possible.
+fn fit(section: Vec<Node>, limit: usize) -> Vec<Vec<Node>> {
+    if size(&section) <= limit {
+        return vec![section];
+    }
+    if section.len() > 1 {
+        return section
+            .into_iter()
+            .flat_map(|node| fit(vec![node], limit))
+            .collect();
+    }
+
+    match section.into_iter().next() {
+        Some(Node::NodeElement(NodeElement {
+            tag,
+            attrs,
+            children: Some(children),
+        })) => {
+            let shell = |children| {
+                vec![Node::NodeElement(NodeElement {
+                    tag: tag.clone(),
+                    attrs: attrs.clone(),
+                    children: Some(children),
+                })]
+            };
+            let overhead = size(&shell(Vec::new())) - 2;
+            split_content(children, limit.saturating_sub(overhead).max(2))
+                .into_iter()
+                .map(shell)
+                .collect()
+        }
+        // A single text too large is kept as is
+        node => vec![node.into_iter().collect()],
+    }
+}
+
+/// Size of serialized `nodes`, in bytes.
+fn size(nodes: &[Node]) -> usize {
+    serde_json::to_string(nodes).map_or(0, |json| json.len())
+}
+
+/// Title of the part `index` of `count` pages.
+pub fn part_title(title: &str, index: usize, count: usize) -> String {
+    if count <= 1 || index == 0 {
+        title.to_string()
+    } else {
+        format!("{title}（{}/{count}）", index + 1)
+    }
+}
+
+/// Append links to the `previous` and `next` pages to serialized `content`.
+pub fn with_navigation(
+    content: &str,
+    previous: Option<&str>,
+    next: Option<&str>,
+) -> Result<String> {
+    if previous.is_none() && next.is_none() {
+        return Ok(content.to_string());
+    }
+
+    let mut nodes: Vec<Node> = serde_json::from_str(content)?;
+    let mut links = Vec::new();
+    if let Some(previous) = previous {
+        links.push(link(previous, "« 上一頁"));
+    }
+    if let Some(next) = next {
+        if !links.is_empty() {
+            links.push(Node::Text("｜".to_string()));
+        }
+        links.push(link(next, "下一頁 »"));
+    }
+    nodes.push(element("hr", None));
+    nodes.push(element("p", Some(links)));
+
+    Ok(serde_json::to_string(&nodes)?)
+}
+
+/// Join serialized pages back into one content, for previews.
+pub fn join_pages(pages: &[String]) -> Result<String> {
+    let mut nodes: Vec<Node> = Vec::new();
+    for page in pages {
+        nodes.extend(serde_json::from_str::<Vec<Node>>(page)?);
+    }
+    Ok(serde_json::to_string(&nodes)?)
+}
+
+fn link(href: &str, text: &str) -> Node {
+    Node::NodeElement(NodeElement {
+        tag: "a".to_string(),
+        attrs: Some([("href".to_string(), Some(href.to_string()))].into()),
+        children: Some(vec![Node::Text(text.to_string())]),
+    })
+}
+
+fn element(tag: &str, children: Option<Vec<Node>>) -> Node {
+    Node::NodeElement(NodeElement {
+        tag: tag.to_string(),
+        attrs: None,
+        children,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::utils::DEFAULT_TIME_ZONE;
+
+    #[test]
+    fn test_content_hash() {
+        let page = TelegraphPage {
+            path: "post-07-01".to_string(),
+            url: "https://telegra.ph/post-07-01".to_string(),
+            content_hash: TelegraphPage::content_hash("公告", r#"["內容"]"#),
+            update_time: Utc::now(),
+            parts: Vec::new(),
+        };
+        assert!(page.is_unchanged("公告", r#"["內容"]"#));
+        assert!(!page.is_unchanged("公告", r#"["內容更新"]"#));
+        assert!(!page.is_unchanged("公告（更新）", r#"["內容"]"#));
+    }
+
+    #[test]
+    fn test_with_footer() {
+        let time = Utc.with_ymd_and_hms(2022, 7, 1, 3, 55, 0).unwrap();
+        let content = with_footer(r#"["內容"]"#, time, None, DEFAULT_TIME_ZONE).unwrap();
+        assert_eq!(
+            content,
+            r#"["內容",{"tag":"hr"},{"tag":"p","children":[{"tag":"em","children":["最後更新：2022/07/01 11:55 (UTC+08:00)"]}]}]"#
+        );
+
+        let changelog = [
+            ChangelogEntry {
+                time: Some(time),
+                title: "公告".to_string(),
+            },
+            ChangelogEntry {
+                time: None,
+                title: "公告（更新）".to_string(),
+            },
+        ];
+        let content = with_footer(
+            r#"["內容"]"#,
+            time,
+            Some(&changelog),
+            chrono_tz::Asia::Tokyo,
+        )
+        .unwrap();
+        assert!(content.ends_with(
+            r#"{"tag":"h4","children":["更新紀錄"]},{"tag":"ul","children":[{"tag":"li","children":["2022/07/01 12:55 (UTC+09:00) 公告"]},{"tag":"li","children":["- 公告（更新）"]}]}]"#
+        ));
+    }
+
+    fn text(nodes: &[Node]) -> String {
+        nodes
+            .iter()
+            .map(|node| match node {
+                Node::Text(text) => text.clone(),
+                Node::NodeElement(element) => text(element.children.as_deref().unwrap_or_default()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_content() {
+        let mut children = Vec::new();
+        for section in 0..10 {
+            children.push(Node::Text(format!("■第{section}節")));
+            children.push(element("br", None));
+            children.push(Node::Text("內容".repeat(20)));
+            children.push(element("br", None));
+        }
+        let nodes = vec![
+            element("p", Some(vec![Node::Text("開頭".to_string())])),
+            element("p", Some(children)),
+            element("hr", None),
+            Node::Text("結尾".to_string()),
+        ];
+
+        let pages = split_content(nodes.clone(), PAGE_LIMIT);
+        assert_eq!(pages, vec![nodes.clone()]);
+
+        let limit = 400;
+        let pages = split_content(nodes.clone(), limit);
+        assert!(pages.len() > 1);
+        for page in &pages {
+            assert!(size(page) <= limit, "{} > {limit}", size(page));
+        }
+        // Pages after the first start with a section, wrapped in `<p>`
+        for page in &pages[1..] {
+            assert!(starts_section(&page[0]), "{page:?}");
+        }
+        assert_eq!(pages.last().unwrap().last(), nodes.last());
+        assert_eq!(text(&pages.concat()), text(&nodes));
+    }
+
+    #[test]
+    fn test_with_navigation() {
+        assert_eq!(
+            with_navigation(r#"["內容"]"#, None, None).unwrap(),
+            r#"["內容"]"#
+        );
+        assert_eq!(
+            with_navigation(
+                r#"["內容"]"#,
+                Some("https://telegra.ph/a"),
+                Some("https://telegra.ph/c")
+            )
+            .unwrap(),
+            r#"["內容",{"tag":"hr"},{"tag":"p","children":[{"tag":"a","attrs":{"href":"https://telegra.ph/a"},"children":["« 上一頁"]},"｜",{"tag":"a","attrs":{"href":"https://telegra.ph/c"},"children":["下一頁 »"]}]}]"#
+        );
+        assert_eq!(part_title("公告", 0, 3), "公告");
+        assert_eq!(part_title("公告", 1, 3), "公告（2/3）");
+    }
+}
@@ -4,17 +4,18 @@
 use async_trait::async_trait;
 use futures::{stream::BoxStream, Stream, TryStreamExt};
 
-use reqwest::{Response, Url};
+use reqwest::Url;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::ResourceClient,
+    client::{HttpResponse, HttpTransport, ResourceClient},
     resource::{
         announcement::{sources::AnnouncementSource, AnnouncementResponse},
         cartoon::{CartoonPage, PagerDetail, PagerTop, Thumbnail, ThumbnailList},
         information::{AjaxAnnounceList, Announce, InformationPage},
     },
+    utils::Tz,
     Error, Page,
 };
 
@@ -25,18 +26,24 @@ pub struct ApiServer {
     pub id: String,
     pub url: Url,
     pub name: String,
+    /// Time zone of the server, default to the zone of the region
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub time_zone: Option<Tz>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ApiClient {
-    pub client: reqwest::Client,
+    pub client: HttpTransport,
     pub api_server: ApiServer,
+    /// Time zone of dates from the server
+    pub time_zone: Tz,
 }
 
 impl ApiClient {
-    async fn get_information_raw(&self, href: &str) -> Result<Response, Error> {
+    async fn get_information_raw(&self, href: &str) -> Result<HttpResponse, Error> {
         let url = self.api_server.url.join(href)?;
-        self.client.get(url).send().await.map_err(Error::from)
+        self.client.get(url).await
     }
 
     fn information_href(&self, announce_id: i32) -> String {
@@ -54,13 +61,14 @@ impl ApiClient {
         let href = self.information_href(announce_id);
         let response = self.get_information_raw(&href).await?;
         let url = response.url().clone();
-        let html = response.text().await?;
+        let html = response.text();
 
         let response = AnnouncementResponse {
             post_id: announce_id,
             source: AnnouncementSource::Api(self.api_server.id.clone()),
             url,
-            page: InformationPage::from_html(html)?,
+            page: InformationPage::from_html(html.clone())?,
+            html,
         };
 
         Ok(response)
@@ -71,14 +79,14 @@ impl ApiClient {
         self.get_information_raw(&href)
             .await?
             .json::<AjaxAnnounceList>()
-            .await
-            .map_err(Error::from)
     }
 
     pub async fn announce_list(&self, offset: i32) -> Result<Vec<Announce>, Error> {
         let ajax_announce = self.ajax_announce_list(offset).await?;
         let ajax_announce_list = ajax_announce.announce_list;
-        let announce_iter = ajax_announce_list.into_iter().map(Announce::from);
+        let announce_iter = ajax_announce_list
+            .into_iter()
+            .map(|announce| Announce::new(announce, self.time_zone));
         let announce_list = announce_iter.collect();
         Ok(announce_list)
     }
@@ -91,7 +99,7 @@ impl ApiClient {
                 ajax_announce
                     .announce_list
                     .into_iter()
-                    .map(Announce::from)
+                    .map(|announce| Announce::new(announce, self.time_zone))
                     .map(Ok)
             })
             .map_ok(futures::stream::iter)
@@ -121,9 +129,9 @@ async fn try_next_ajax(
 }
 
 impl ApiClient {
-    async fn cartoon_get(&self, href: &str) -> Result<Response, Error> {
+    async fn cartoon_get(&self, href: &str) -> Result<HttpResponse, Error> {
         let url = self.api_server.url.join(href)?;
-        self.client.get(url).send().await.map_err(Error::from)
+        self.client.get(url).await
     }
 
     fn cartoon_thumbnail_href(num: i32) -> String {
@@ -144,7 +152,7 @@ impl ApiClient {
 
     pub async fn thumbnail_list(&self, page: i32) -> Result<ThumbnailList, Error> {
         let href = Self::cartoon_thumbnail_href(page);
-        let result: ThumbnailList = self.cartoon_get(&href).await?.json().await?;
+        let result: ThumbnailList = self.cartoon_get(&href).await?.json()?;
 
         Ok(result)
     }
@@ -155,7 +163,7 @@ impl ApiClient {
         page_set: i32,
     ) -> Result<PagerTop, Error> {
         let href = Self::cartoon_pager_top_href(current_page_id, page_set);
-        let result = self.cartoon_get(&href).await?.json().await?;
+        let result = self.cartoon_get(&href).await?.json()?;
 
         Ok(result)
     }
@@ -166,14 +174,14 @@ impl ApiClient {
         page_set: i32,
     ) -> Result<PagerDetail, Error> {
         let href = Self::cartoon_pager_detail_href(current_page_id, page_set);
-        let result = self.cartoon_get(&href).await?.json().await?;
+        let result = self.cartoon_get(&href).await?.json()?;
 
         Ok(result)
     }
 
     pub async fn cartoon(&self, id: i32) -> Result<CartoonPage, Error> {
         let href = Self::cartoon_detail_href(id);
-        let html = self.cartoon_get(&href).await?.text().await?;
+        let html = self.cartoon_get(&href).await?.text();
 
         CartoonPage::from_html(html)
     }
@@ -203,6 +211,9 @@ impl ResourceClient<Announce> for ApiClient {
     fn try_stream(&self) -> BoxStream<Result<Announce, Error>> {
         Box::pin(self.announce_try_stream())
     }
+    fn time_zone(&self) -> Tz {
+        self.time_zone
+    }
     async fn get_by_id(&self, id: i32) -> Result<Self::Response, Error> {
         self.get_information(id).await
     }
@@ -222,6 +233,9 @@ impl ResourceClient<Thumbnail> for ApiClient {
     fn try_stream(&self) -> BoxStream<Result<Thumbnail, Error>> {
         Box::pin(self.thumbnail_try_stream())
     }
+    fn time_zone(&self) -> Tz {
+        self.time_zone
+    }
     async fn get_by_id(&self, id: i32) -> Result<Self::Response, Error> {
         self.cartoon(id).await
     }
@@ -2,9 +2,15 @@ mod page;
 pub mod service;
 pub use page::*;
 use reqwest::Url;
+use serde::Serialize;
 
-use crate::chat::{Message, Sendable};
+use crate::{
+    chat::{Message, Publication, Sendable},
+    resource::{Region, ResourceId},
+    template::Templates,
+};
 
+#[derive(Debug, Clone, Serialize)]
 pub struct Cartoon {
     pub id: i32,
     pub episode: String,
@@ -13,23 +19,49 @@ pub struct Cartoon {
 }
 
 impl Cartoon {
-    pub fn caption(&self) -> String {
-        format!(
-            "<b>第 {episode} 話</b>: {title}\n{image_src} <code>#{id}</code>",
-            episode = self.episode,
-            title = self.title,
-            image_src = self.image_src,
-            id = self.id
-        )
+    pub fn caption(&self, templates: &Templates) -> String {
+        templates.render_cartoon(self).text
     }
-}
 
-impl Sendable for Cartoon {
-    fn message(&self) -> Message {
+    /// Same as [`message`](Sendable::message), with the caption rendered by `templates`.
+    pub fn message_with(&self, templates: &Templates) -> Message {
+        let rendered = templates.render_cartoon(self);
         Message {
-            text: self.caption(),
+            text: rendered.text,
+            footer: rendered.footer,
             silent: false,
             image_src: Some(self.image_src.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// A cartoon to check templates.
+    pub(crate) fn sample() -> Self {
+        Self {
+            id: 280,
+            episode: "280".to_string(),
+            title: "公會小屋的大掃除".to_string(),
+            image_src: Url::parse("https://example.com/cartoon/280.png").unwrap(),
+        }
+    }
+}
+
+impl Sendable for Cartoon {
+    fn message(&self) -> Message {
+        self.message_with(&Templates::default())
+    }
+
+    fn publication(&self) -> Publication {
+        Publication {
+            resource_id: ResourceId::Cartoon(self.id),
+            title: format!("第 {} 話: {}", self.episode, self.title),
+            url: None,
+            tags: vec![],
+            events: vec![],
+            image: Some(self.image_src.clone()),
+            source: None,
+            region: Region::TW,
+            message: self.message(),
         }
     }
 }
@@ -1,4 +1,5 @@
 use crate::{
+    chat::{Preview, Sendable},
     client::{MemorizedResourceClient, MetadataFindResult, ResourceClient, ResourceResponse},
     resource::api::ApiClient,
     service::{PriconneService, ResourceService},
@@ -29,23 +30,41 @@ impl ResourceService<MetadataFindResult<Thumbnail>>
         priconne: &PriconneService,
         result: MetadataFindResult<Thumbnail>,
     ) -> Result<(), Error> {
-        let item = result.item();
-        let image_src = { self.client.fetch(item).await?.image_src };
-
-        let cartoon = Cartoon {
-            id: item.id,
-            episode: item.episode.clone(),
-            title: item.title.clone(),
-            image_src: Url::parse(&image_src)?,
-        };
-
-        priconne.chat_manager.send_cartoon(&cartoon).await?;
+        let cartoon = fetch_cartoon(&self.client, result.item()).await?;
+        priconne
+            .chat_manager
+            .send_cartoon(&cartoon, &priconne.templates)
+            .await?
+            .into_results()?;
 
         Ok(())
     }
-    fn dry_work(&self, metadata: MetadataFindResult<Thumbnail>) {
-        tracing::info!("dry_run: work cartoon {}", metadata.item().title)
+    async fn dry_work(
+        &self,
+        priconne: &PriconneService,
+        result: MetadataFindResult<Thumbnail>,
+    ) -> Result<(), Error> {
+        let cartoon = fetch_cartoon(&self.client, result.item()).await?;
+        let preview = Preview {
+            name: format!("cartoon-{}", cartoon.id),
+            title: cartoon.publication().title,
+            message: cartoon.message_with(&priconne.templates),
+            telegraph: None,
+            insight: None,
+        };
+        priconne.preview_writer().write(&preview).await
     }
 }
 
+async fn fetch_cartoon(client: &ApiClient, item: &Thumbnail) -> Result<Cartoon, Error> {
+    let image_src = { client.fetch(item).await?.image_src };
+
+    Ok(Cartoon {
+        id: item.id,
+        episode: item.episode.clone(),
+        title: item.title.clone(),
+        image_src: Url::parse(&image_src)?,
+    })
+}
+
 impl ResourceResponse for crate::resource::cartoon::CartoonPage {}
@@ -18,33 +18,50 @@ pub mod announcement;
 pub mod api;
 pub mod cartoon;
 pub mod glossary;
+pub mod post;
 use std::{fmt::Display, str::FromStr};
 
 pub use announcement::*;
 use mongodb::bson;
 
-use crate::utils::HOUR;
-use chrono::{DateTime, FixedOffset, Utc};
+use crate::utils::{midnight, Tz};
+use chrono::{DateTime, Utc};
 
+use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use cartoon::Thumbnail;
 use information::Announce;
 use news::News;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
 pub enum Region {
     JP,
     /// No more
     EN,
+    #[default]
     TW,
     CN,
     KR,
     TH,
 }
 
+impl Region {
+    /// Time zone of the game server in the region.
+    pub fn time_zone(&self) -> Tz {
+        match self {
+            Region::JP => chrono_tz::Asia::Tokyo,
+            Region::EN => chrono_tz::America::Los_Angeles,
+            Region::TW => chrono_tz::Asia::Taipei,
+            Region::CN => chrono_tz::Asia::Shanghai,
+            Region::KR => chrono_tz::Asia::Seoul,
+            Region::TH => chrono_tz::Asia::Bangkok,
+        }
+    }
+}
+
 /// Identifiers for resources
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ResourceId {
     Announcement(bson::oid::ObjectId),
     Cartoon(i32),
@@ -52,7 +69,7 @@ pub enum ResourceId {
 
 /// Kind of a resource, the difference from [`ResourceId`] is that
 /// this type does not have any fields.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ResourceKind {
     Information,
     News,
@@ -60,7 +77,7 @@ pub enum ResourceKind {
     Unknown,
 }
 
-const RESOURCE_KINDS: [ResourceKind; 3] = [
+pub(crate) const RESOURCE_KINDS: [ResourceKind; 3] = [
     ResourceKind::Information,
     ResourceKind::News,
     ResourceKind::Cartoon,
@@ -100,7 +117,9 @@ where
 {
     fn id(&self) -> i32;
     fn title(&self) -> &str;
-    fn update_time(&self) -> DateTime<Utc>;
+    /// Time of the last update. Metadata having only a local date takes its
+    /// midnight in `zone`, the time zone of the source.
+    fn update_time(&self, zone: Tz) -> DateTime<Utc>;
 
     /// The [`ResourceKind`] for this client
     /// This is required to not use a plain literial string
@@ -125,7 +144,7 @@ impl ResourceMetadata for Announce {
     fn title(&self) -> &str {
         &self.title.title
     }
-    fn update_time(&self) -> DateTime<Utc> {
+    fn update_time(&self, _zone: Tz) -> DateTime<Utc> {
         self.replace_time
     }
 }
@@ -142,13 +161,10 @@ impl ResourceMetadata for News {
         &self.title
     }
 
-    fn update_time(&self) -> DateTime<Utc> {
-        self.date
-            .and_hms_opt(0, 0, 0)
-            .unwrap()
-            .and_local_timezone(FixedOffset::east_opt(8 * HOUR).unwrap())
-            .unwrap()
-            .with_timezone(&Utc)
+    /// Midnight of the date in `zone`, the same as the create time of its
+    /// [page](news::NewsPage).
+    fn update_time(&self, zone: Tz) -> DateTime<Utc> {
+        midnight(self.date, zone).with_timezone(&Utc)
     }
 }
 
@@ -164,7 +180,7 @@ impl ResourceMetadata for Thumbnail {
         &self.title
     }
 
-    fn update_time(&self) -> DateTime<Utc> {
+    fn update_time(&self, _zone: Tz) -> DateTime<Utc> {
         // TODO
         Utc::now()
     }
@@ -184,8 +200,8 @@ where
         T::title(self)
     }
 
-    fn update_time(&self) -> DateTime<Utc> {
-        T::update_time(self)
+    fn update_time(&self, zone: Tz) -> DateTime<Utc> {
+        T::update_time(self, zone)
     }
 }
 
@@ -0,0 +1,521 @@
+//! Posts
+//!
+//! A [`Post`] is the view of an [`Announcement`] designed in `doc/post.md`:
+//! one post per article, whatever sources it was found in. Posts are stored
+//! under the [`POST_FIELD`] of the documents of announcements, see
+//! [`Post::store_in`], and rendered to a [`Message`] with [`Post::render`].
+
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use kuchikiki::{iter::NodeEdge, traits::TendrilSink, NodeRef};
+use mongodb::bson::{self, oid::ObjectId, Document};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use teloxide::{types::MessageId, utils::html::escape};
+use url::Url;
+
+use super::{
+    announcement::sources::AnnouncementSource, information::InformationPage, news::NewsPage,
+    Announcement, Region,
+};
+use crate::{
+    chat::{Message, TEXT_LIMIT},
+    insight::{summary, AnnouncementPage, EventInAnnouncement, Tags},
+    template::{AnnouncementContext, Templates},
+    Page, Result,
+};
+
+/// Maximum length of the summary, in characters.
+const SUMMARY_LIMIT: usize = 500;
+
+/// Key of the post in the document of its announcement.
+pub const POST_FIELD: &str = "post";
+
+/// Elements making a post not short, since they can't be sent inline.
+const MEDIA: &str = "img, video, audio, iframe, embed, object";
+
+/// How posts are rendered.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PostConfig {
+    /// Posts without media and shorter than this, in characters, are sent in
+    /// full instead of a summary. Set to 0 to always summarize.
+    pub full_text_limit: usize,
+    /// Show the versions of an announcement at the end of its Telegraph page
+    pub changelog: bool,
+}
+
+impl Default for PostConfig {
+    fn default() -> Self {
+        Self {
+            full_text_limit: 200,
+            changelog: false,
+        }
+    }
+}
+
+/// Where a post is found.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PostSource {
+    /// Announcement from an API server
+    Announce { api: String, id: i32 },
+    /// News on the official website
+    News { id: i32 },
+}
+
+impl PostSource {
+    pub fn new(source: &AnnouncementSource, id: i32) -> Self {
+        match source {
+            AnnouncementSource::Api(api) => PostSource::Announce {
+                api: api.clone(),
+                id,
+            },
+            AnnouncementSource::Website => PostSource::News { id },
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        match self {
+            PostSource::Announce { id, .. } | PostSource::News { id } => *id,
+        }
+    }
+
+    pub fn announcement_source(&self) -> AnnouncementSource {
+        match self {
+            PostSource::Announce { api, .. } => AnnouncementSource::Api(api.clone()),
+            PostSource::News { .. } => AnnouncementSource::Website,
+        }
+    }
+}
+
+impl Display for PostSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{}", self.announcement_source().name(), self.id())
+    }
+}
+
+/// An article, from one or more sources.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Post {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// Title of the latest data
+    pub title: String,
+    /// Mapped title for matching
+    pub mapped_title: String,
+    pub region: Region,
+    /// Sources, in the order they are found
+    pub sources: Vec<PostSource>,
+    /// The time when the post was created
+    #[serde_as(as = "mongodb::bson::DateTime")]
+    pub create_time: DateTime<Utc>,
+    /// The time when the post was updated, `None` if never
+    #[serde_as(as = "Option<mongodb::bson::DateTime>")]
+    pub update_time: Option<DateTime<Utc>>,
+    /// History post ID
+    pub history: Option<ObjectId>,
+    pub tags: Tags,
+    pub events: Vec<EventInAnnouncement>,
+    /// URL of the [Telegraph page](Announcement::telegraph)
+    pub telegraph: Option<String>,
+    /// Message ID in the post channel
+    pub message_id: Option<i32>,
+}
+
+/// Document of an announcement, read for its post only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPost {
+    pub post: Post,
+}
+
+impl From<&Announcement> for Post {
+    fn from(announcement: &Announcement) -> Self {
+        let mut sources = Vec::new();
+        for data in &announcement.data {
+            let source = PostSource::new(&data.source, data.id);
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+
+        let create_time = announcement
+            .data
+            .iter()
+            .find_map(|data| data.create_time)
+            .unwrap_or_else(|| announcement.id.timestamp().to_chrono());
+        let latest = announcement.data.last();
+        let update_time = latest
+            .and_then(|data| data.update_time)
+            .filter(|time| *time > create_time);
+
+        Post {
+            id: announcement.id,
+            title: latest.map(|data| data.title.clone()).unwrap_or_default(),
+            mapped_title: announcement.mapped_title.clone(),
+            region: announcement.region.clone(),
+            sources,
+            create_time,
+            update_time,
+            history: announcement.history,
+            tags: latest.map(|data| data.tags.clone()).unwrap_or_default(),
+            events: announcement.events.clone(),
+            telegraph: announcement
+                .telegraph
+                .as_ref()
+                .map(|page| page.url.clone())
+                .or_else(|| latest.and_then(|data| data.telegraph_url.clone())),
+            message_id: announcement.message_id,
+        }
+    }
+}
+
+impl Post {
+    /// Write the post into the `document` of its announcement, under
+    /// [`POST_FIELD`], so that it can be read as a [`StoredPost`].
+    pub fn store_in(&self, document: &mut Document) -> Result<()> {
+        document.insert(POST_FIELD, bson::to_document(self)?);
+        Ok(())
+    }
+
+    /// Title without the leading `【category】`.
+    pub fn message_title(&self) -> &str {
+        match self.title.strip_prefix('【') {
+            Some(rest) => rest
+                .split_once('】')
+                .map_or(&self.title, |(_, title)| title),
+            None => &self.title,
+        }
+    }
+
+    /// Render the message of the post with `templates`.
+    ///
+    /// `html` is the page the post is fetched from. Short posts are sent in
+    /// full, others with a summary taking the room left in the message.
+    /// Updated posts reply to the message sent before.
+    pub fn render(
+        &self,
+        url: Option<Url>,
+        html: Option<&str>,
+        config: &PostConfig,
+        templates: &Templates,
+    ) -> Message {
+        let render = |message: &mut Message| {
+            let rendered = templates.render_announcement(&AnnouncementContext::new(self, message));
+            message.text = rendered.text;
+            message.footer = rendered.footer;
+        };
+        let mut message = Message {
+            text: String::new(),
+            footer: String::new(),
+            silent: false,
+            image_src: None,
+            tags: self.tags.clone(),
+            title: self.message_title().to_string(),
+            summary: None,
+            content: None,
+            events: self.events.clone(),
+            telegraph: self.telegraph.clone(),
+            source: url,
+            create_time: Some(self.create_time),
+            sources: self.sources.clone(),
+            reply_to: self.update_time.and(self.message_id).map(MessageId),
+        };
+        render(&mut message);
+
+        let content = html.and_then(|html| {
+            let source = self.sources.last()?.announcement_source();
+            page_content(&source, html)
+        });
+        let Some(content) = content else {
+            return message;
+        };
+
+        // Blank line around the summary or content
+        let room = TEXT_LIMIT.saturating_sub(text_length(&message.text) + 2);
+        message.content = full_text(&content, message.source.as_ref(), config)
+            .filter(|full| text_length(full) <= room);
+        if message.content.is_none() {
+            let sections = summary::get_sections(&content);
+            message.summary = summary::summarize(&sections, room.min(SUMMARY_LIMIT));
+        }
+        render(&mut message);
+        message
+    }
+}
+
+/// Content node of the page fetched from `source`.
+fn page_content(source: &AnnouncementSource, html: &str) -> Option<NodeRef> {
+    let content = match source {
+        AnnouncementSource::Website => NewsPage::from_html(html.to_string()).ok()?.content(),
+        AnnouncementSource::Api(_) => InformationPage::from_html(html.to_string()).ok()?.content(),
+    };
+    Some(content)
+}
+
+/// The content in Telegram HTML if the post is short and without media.
+fn full_text(content: &NodeRef, base: Option<&Url>, config: &PostConfig) -> Option<String> {
+    if content.select_first(MEDIA).is_ok() {
+        return None;
+    }
+    let length = content
+        .text_contents()
+        .split_whitespace()
+        .map(|word| word.chars().count())
+        .sum::<usize>();
+    if length == 0 || length > config.full_text_limit {
+        return None;
+    }
+    Some(telegram_html(content, base))
+}
+
+/// Convert `content` to HTML supported by Telegram, keeping bold, italic,
+/// underline, strikethrough, links and line breaks.
+fn telegram_html(content: &NodeRef, base: Option<&Url>) -> String {
+    let mut html = String::new();
+    for edge in content.traverse() {
+        match edge {
+            NodeEdge::Start(node) => {
+                if let Some(text) = node.as_text() {
+                    summary::push_text(&mut html, &escape(&text.borrow()));
+                    continue;
+                }
+                let Some(element) = node.as_element() else {
+                    continue;
+                };
+                match &*element.name.local {
+                    "br" => html.push('\n'),
+                    "li" => {
+                        new_line(&mut html);
+                        html.push('・');
+                    }
+                    "a" => {
+                        if let Some(href) = link(&element.attributes.borrow(), base) {
+                            html.push_str(&format!("<a href=\"{}\">", escape(href.as_str())));
+                        }
+                    }
+                    name => match inline_tag(name) {
+                        Some(tag) => html.push_str(&format!("<{tag}>")),
+                        None if summary::BLOCKS.contains(&name) => new_line(&mut html),
+                        None => {}
+                    },
+                }
+            }
+            NodeEdge::End(node) => {
+                let Some(element) = node.as_element() else {
+                    continue;
+                };
+                match &*element.name.local {
+                    "br" => {}
+                    "a" => {
+                        if link(&element.attributes.borrow(), base).is_some() {
+                            html.push_str("</a>");
+                        }
+                    }
+                    name => match inline_tag(name) {
+                        Some(tag) => html.push_str(&format!("</{tag}>")),
+                        None if summary::BLOCKS.contains(&name) => new_line(&mut html),
+                        None => {}
+                    },
+                }
+            }
+        }
+    }
+
+    // At most one blank line in a row
+    let mut text = String::new();
+    let mut blank = false;
+    for line in html.lines().map(str::trim) {
+        if line.is_empty() {
+            blank = !text.is_empty();
+            continue;
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        if blank {
+            text.push('\n');
+            blank = false;
+        }
+        text.push_str(line);
+    }
+    text
+}
+
+/// Start a new line unless already at one.
+fn new_line(html: &mut String) {
+    if !html.is_empty() && !html.ends_with('\n') {
+        html.push('\n');
+    }
+}
+
+/// Telegram tag of an inline element.
+fn inline_tag(name: &str) -> Option<&'static str> {
+    match name {
+        "b" | "strong" => Some("b"),
+        "i" | "em" => Some("i"),
+        "u" | "ins" => Some("u"),
+        "s" | "strike" | "del" => Some("s"),
+        _ => None,
+    }
+}
+
+/// Absolute HTTP URL of a link.
+fn link(attributes: &kuchikiki::Attributes, base: Option<&Url>) -> Option<Url> {
+    let href = attributes.get("href")?;
+    let url = match base {
+        Some(base) => base.join(href).ok()?,
+        None => Url::parse(href).ok()?,
+    };
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+/// Length of HTML `text` as counted by Telegram, that is, without tags.
+fn text_length(text: &str) -> usize {
+    let stripped = kuchikiki::parse_html().one(text).text_contents();
+    stripped.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::insight::AnnouncementInsight;
+
+    fn insight(source: AnnouncementSource, id: i32, day: u32) -> AnnouncementInsight<()> {
+        AnnouncementInsight {
+            title: "【轉蛋】《公主祭典 獎勵轉蛋》★3「蘭法」期間限定角色登場！".to_string(),
+            source,
+            id,
+            url: url::Url::parse("http://www.princessconnect.so-net.tw/news/newsDetail/1774")
+                .unwrap(),
+            tags: ["轉蛋", "公主祭典"].into_iter().map(String::from).collect(),
+            create_time: Some(Utc.with_ymd_and_hms(2022, 7, day, 3, 55, 0).unwrap()),
+            update_time: Some(Utc.with_ymd_and_hms(2022, 7, day, 3, 55, 0).unwrap()),
+            telegraph_url: Some("https://telegra.ph/post-07-01".to_string()),
+            events: vec![EventInAnnouncement {
+                start: Utc.with_ymd_and_hms(2022, 7, 2, 0, 0, 0).unwrap(),
+                end: Utc.with_ymd_and_hms(2022, 7, 4, 23, 59, 0).unwrap(),
+                title: "公主祭典 獎勵轉蛋".to_string(),
+                pattern: None,
+            }],
+            extra: (),
+        }
+    }
+
+    #[test]
+    fn test_from_announcement() {
+        let api = AnnouncementSource::Api("PROD3".to_string());
+        let mut announcement = Announcement::new(insight(api.clone(), 1803, 1), None);
+        announcement.push(insight(AnnouncementSource::Website, 1774, 1));
+        announcement.message_id = Some(1800);
+
+        let post = Post::from(&announcement);
+        assert_eq!(
+            post.sources,
+            vec![
+                PostSource::Announce {
+                    api: "PROD3".to_string(),
+                    id: 1803
+                },
+                PostSource::News { id: 1774 },
+            ]
+        );
+        assert_eq!(
+            post.create_time,
+            Utc.with_ymd_and_hms(2022, 7, 1, 3, 55, 0).unwrap()
+        );
+        assert_eq!(post.update_time, None);
+        assert_eq!(post.message_id, Some(1800));
+        assert_eq!(
+            post.message_title(),
+            "《公主祭典 獎勵轉蛋》★3「蘭法」期間限定角色登場！"
+        );
+
+        let document = bson::to_document(&post).unwrap();
+        let sources = document.get_array("sources").unwrap();
+        assert_eq!(
+            sources[0].as_document().unwrap().get_str("type").unwrap(),
+            "Announce"
+        );
+
+        announcement.push(insight(AnnouncementSource::Website, 1774, 3));
+        let post = Post::from(&announcement);
+        assert_eq!(post.sources.len(), 2);
+        assert!(post.update_time.is_some());
+    }
+
+    #[test]
+    fn test_render() {
+        let html = std::fs::read_to_string("tests/news_page.html").unwrap();
+        let mut announcement =
+            Announcement::new(insight(AnnouncementSource::Website, 1774, 1), None);
+        let post = Post::from(&announcement);
+
+        let templates = Templates::default();
+        let message = post.render(None, Some(&html), &PostConfig::default(), &templates);
+        assert!(message
+            .summary
+            .as_ref()
+            .unwrap()
+            .starts_with("・角色交換Pt共通的轉蛋："));
+        assert!(message
+            .text
+            .contains("\n・贈品內容：克蘿依（聖學祭）的記憶碎片×100\n"));
+        assert!(text_length(&message.text) <= TEXT_LIMIT);
+        assert_eq!(message.reply_to, None);
+        assert!(message.text.starts_with(
+            "#轉蛋 #公主祭典\n<b>《公主祭典 獎勵轉蛋》★3「蘭法」期間限定角色登場！</b>\n"
+        ));
+        assert!(message
+            .text
+            .contains("- 公主祭典 獎勵轉蛋: \n   07/02 08:00 - 07/05 07:59 (UTC+08:00)\n"));
+        assert!(message.text.ends_with(
+            "https://telegra.ph/post-07-01\n2022/07/01 11:55 (UTC+08:00) <code>news#1774</code>"
+        ));
+
+        assert_eq!(message.content, None);
+
+        announcement.message_id = Some(1800);
+        announcement.push(insight(AnnouncementSource::Website, 1774, 3));
+        let message =
+            Post::from(&announcement).render(None, None, &PostConfig::default(), &templates);
+        assert_eq!(message.summary, None);
+        assert_eq!(message.reply_to, Some(MessageId(1800)));
+    }
+
+    #[test]
+    fn test_full_text() {
+        let base = Url::parse("http://www.princessconnect.so-net.tw/news/newsDetail/1774").unwrap();
+        let config = PostConfig::default();
+        let content = kuchikiki::parse_html().one(
+            r#"<div>感謝各位騎士君的<strong>支持</strong>&amp;愛護！<br />
+            詳情請見<a href="/news/1775">公告</a>。<br /><br /><br />
+            <ul><li>補償：<span>寶石×500</span></li></ul>
+            <a href="javascript:void(0)">無效連結</a></div>"#,
+        );
+
+        assert_eq!(
+            full_text(&content, Some(&base), &config).unwrap(),
+            "感謝各位騎士君的<b>支持</b>&amp;愛護！\n\
+             詳情請見<a href=\"http://www.princessconnect.so-net.tw/news/1775\">公告</a>。\n\
+             \n\
+             ・補償：寶石×500\n\
+             無效連結"
+        );
+        let disabled = PostConfig {
+            full_text_limit: 0,
+            ..Default::default()
+        };
+        assert_eq!(full_text(&content, Some(&base), &disabled), None);
+
+        let long = kuchikiki::parse_html().one(format!("<p>{}</p>", "公主".repeat(101)));
+        assert_eq!(full_text(&long, None, &config), None);
+        let image = kuchikiki::parse_html().one("<p>新角色登場！<img src=\"a.png\"></p>");
+        assert_eq!(full_text(&image, None, &config), None);
+    }
+}
@@ -0,0 +1,509 @@
+//! Full-text search
+//!
+//! A small embedded inverted index over the content of fetched pages. Text is
+//! split into character bigrams for CJK, and into words for other scripts, so
+//! that queries like `公主祭典` match without a dictionary.
+//!
+//! Documents are kept in memory, and written to `<path>/documents.json` on
+//! [`commit`](SearchIndex::commit), once per batch of fetched resources. The
+//! postings are rebuilt when opened. The index can be rebuilt from the
+//! [page archive](crate::archive) with
+//! [`PriconneService::rebuild_search_index`].
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html::escape;
+use url::Url;
+
+use crate::{
+    insight::AnnouncementPage,
+    resource::announcement::{sources::AnnouncementSource, AnnouncementResponse},
+    Error, PriconneService, Result,
+};
+
+/// Weight of tokens in the title, relative to the content.
+const TITLE_WEIGHT: f32 = 3.0;
+/// Characters of context around the first match in snippets.
+const SNIPPET_BEFORE: usize = 30;
+const SNIPPET_LENGTH: usize = 120;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SearchConfig {
+    /// Directory of the index, kept in memory if not set
+    pub path: Option<PathBuf>,
+}
+
+/// A page in the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchDocument {
+    pub source: AnnouncementSource,
+    pub post_id: i32,
+    /// Announcement containing the page, as hex
+    pub announcement_id: Option<String>,
+    pub title: String,
+    pub url: Url,
+    /// Normalized text content
+    pub content: String,
+    pub fetch_time: DateTime<Utc>,
+}
+
+impl SearchDocument {
+    pub fn new<P: AnnouncementPage>(
+        response: &AnnouncementResponse<P>,
+        announcement_id: Option<ObjectId>,
+        fetch_time: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            source: response.source.clone(),
+            post_id: response.post_id,
+            announcement_id: announcement_id.map(|id| id.to_hex()),
+            title: response.page.title(),
+            url: response.url.clone(),
+            content: normalize_text(&response.page.content().text_contents()),
+            fetch_time,
+        }
+    }
+
+    fn key(&self) -> (AnnouncementSource, i32) {
+        (self.source.clone(), self.post_id)
+    }
+}
+
+/// A search result.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub source: AnnouncementSource,
+    pub post_id: i32,
+    pub announcement_id: Option<String>,
+    pub title: String,
+    pub url: Url,
+    pub fetch_time: DateTime<Utc>,
+    pub score: f32,
+    /// Part of the content around the match, in HTML with matches in `<b>`
+    pub snippet: String,
+}
+
+/// Collapse whitespace, so that text from HTML reads as one paragraph.
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}' // Hangul
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2a6df}')
+}
+
+/// Lowercase, and convert full-width ASCII to half-width.
+fn normalize_char(c: char) -> char {
+    let c = match c {
+        '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+        c => c,
+    };
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Split `text` into tokens: bigrams of CJK runs, or the character itself
+/// for a single CJK character, and words of other letters and digits.
+///
+/// With `unigrams`, every CJK character is also a token, so that
+/// single-character queries match documents.
+fn split_tokens(text: &str, unigrams: bool) -> Vec<String> {
+    fn flush_cjk(run: &mut Vec<char>, tokens: &mut Vec<String>, unigrams: bool) {
+        if run.len() == 1 || unigrams {
+            tokens.extend(run.iter().map(|c| c.to_string()));
+        }
+        tokens.extend(run.windows(2).map(|w| w.iter().collect()));
+        run.clear();
+    }
+    fn flush_word(word: &mut String, tokens: &mut Vec<String>) {
+        if !word.is_empty() {
+            tokens.push(std::mem::take(word));
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut run = Vec::new();
+    let mut word = String::new();
+    for c in text.chars().map(normalize_char) {
+        if is_cjk(c) {
+            flush_word(&mut word, &mut tokens);
+            run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_cjk(&mut run, &mut tokens, unigrams);
+            word.push(c);
+        } else {
+            flush_word(&mut word, &mut tokens);
+            flush_cjk(&mut run, &mut tokens, unigrams);
+        }
+    }
+    flush_word(&mut word, &mut tokens);
+    flush_cjk(&mut run, &mut tokens, unigrams);
+    tokens
+}
+
+/// Tokens of a query.
+pub fn tokenize(text: &str) -> Vec<String> {
+    split_tokens(text, false)
+}
+
+#[derive(Debug, Default)]
+struct IndexData {
+    documents: Vec<SearchDocument>,
+    keys: HashMap<(AnnouncementSource, i32), usize>,
+    /// Weight of each token in each document
+    postings: HashMap<String, HashMap<usize, f32>>,
+}
+
+impl IndexData {
+    fn weights(document: &SearchDocument) -> HashMap<String, f32> {
+        let mut weights = HashMap::new();
+        for token in split_tokens(&document.title, true) {
+            *weights.entry(token).or_default() += TITLE_WEIGHT;
+        }
+        for token in split_tokens(&document.content, true) {
+            *weights.entry(token).or_default() += 1.0;
+        }
+        weights
+    }
+
+    fn add(&mut self, document: SearchDocument) {
+        let index = match self.keys.get(&document.key()) {
+            Some(&index) => {
+                for token in Self::weights(&self.documents[index]).into_keys() {
+                    if let Some(posting) = self.postings.get_mut(&token) {
+                        posting.remove(&index);
+                    }
+                }
+                index
+            }
+            None => {
+                self.keys.insert(document.key(), self.documents.len());
+                self.documents.push(document.clone());
+                self.documents.len() - 1
+            }
+        };
+
+        for (token, weight) in Self::weights(&document) {
+            self.postings
+                .entry(token)
+                .or_default()
+                .insert(index, weight);
+        }
+        self.documents[index] = document;
+    }
+}
+
+/// Full-text index of pages.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    path: Option<PathBuf>,
+    data: RwLock<IndexData>,
+    /// Whether documents changed since the last commit
+    changed: AtomicBool,
+}
+
+impl SearchIndex {
+    /// Open the index in `path`, creating it if not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)?;
+
+        let mut data = IndexData::default();
+        let file = path.join("documents.json");
+        if file.exists() {
+            let documents: Vec<SearchDocument> =
+                serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            for document in documents {
+                data.add(document);
+            }
+        }
+
+        Ok(Self {
+            path: Some(path),
+            data: RwLock::new(data),
+            changed: AtomicBool::new(false),
+        })
+    }
+
+    /// An index only kept in memory.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Add a document, replacing the one from the same source and id.
+    /// It is saved on the next [`commit`](Self::commit).
+    pub fn add(&self, document: SearchDocument) {
+        self.data.write().unwrap().add(document);
+        self.changed.store(true, Ordering::Release);
+    }
+
+    /// Remove all documents.
+    pub fn clear(&self) {
+        *self.data.write().unwrap() = IndexData::default();
+        self.changed.store(true, Ordering::Release);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.read().unwrap().documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write documents to disk, if they changed since the last commit.
+    pub fn commit(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let data = self.data.read().unwrap();
+        let temp = path.join("documents.json.tmp");
+        std::fs::write(&temp, serde_json::to_string(&data.documents)?)?;
+        std::fs::rename(temp, path.join("documents.json"))?;
+        Ok(())
+    }
+
+    /// Documents containing every token of `query`, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let mut tokens = tokenize(query);
+        tokens.sort();
+        tokens.dedup();
+        if tokens.is_empty() {
+            return vec![];
+        }
+
+        let data = self.data.read().unwrap();
+        let total = data.documents.len() as f32;
+        let mut scores: Option<HashMap<usize, f32>> = None;
+        for token in &tokens {
+            let Some(posting) = data.postings.get(token) else {
+                return vec![];
+            };
+            let idf = (1.0 + total / posting.len() as f32).ln();
+            scores = Some(match scores {
+                None => posting.iter().map(|(&i, &w)| (i, w * idf)).collect(),
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(i, score)| Some((i, score + posting.get(&i)? * idf)))
+                    .collect(),
+            });
+        }
+
+        let mut scores: Vec<_> = scores.unwrap_or_default().into_iter().collect();
+        scores.sort_by(|(a, a_score), (b, b_score)| {
+            b_score.total_cmp(a_score).then(
+                data.documents[*b]
+                    .fetch_time
+                    .cmp(&data.documents[*a].fetch_time),
+            )
+        });
+
+        scores
+            .into_iter()
+            .take(limit)
+            .map(|(i, score)| {
+                let document = &data.documents[i];
+                SearchHit {
+                    source: document.source.clone(),
+                    post_id: document.post_id,
+                    announcement_id: document.announcement_id.clone(),
+                    title: document.title.clone(),
+                    url: document.url.clone(),
+                    fetch_time: document.fetch_time,
+                    score,
+                    snippet: snippet(&document.content, query),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Part of `content` around the first term of `query`, with terms in `<b>`.
+pub fn snippet(content: &str, query: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|&c| normalize_char(c)).collect();
+    let terms: Vec<Vec<char>> = query
+        .split_whitespace()
+        .map(|term| term.chars().map(normalize_char).collect())
+        .collect();
+
+    let matches_at = |i: usize| {
+        terms
+            .iter()
+            .find(|term| lower[i..].starts_with(term))
+            .map(|term| term.len())
+    };
+
+    let first = (0..lower.len()).find(|&i| matches_at(i).is_some());
+    let start = first.map_or(0, |i| i.saturating_sub(SNIPPET_BEFORE));
+    let end = (start + SNIPPET_LENGTH).min(chars.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut plain = String::new();
+    let mut i = start;
+    while i < end {
+        match matches_at(i) {
+            Some(len) => {
+                let len = len.min(end - i);
+                snippet.push_str(&escape(&std::mem::take(&mut plain)));
+                let matched: String = chars[i..i + len].iter().collect();
+                snippet.push_str(&format!("<b>{}</b>", escape(&matched)));
+                i += len;
+            }
+            None => {
+                plain.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    snippet.push_str(&escape(&plain));
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+impl PriconneService {
+    /// Clear the search index, and add the latest archived page of every resource.
+    pub async fn rebuild_search_index(&self) -> Result<usize> {
+        let announcements = self.storage.announcements()?;
+
+        self.search.clear();
+        // Pages are sorted by fetch time, newer ones replace older ones
+        for page in self.page_archive()?.all().await? {
+            let announcement_id = announcements
+                .find_by_source(&page.source, page.post_id)
+                .await?
+                .map(|announcement| announcement.id);
+            match page.search_document(announcement_id) {
+                Ok(document) => self.search.add(document),
+                Err(e) => tracing::error!("failed to index {} #{}: {e}", page.source, page.post_id),
+            }
+        }
+        self.commit_search().await?;
+
+        Ok(self.search.len())
+    }
+
+    /// [`Commit`](SearchIndex::commit) the search index off the async runtime.
+    pub async fn commit_search(&self) -> Result<()> {
+        let search = self.search.clone();
+        tokio::task::spawn_blocking(move || search.commit())
+            .await
+            .map_err(|e| Error::SearchIndexError(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(post_id: i32, title: &str, content: &str) -> SearchDocument {
+        SearchDocument {
+            source: AnnouncementSource::Website,
+            post_id,
+            announcement_id: None,
+            title: title.to_string(),
+            url: Url::parse(&format!(
+                "http://www.princessconnect.so-net.tw/news/newsDetail/{post_id}"
+            ))
+            .unwrap(),
+            content: content.to_string(),
+            fetch_time: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("公主祭典"), vec!["公主", "主祭", "祭典"]);
+        assert_eq!(tokenize("蘭"), vec!["蘭"]);
+        assert_eq!(tokenize("ＥＸＰ 1.5倍"), vec!["exp", "1", "5", "倍"]);
+        assert_eq!(split_tokens("祭典", true), vec!["祭", "典", "祭典"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let index = SearchIndex::in_memory();
+        index.add(document(
+            1,
+            "【轉蛋】公主祭典",
+            "期間限定角色登場！公主祭典舉辦中。",
+        ));
+        index.add(document(
+            2,
+            "【活動】戰隊競賽",
+            "戰隊競賽將於近日舉辦，獎勵包含公主寶石。",
+        ));
+        index.add(document(3, "【維護】停機維護", "維護期間無法遊玩。"));
+
+        let ids = |hits: Vec<SearchHit>| hits.iter().map(|hit| hit.post_id).collect::<Vec<_>>();
+        assert_eq!(ids(index.search("公主祭典", 10)), vec![1]);
+        // The title weighs more than content
+        assert_eq!(ids(index.search("公主", 10)), vec![1, 2]);
+        assert_eq!(ids(index.search("舉辦", 10)).len(), 2);
+        assert!(index.search("蘭法", 10).is_empty());
+        assert!(index.search("", 10).is_empty());
+
+        // Replaced by the same source and id
+        index.add(document(3, "【維護】停機維護", "維護已結束。"));
+        assert_eq!(index.len(), 3);
+        assert!(index.search("遊玩", 10).is_empty());
+        assert_eq!(ids(index.search("結束", 10)), vec![3]);
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(
+            snippet("期間限定角色登場！<公主祭典>舉辦中。", "公主祭典"),
+            "期間限定角色登場！&lt;<b>公主祭典</b>&gt;舉辦中。"
+        );
+        let content = format!("{}EXP{}", "前".repeat(40), "後".repeat(200));
+        let snippet = snippet(&content, "exp");
+        assert!(snippet.starts_with(&format!("…{}<b>EXP</b>", "前".repeat(SNIPPET_BEFORE))));
+        assert!(snippet.ends_with('…'));
+    }
+
+    #[test]
+    fn test_persistence() -> Result<()> {
+        let path = std::env::temp_dir().join("priconne-test-search");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let index = SearchIndex::open(&path)?;
+        index.add(document(1, "【轉蛋】公主祭典", "公主祭典舉辦中。"));
+        index.commit()?;
+
+        let index = SearchIndex::open(&path)?;
+        assert_eq!(index.search("祭典", 10).len(), 1);
+
+        // Nothing changed since opened, nothing is written
+        std::fs::remove_file(path.join("documents.json"))?;
+        index.commit()?;
+        assert!(!path.join("documents.json").exists());
+
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+}
@@ -4,24 +4,29 @@
 //! this service layer is responsible for managing the resources, continuously
 //! fetching and parsing data, and sending messages using [`ChatManager`].
 
-use std::sync::Arc;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use async_trait::async_trait;
-use futures::TryStreamExt;
-use mongodb::bson::doc;
+use tokio::sync::Mutex;
 use tokio_cron_scheduler::{Job, JobScheduler};
 
 use crate::{
-    chat::ChatManager,
+    chat::{ChatManager, PreviewWriter},
     client::ResourceClient,
     config::FetchConfig,
-    database::AnnouncementCollection,
+    database::Storage,
+    image::{ImageHost, ImageStoreConfig},
     insight::Extractor,
-    resource::{api::ApiClient, event::Event, news::service::NewsClient, ResourceKind},
+    reminder::{ReminderConfig, ReminderScheduler},
+    resource::{
+        api::ApiClient, event::Event, news::service::NewsClient, sources::AnnouncementSource,
+        ResourceKind, RESOURCE_KINDS,
+    },
+    search::SearchIndex,
+    template::Templates,
     Result,
 };
 
-// TODO: We may need to housekeeping the database.
 /// Resource collection is generalized to two steps, as in this trait.
 ///
 /// 1. Get metadata from remote
@@ -36,8 +41,34 @@ pub trait ResourceService<M> {
     where
         M: 'async_trait;
     // TODO: Make it a trait on M?
-    /// Dry run
-    fn dry_work(&self, metadata: M);
+    /// Dry run, go through the same pipeline as [`work`](ResourceService::work),
+    /// but write a [`Preview`](crate::chat::Preview) instead of publishing.
+    /// It must not touch the database or Telegraph.
+    async fn dry_work(&self, priconne: &PriconneService, metadata: M) -> Result<()>
+    where
+        M: 'async_trait;
+}
+
+/// Kind of a job in [`FetchConfig::schedule`], either fetching a resource,
+/// posting the [daily digest](crate::digest), or checking
+/// [maintenance](crate::maintenance).
+#[derive(Debug, Clone)]
+pub enum JobKind {
+    Resource(ResourceKind),
+    Digest,
+    Maintenance,
+}
+
+impl FromStr for JobKind {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "digest" => Ok(JobKind::Digest),
+            "maintenance" => Ok(JobKind::Maintenance),
+            s => Ok(JobKind::Resource(s.parse()?)),
+        }
+    }
 }
 
 // TODO: Since these values are cloned, we may want to use `Arc` instead. Or their mutation may not be reflected.
@@ -57,29 +88,58 @@ pub trait ResourceService<M> {
 ///
 #[derive(Clone)]
 pub struct PriconneService {
-    pub database: mongodb::Database,
+    pub storage: Storage,
     pub telegraph: telegraph_rs::Telegraph,
 
     pub client: reqwest::Client,
     pub config: FetchConfig,
     pub extractor: Extractor,
     pub chat_manager: Arc<ChatManager>,
+    pub reminders: Arc<ReminderScheduler>,
+    pub search: Arc<SearchIndex>,
+    pub images: Arc<ImageHost>,
+    pub templates: Arc<Templates>,
+    /// Held while fetching a kind of resource, so that runs of the same kind,
+    /// like the scheduled one and the one during maintenance, do not overlap
+    runs: Arc<HashMap<ResourceKind, Mutex<()>>>,
 }
 
 impl PriconneService {
     pub fn new(
-        database: mongodb::Database,
+        storage: Storage,
         chat_manager: ChatManager,
         telegraph: telegraph_rs::Telegraph,
         client: reqwest::Client,
         config: FetchConfig,
         extractor: Extractor,
+        reminders: Vec<ReminderConfig>,
     ) -> Result<PriconneService> {
         let chat_manager = Arc::new(chat_manager);
+        let reminders = Arc::new(ReminderScheduler::new(
+            reminders,
+            storage.clone(),
+            chat_manager.clone(),
+        ));
+
+        let images = Arc::new(ImageHost::new(
+            client.clone(),
+            ImageStoreConfig::default(),
+            storage.images()?,
+        ));
 
         Ok(Self {
-            database,
+            storage,
             chat_manager,
+            reminders,
+            search: Arc::new(SearchIndex::in_memory()),
+            images,
+            templates: Arc::new(Templates::default()),
+            runs: Arc::new(
+                RESOURCE_KINDS
+                    .into_iter()
+                    .map(|kind| (kind, Mutex::new(())))
+                    .collect(),
+            ),
             extractor,
             telegraph,
             client,
@@ -87,54 +147,94 @@ impl PriconneService {
         })
     }
 
-    pub async fn serve_and_work<M>(&self, service: impl ResourceService<M>) -> Result<()> {
-        let latests = service.collect_latests(self).await;
+    /// Use `search` as the full-text index, instead of an in-memory one.
+    pub fn with_search(mut self, search: SearchIndex) -> Self {
+        self.search = Arc::new(search);
+        self
+    }
 
-        for result in latests? {
-            if self.config.dry_run {
-                service.dry_work(result);
-            } else {
-                service.work(self, result).await?;
+    /// Use `images` to re-host images, instead of uploading them to Telegraph.
+    pub fn with_images(mut self, images: ImageHost) -> Self {
+        self.images = Arc::new(images);
+        self
+    }
+
+    /// Render messages with `templates`, instead of the builtin ones.
+    pub fn with_templates(mut self, templates: Templates) -> Self {
+        self.templates = Arc::new(templates);
+        self
+    }
+
+    pub async fn serve_and_work<M>(&self, service: impl ResourceService<M>) -> Result<()> {
+        let latests = service.collect_latests(self).await?;
+
+        let worked = async {
+            for result in latests {
+                if self.config.dry_run {
+                    service.dry_work(self, result).await?;
+                } else {
+                    service.work(self, result).await?;
+                }
             }
+            Ok(())
         }
-        Ok(())
+        .await;
+
+        // Keep documents indexed before a failure
+        self.commit_search().await?;
+        worked
+    }
+
+    /// Where to write previews in dry runs
+    pub fn preview_writer(&self) -> PreviewWriter {
+        PreviewWriter::new(self.config.preview_dir.clone())
     }
 
     fn build_api_client(&self) -> ApiClient {
+        let api_server = self.config.server.api[0].clone();
         ApiClient {
-            client: self.client.clone(),
-            api_server: self.config.server.api[0].clone(),
+            client: self.config.transport.build(self.client.clone()),
+            time_zone: self
+                .config
+                .server
+                .time_zone(&AnnouncementSource::Api(api_server.id.clone())),
+            api_server,
         }
     }
 
     fn build_news_client(&self) -> NewsClient {
         NewsClient {
-            client: self.client.clone(),
+            client: self.config.transport.build(self.client.clone()),
             server: self.config.server.news.clone(),
+            time_zone: self.config.server.time_zone(&AnnouncementSource::Website),
         }
     }
 
     pub async fn run_service(&self, kind: ResourceKind) -> Result<()> {
+        let _run = match self.runs.get(&kind) {
+            Some(run) => Some(run.lock().await),
+            None => None,
+        };
         match kind {
             ResourceKind::Information => {
                 let api_client = self.build_api_client().memorize(
-                    self.database
-                        .collection::<crate::resource::information::Announce>(&kind.to_string()),
+                    self.storage
+                        .metadata::<crate::resource::information::Announce>(&kind.to_string())?,
                     self.config.strategy.build_for(kind),
                 );
                 self.serve_and_work(api_client).await?
             }
             ResourceKind::News => {
                 let news_client = self.build_news_client().memorize(
-                    self.database.collection(&kind.to_string()),
+                    self.storage.metadata(&kind.to_string())?,
                     self.config.strategy.build_for(kind),
                 );
                 self.serve_and_work(news_client).await?
             }
             ResourceKind::Cartoon => {
                 let api_client = self.build_api_client().memorize(
-                    self.database
-                        .collection::<crate::resource::cartoon::Thumbnail>(&kind.to_string()),
+                    self.storage
+                        .metadata::<crate::resource::cartoon::Thumbnail>(&kind.to_string())?,
                     self.config.strategy.build_for(kind),
                 );
                 self.serve_and_work(api_client).await?
@@ -149,38 +249,18 @@ impl PriconneService {
     /// List all incoming events
     /// event collection.
     pub async fn incomming_events(&self) -> Result<Vec<Event>> {
-        let collection = AnnouncementCollection(self.database.collection("announcement")).0;
-        let announcements = collection
-            .find(
-                doc! {
-                    "events.end": {
-                        "$gt": chrono::Utc::now() - chrono::Duration::days(2)
-                    }
-                },
-                None,
-            )
-            .await?;
-
-        Ok(announcements
-            .try_filter_map(|a| async move {
-                Ok(Some(
-                    a.events
-                        .into_iter()
-                        .map(move |e| {
-                            Event {
-                                start: e.start,
-                                end: e.end,
-                                kind: crate::resource::event::EventKind::Other, // TODO: This is a placeholder
-                                title: e.title,
-                                announcement_id: a.id,
-                                announcement_title: a.data.last().unwrap().title.clone(),
-                            }
-                        })
-                        .collect::<Vec<_>>(),
-                ))
-            })
-            .try_concat()
-            .await?)
+        self.storage
+            .events()?
+            .events_end_after(chrono::Utc::now() - chrono::Duration::days(2))
+            .await
+    }
+
+    pub async fn run_job(&self, kind: JobKind) -> Result<()> {
+        match kind {
+            JobKind::Resource(kind) => self.run_service(kind).await,
+            JobKind::Digest => self.send_digest().await,
+            JobKind::Maintenance => self.check_maintenance().await,
+        }
     }
 
     /// Build scheduler
@@ -188,7 +268,7 @@ impl PriconneService {
         let priconne = self;
 
         for (kind, cron) in priconne.config.schedule.iter() {
-            let kind: ResourceKind = kind.parse()?;
+            let kind: JobKind = kind.parse()?;
             // First clone (1): provide `priconne` for the following closure
             let priconne = priconne.clone();
             let run = move |_uuid: uuid::Uuid, _lock: JobScheduler| -> std::pin::Pin<Box<dyn futures::Future<Output = ()> + Send>> {
@@ -199,7 +279,7 @@ impl PriconneService {
                 let priconne = priconne.clone();
                 Box::pin(async move {
                     priconne
-                        .run_service(kind)
+                        .run_job(kind)
                         .await
                         .map_err(|e| {
                             tracing::error!("Error when running service: {}", e);
@@ -212,6 +292,8 @@ impl PriconneService {
             sched.add(job).await?;
         }
 
+        priconne.reminders.start(sched.clone()).await?;
+
         Ok(())
     }
 }
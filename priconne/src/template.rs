@@ -0,0 +1,421 @@
+//! Message templates
+//!
+//! Messages of announcements and cartoons are rendered with [minijinja]
+//! templates. The builtin ones in `priconne/templates` can be replaced in
+//! [`TemplateConfig`], either inline or by a file. Templates are checked when
+//! the config is built, by rendering them with a sample context.
+//!
+//! Values are escaped for Telegram HTML, except the full content of short
+//! posts which is already in Telegram HTML. Extra filters are:
+//!
+//! - `date(format)`: format an RFC 3339 time in the time zone of recipients
+//!   with a [`chrono` format], default to `%Y/%m/%d %H:%M (UTC%:z)`
+//! - `hashtag`: prefix a tag with `#`
+//!
+//! The end of a message wrapped in `{% block footer %}` is its footer, which is
+//! kept intact when a long message is cut.
+//!
+//! [`chrono` format]: chrono::format::strftime
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use minijinja::{
+    context, Environment, Error as TemplateError, ErrorKind, Output, State, UndefinedBehavior,
+    Value,
+};
+use mongodb::bson::oid::ObjectId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html::escape;
+
+use crate::{
+    chat::Message,
+    insight::tagging::message_title,
+    resource::{cartoon::Cartoon, post::Post, Announcement},
+    utils::{Tz, DEFAULT_TIME_ZONE, TIME_FORMAT},
+    PriconneService, Result,
+};
+
+const ANNOUNCEMENT: &str = "announcement";
+const CARTOON: &str = "cartoon";
+
+/// Prefix of the builtin templates, used when a configured one fails.
+const BUILTIN: &str = "builtin/";
+
+/// Block marking the footer of a message.
+const FOOTER: &str = "footer";
+
+/// Where a template is read from.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum TemplateSource {
+    /// Template in a file
+    File { file: PathBuf },
+    /// Template source
+    Inline(String),
+}
+
+impl TemplateSource {
+    fn load(&self) -> Result<String> {
+        match self {
+            TemplateSource::File { file } => Ok(std::fs::read_to_string(file)?),
+            TemplateSource::Inline(source) => Ok(source.clone()),
+        }
+    }
+}
+
+/// Templates of messages, the builtin ones are used if not given.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TemplateConfig {
+    /// Template of announcements, with [`AnnouncementContext`]
+    pub announcement: Option<TemplateSource>,
+    /// Template of cartoons, with [`Cartoon`]
+    pub cartoon: Option<TemplateSource>,
+}
+
+/// Values available in the announcement template.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnnouncementContext {
+    /// ID of the announcement
+    pub id: String,
+    /// Title without the category
+    pub title: String,
+    /// Category in `【】` of the title
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    pub events: Vec<EventContext>,
+    /// Sources, like `news#1774`
+    pub sources: Vec<String>,
+    /// IDs in the sources
+    pub ids: Vec<i32>,
+    /// Telegraph page, or the source page if none
+    pub link: Option<String>,
+    pub telegraph: Option<String>,
+    pub source: Option<String>,
+    pub summary: Option<String>,
+    /// Full content in Telegram HTML, not escaped
+    #[serde(skip)]
+    pub content: Option<String>,
+    pub create_time: Option<String>,
+    pub update_time: Option<String>,
+}
+
+/// An event in [`AnnouncementContext`].
+#[derive(Debug, Clone, Serialize)]
+pub struct EventContext {
+    pub title: String,
+    pub start: String,
+    pub end: String,
+}
+
+impl AnnouncementContext {
+    pub fn new(post: &Post, message: &Message) -> Self {
+        let source = message.source.as_ref().map(|url| url.to_string());
+        Self {
+            id: post.id.to_hex(),
+            title: message.title.clone(),
+            category: message_title(&post.title).0,
+            tags: message.tags.iter().cloned().collect(),
+            events: message
+                .events
+                .iter()
+                .map(|event| EventContext {
+                    title: event.title.clone(),
+                    start: event.start.to_rfc3339(),
+                    end: event.end.to_rfc3339(),
+                })
+                .collect(),
+            sources: message.sources.iter().map(|s| s.to_string()).collect(),
+            ids: message.sources.iter().map(|source| source.id()).collect(),
+            link: message.telegraph.clone().or_else(|| source.clone()),
+            telegraph: message.telegraph.clone(),
+            source,
+            summary: message.summary.clone(),
+            content: message.content.clone(),
+            create_time: message.create_time.map(|time| time.to_rfc3339()),
+            update_time: post.update_time.map(|time| time.to_rfc3339()),
+        }
+    }
+
+    fn value(&self) -> Value {
+        context! {
+            content => self.content.clone().map(Value::from_safe_string),
+            ..Value::from_serialize(self)
+        }
+    }
+
+    /// Context with every field filled, to check templates.
+    fn sample() -> Self {
+        let time = Utc::now().to_rfc3339();
+        Self {
+            id: ObjectId::new().to_hex(),
+            title: "《公主祭典 獎勵轉蛋》期間限定角色登場！".to_string(),
+            category: Some("轉蛋".to_string()),
+            tags: vec!["轉蛋".to_string()],
+            events: vec![EventContext {
+                title: "公主祭典 獎勵轉蛋".to_string(),
+                start: time.clone(),
+                end: time.clone(),
+            }],
+            sources: vec!["news#1774".to_string()],
+            ids: vec![1774],
+            link: Some("https://telegra.ph/post-07-01".to_string()),
+            telegraph: Some("https://telegra.ph/post-07-01".to_string()),
+            source: Some("http://www.princessconnect.so-net.tw/news/newsDetail/1774".to_string()),
+            summary: Some("・贈品內容：記憶碎片×100".to_string()),
+            content: Some("感謝各位騎士君的<b>支持</b>".to_string()),
+            create_time: Some(time.clone()),
+            update_time: Some(time),
+        }
+    }
+}
+
+/// A rendered message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rendered {
+    pub text: String,
+    /// Output of the `footer` block, empty if the template has none
+    pub footer: String,
+}
+
+/// Compiled message templates.
+pub struct Templates {
+    env: Environment<'static>,
+    time_zone: Tz,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self::new(&TemplateConfig::default(), DEFAULT_TIME_ZONE)
+            .expect("builtin templates are valid")
+    }
+}
+
+impl Templates {
+    /// Compile and check templates in `config`, showing times in `time_zone`.
+    pub fn new(config: &TemplateConfig, time_zone: Tz) -> Result<Self> {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_formatter(telegram_formatter);
+        env.add_filter("date", move |time: String, format: Option<String>| {
+            date(time, format, time_zone)
+        });
+        env.add_filter("hashtag", |tag: String| format!("#{tag}"));
+
+        let builtins = [
+            (ANNOUNCEMENT, include_str!("../templates/announcement.html")),
+            (CARTOON, include_str!("../templates/cartoon.html")),
+        ];
+        for (name, source) in builtins {
+            env.add_template(name, source)?;
+            env.add_template_owned(format!("{BUILTIN}{name}"), source)?;
+        }
+        for (name, source) in [
+            (ANNOUNCEMENT, &config.announcement),
+            (CARTOON, &config.cartoon),
+        ] {
+            if let Some(source) = source {
+                env.add_template_owned(name, source.load()?)?;
+            }
+        }
+
+        let templates = Self { env, time_zone };
+        templates.render(ANNOUNCEMENT, AnnouncementContext::sample().value())?;
+        templates.render(CARTOON, Value::from_serialize(Cartoon::sample()))?;
+        Ok(templates)
+    }
+
+    /// Time zone that times are shown in.
+    pub fn time_zone(&self) -> Tz {
+        self.time_zone
+    }
+
+    pub fn render_announcement(&self, context: &AnnouncementContext) -> Rendered {
+        self.render_or_builtin(ANNOUNCEMENT, context.value())
+    }
+
+    pub fn render_cartoon(&self, cartoon: &Cartoon) -> Rendered {
+        self.render_or_builtin(CARTOON, Value::from_serialize(cartoon))
+    }
+
+    fn render(&self, name: &str, context: Value) -> Result<Rendered> {
+        let mut captured = self.env.get_template(name)?.render_captured(context)?;
+        let footer = captured.with_state_mut(|state| match state.render_block(FOOTER) {
+            Err(e) if e.kind() == ErrorKind::UnknownBlock => Ok(String::new()),
+            footer => footer,
+        })?;
+        Ok(Rendered {
+            text: captured.into_output(),
+            footer,
+        })
+    }
+
+    /// Render with the builtin template if the configured one fails, or
+    /// renders only whitespace, which Telegram rejects.
+    fn render_or_builtin(&self, name: &str, context: Value) -> Rendered {
+        match self.render(name, context.clone()) {
+            Ok(rendered) if !rendered.text.trim().is_empty() => return rendered,
+            Ok(_) => tracing::error!("{name} template is blank, use the builtin one"),
+            Err(e) => {
+                tracing::error!("failed to render {name} template, use the builtin one: {e}")
+            }
+        }
+        self.render(&format!("{BUILTIN}{name}"), context)
+            .unwrap_or_default()
+    }
+}
+
+/// Write values escaped for Telegram HTML, and `none` as nothing.
+fn telegram_formatter(
+    out: &mut Output,
+    _state: &State,
+    value: &Value,
+) -> std::result::Result<(), TemplateError> {
+    if value.is_undefined() {
+        return Err(TemplateError::new(
+            ErrorKind::UndefinedError,
+            "undefined value in output",
+        ));
+    }
+    if value.is_none() {
+        return Ok(());
+    }
+    if value.is_safe() {
+        write!(out, "{value}")?;
+    } else {
+        write!(out, "{}", escape(&value.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Format an RFC 3339 time in `zone`.
+fn date(
+    time: String,
+    format: Option<String>,
+    zone: Tz,
+) -> std::result::Result<String, TemplateError> {
+    let time = DateTime::parse_from_rfc3339(&time).map_err(|e| {
+        TemplateError::new(ErrorKind::InvalidOperation, "not a time").with_source(e)
+    })?;
+    let format = format.as_deref().unwrap_or(TIME_FORMAT);
+    Ok(time.with_timezone(&zone).format(format).to_string())
+}
+
+impl PriconneService {
+    /// Render the message of the announcement `id` with the configured
+    /// templates, summarizing its latest archived page if any.
+    pub async fn render_announcement(&self, id: ObjectId) -> Result<Option<Message>> {
+        let Some(announcement) = self.storage.announcements()?.find_by_id(id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(self.announcement_message(&announcement).await?))
+    }
+
+    /// The message posted for `announcement`, rendered as in
+    /// [`render_announcement`](Self::render_announcement).
+    pub async fn announcement_message(&self, announcement: &Announcement) -> Result<Message> {
+        let page = match announcement.data.last() {
+            Some(latest) => {
+                self.page_archive()?
+                    .find_latest(&latest.source, latest.id)
+                    .await?
+            }
+            None => None,
+        };
+        let html = page.map(|page| page.html()).transpose()?;
+
+        let publication =
+            announcement.publication_with_page(html.as_deref(), &self.config.post, &self.templates);
+        Ok(publication.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config = TemplateConfig {
+            announcement: Some(TemplateSource::Inline(
+                "{{ category }}｜{{ title }} {{ ids|join(',') }}".to_string(),
+            )),
+            cartoon: None,
+        };
+        let templates = Templates::new(&config, DEFAULT_TIME_ZONE).unwrap();
+        let context = AnnouncementContext::sample();
+        assert_eq!(
+            templates.render_announcement(&context).text,
+            "轉蛋｜《公主祭典 獎勵轉蛋》期間限定角色登場！ 1774"
+        );
+
+        for invalid in ["{{ title ", "{{ titel }}", "{{ title|unknown }}"] {
+            let config = TemplateConfig {
+                announcement: Some(TemplateSource::Inline(invalid.to_string())),
+                cartoon: None,
+            };
+            assert!(
+                Templates::new(&config, DEFAULT_TIME_ZONE).is_err(),
+                "{invalid} is valid"
+            );
+        }
+
+        let config: TemplateConfig =
+            serde_yaml::from_str("announcement:\n  file: /nonexistent.html\ncartoon: '{{ id }}'")
+                .unwrap();
+        assert!(matches!(
+            config.announcement,
+            Some(TemplateSource::File { .. })
+        ));
+        assert!(Templates::new(&config, DEFAULT_TIME_ZONE).is_err());
+
+        // Blank output falls back to the builtin template
+        let config = TemplateConfig {
+            announcement: None,
+            cartoon: Some(TemplateSource::Inline(
+                "{% if false %}{{ title }}{% endif %}\n".to_string(),
+            )),
+        };
+        let cartoon = Cartoon::sample();
+        assert_eq!(
+            Templates::new(&config, DEFAULT_TIME_ZONE)
+                .unwrap()
+                .render_cartoon(&cartoon),
+            Templates::default().render_cartoon(&cartoon)
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        let templates = Templates::default();
+        let mut context = AnnouncementContext::sample();
+        context.title = "a < b & c".to_string();
+        context.summary = None;
+        context.content = Some("<b>粗體</b>".to_string());
+        let rendered = templates.render_announcement(&context);
+        assert!(rendered
+            .footer
+            .starts_with("https://telegra.ph/post-07-01\n"));
+        assert!(rendered.text.ends_with(&rendered.footer));
+        let text = rendered.text;
+        assert!(text.contains("<b>a &lt; b &amp; c</b>"));
+        assert!(text.contains("\n<b>粗體</b>\n"));
+
+        let cartoon = Cartoon::sample();
+        let footer = format!("{} <code>#{}</code>", cartoon.image_src, cartoon.id);
+        assert_eq!(
+            templates.render_cartoon(&cartoon),
+            Rendered {
+                text: format!(
+                    "<b>第 {} 話</b>: {}\n{footer}",
+                    cartoon.episode, cartoon.title
+                ),
+                footer,
+            }
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use html5ever::{local_name, namespace_url, ns, QualName};
 use kuchikiki::{ElementData, NodeData, NodeRef};
 
+use super::sanitize_for_telegraph;
+
 /// Trim leading space in nodes, returns true if nodes isn't empty.
 ///
 /// Rescursively traverses the tree and detatch any empty string nodes,
@@ -343,6 +345,9 @@ pub fn optimize_for_telegraph(node: NodeRef) -> NodeRef {
     // Remove `<div>`
     remove_div(node.children());
 
+    // Convert tables, embeds and styled spans, strip unknown tags
+    sanitize_for_telegraph(&node);
+
     // Trim leading and trailing whitespace to remove empty `<p>` nodes
     // This should be done before pulling children to the parent.
     trim_leading_whitespace(node.children());
@@ -1,4 +1,3 @@
-use chrono::{DateTime, FixedOffset, TimeZone};
 use regex::Regex;
 use serde::{
     de::{self, Visitor},
@@ -7,20 +6,23 @@ use serde::{
 use std::{fmt, str::FromStr};
 
 mod html;
+mod sanitize;
+mod time;
 pub use html::*;
+pub use sanitize::*;
+pub use time::*;
 
 /// Number of seconds in an hour
 pub const HOUR: i32 = 3600;
 
-/// Date format from priconne api server
+/// Date format from priconne api server, in the local time of the server
 pub mod api_date_format {
-    use super::string_to_date;
-    use chrono::{DateTime, FixedOffset};
+    use chrono::NaiveDateTime;
     use serde::{Deserialize, Deserializer, Serializer};
 
     pub const FORMAT: &str = "%Y-%m-%d %H:%M";
 
-    pub fn serialize<S>(date: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(date: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
@@ -28,26 +30,15 @@ pub mod api_date_format {
         serializer.serialize_str(&s)
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<FixedOffset>, D::Error>
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
     where
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        string_to_date(&s, FORMAT).map_err(serde::de::Error::custom)
+        NaiveDateTime::parse_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
     }
 }
 
-/// Convert string to UTC+8 DateTime
-pub fn string_to_date(
-    string: &str,
-    format: &str,
-) -> Result<DateTime<FixedOffset>, chrono::ParseError> {
-    let offset: chrono::FixedOffset = chrono::FixedOffset::east_opt(8 * HOUR).unwrap();
-
-    let datetime = offset.datetime_from_str(string, format)?;
-    Ok(datetime)
-}
-
 pub trait SplitPrefix {
     /// Trim the `prefix` of a string,
     /// then split the rest by a separator and return the first part and the rest
@@ -74,6 +65,10 @@ impl SplitPrefix for str {
     }
 }
 
+/// Dates are stored at their midnight in UTC+8 and read back in UTC+8, so the
+/// date round-trips whatever the zone of its source. The stored instant is not
+/// a time of the source, compare dates with times through
+/// [`ResourceMetadata::update_time`](crate::resource::ResourceMetadata::update_time).
 pub mod chrono_date_utc8_as_bson_datetime {
     use crate::utils::HOUR;
 
@@ -0,0 +1,356 @@
+//! Sanitize HTML for Telegraph
+//!
+//! Telegraph only accepts a small set of tags, see [`ALLOWED`], while So-net
+//! pages have tables of rewards, styled `<span>` and embedded videos.
+//! [`sanitize_for_telegraph`] converts them to tags Telegraph knows, and
+//! strips everything else while keeping the text.
+
+use html5ever::{local_name, namespace_url, ns, LocalName, QualName};
+use kuchikiki::NodeRef;
+use url::Url;
+
+/// Tags accepted by Telegraph.
+const ALLOWED: [&str; 24] = [
+    "a",
+    "aside",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "em",
+    "figcaption",
+    "figure",
+    "h3",
+    "h4",
+    "hr",
+    "i",
+    "iframe",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "strong",
+    "u",
+    "ul",
+    "video",
+];
+
+/// Tags removed with their content.
+const REMOVED: [&str; 10] = [
+    "script", "style", "noscript", "template", "head", "form", "button", "input", "select",
+    "textarea",
+];
+
+/// Attributes kept for Telegraph, others are removed.
+const ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// Tables with more columns than this are kept as preformatted text,
+/// smaller ones are converted to lists.
+const LIST_COLUMNS: usize = 2;
+
+/// Convert descendants of `node` to tags supported by Telegraph.
+pub fn sanitize_for_telegraph(node: &NodeRef) {
+    for name in REMOVED {
+        for element in select_all(node, name) {
+            element.detach();
+        }
+    }
+
+    // Innermost tables first
+    for table in select_all(node, "table").into_iter().rev() {
+        table.insert_before(convert_table(&table));
+        table.detach();
+    }
+
+    for name in ["iframe", "video", "embed", "object"] {
+        for element in select_all(node, name) {
+            if let Some(embed) = convert_embed(&element) {
+                element.insert_before(embed);
+            }
+            element.detach();
+        }
+    }
+
+    // Children before parents, so that pulled children are already sanitized
+    let descendants: Vec<NodeRef> = node.descendants().collect();
+    for descendant in descendants.into_iter().rev() {
+        sanitize_element(&descendant);
+    }
+}
+
+fn sanitize_element(node: &NodeRef) {
+    let Some(element) = node.as_element() else {
+        if node.as_comment().is_some() {
+            node.detach();
+        }
+        return;
+    };
+    let name = element.name.local.to_string();
+
+    let renamed = match name.as_str() {
+        "h1" | "h2" => Some("h3"),
+        "h5" | "h6" => Some("h4"),
+        "del" | "strike" => Some("s"),
+        "ins" => Some("u"),
+        "span" | "font" => emphasis(node),
+        _ => None,
+    };
+    if let Some(renamed) = renamed {
+        replace_tag(node, renamed);
+        return;
+    }
+
+    if ALLOWED.contains(&name.as_str()) {
+        element
+            .attributes
+            .borrow_mut()
+            .map
+            .retain(|name, _| ATTRIBUTES.contains(&&*name.local));
+    } else {
+        pull_up(node);
+    }
+}
+
+/// Tag emphasizing a styled `<span>` or `<font>`, `None` if not emphasized.
+///
+/// So-net marks important text in red, which is shown as `<strong>` since
+/// Telegraph has no colors.
+fn emphasis(node: &NodeRef) -> Option<&'static str> {
+    let element = node.as_element()?;
+    let attributes = element.attributes.borrow();
+    let style = attributes
+        .get(local_name!("style"))
+        .unwrap_or_default()
+        .replace(' ', "")
+        .to_lowercase();
+
+    if style.contains("font-weight:bold") || style.contains("font-weight:700") {
+        Some("b")
+    } else if style.contains("color:") || attributes.contains(local_name!("color")) {
+        Some("strong")
+    } else if style.contains("text-decoration:underline") {
+        Some("u")
+    } else {
+        None
+    }
+}
+
+/// Convert a table to a list of rows, or preformatted text if it has many columns.
+fn convert_table(table: &NodeRef) -> NodeRef {
+    let rows: Vec<Vec<String>> = select_all(table, "tr")
+        .iter()
+        .map(|row| {
+            row.children()
+                .filter(|cell| {
+                    cell.as_element()
+                        .is_some_and(|cell| matches!(&*cell.name.local, "td" | "th"))
+                })
+                .map(|cell| text(&cell))
+                .collect::<Vec<_>>()
+        })
+        .filter(|cells| cells.iter().any(|cell| !cell.is_empty()))
+        .collect();
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+    if columns > LIST_COLUMNS {
+        let pre = new_element("pre");
+        let lines: Vec<String> = rows.iter().map(|cells| cells.join(" | ")).collect();
+        pre.append(NodeRef::new_text(lines.join("\n")));
+        return pre;
+    }
+
+    let list = new_element("ul");
+    for cells in rows {
+        let cells: Vec<_> = cells.into_iter().filter(|cell| !cell.is_empty()).collect();
+        let item = new_element("li");
+        item.append(NodeRef::new_text(cells.join("：")));
+        list.append(item);
+    }
+    list
+}
+
+/// Convert an embedded media to a Telegraph embed or a link.
+fn convert_embed(node: &NodeRef) -> Option<NodeRef> {
+    let element = node.as_element()?;
+    let src = {
+        let attributes = element.attributes.borrow();
+        attributes
+            .get(local_name!("src"))
+            .or_else(|| attributes.get(local_name!("data")))
+            .map(String::from)
+    };
+    let src = src.or_else(|| {
+        let source = node.select_first("source").ok()?;
+        let attributes = source.attributes.borrow();
+        attributes.get(local_name!("src")).map(String::from)
+    })?;
+    let url = Url::parse(&src).ok()?;
+
+    if let Some(embed) = youtube_embed(&url) {
+        let iframe = new_element("iframe");
+        set_attribute(&iframe, "src", embed);
+        let figure = new_element("figure");
+        figure.append(iframe);
+        return Some(figure);
+    }
+
+    let link = new_element("a");
+    set_attribute(&link, "href", url.to_string());
+    link.append(NodeRef::new_text(url.to_string()));
+    let paragraph = new_element("p");
+    paragraph.append(link);
+    Some(paragraph)
+}
+
+/// Telegraph embed of a YouTube video.
+fn youtube_embed(url: &Url) -> Option<String> {
+    let id = match url.host_str()? {
+        "youtu.be" => url.path_segments()?.next()?.to_string(),
+        "www.youtube.com" | "youtube.com" | "m.youtube.com" | "www.youtube-nocookie.com" => {
+            let mut segments = url.path_segments()?;
+            match segments.next()? {
+                "embed" | "shorts" => segments.next()?.to_string(),
+                "watch" => url
+                    .query_pairs()
+                    .find(|(key, _)| key == "v")
+                    .map(|(_, id)| id.to_string())?,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    if id.is_empty() {
+        return None;
+    }
+
+    let watch = format!("https://www.youtube.com/watch?v={id}");
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("url", &watch)
+        .finish();
+    Some(format!("/embed/youtube?{query}"))
+}
+
+/// Text of a node with whitespace collapsed.
+fn text(node: &NodeRef) -> String {
+    node.text_contents()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn select_all(node: &NodeRef, selector: &str) -> Vec<NodeRef> {
+    node.select(selector)
+        .map(|elements| elements.map(|element| element.as_node().clone()).collect())
+        .unwrap_or_default()
+}
+
+fn new_element(name: &str) -> NodeRef {
+    NodeRef::new_element(
+        QualName::new(None, ns!(html), LocalName::from(name)),
+        vec![],
+    )
+}
+
+fn set_attribute(node: &NodeRef, name: &str, value: String) {
+    if let Some(element) = node.as_element() {
+        element.attributes.borrow_mut().insert(name, value);
+    }
+}
+
+/// Replace the tag of `node` with `name`, without attributes.
+fn replace_tag(node: &NodeRef, name: &str) {
+    let replacement = new_element(name);
+    for child in node.children() {
+        replacement.append(child);
+    }
+    node.insert_before(replacement);
+    node.detach();
+}
+
+/// Remove `node`, keeping its children in place.
+fn pull_up(node: &NodeRef) {
+    for child in node.children() {
+        node.insert_before(child);
+    }
+    node.detach();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use crate::{
+        insight::AnnouncementPage, resource::news::NewsPage, utils::optimize_for_telegraph, Page,
+    };
+
+    /// Compare `actual` with the golden file, or write it if `UPDATE_GOLDEN` is set.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = Path::new("tests/golden").join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("missing {path:?}, run with UPDATE_GOLDEN=1"));
+        assert_eq!(actual, expected, "{path:?} differs");
+    }
+
+    /// Tags of the sanitized tree are all accepted by Telegraph.
+    fn assert_allowed(node: &NodeRef) {
+        for descendant in node.descendants() {
+            if let Some(element) = descendant.as_element() {
+                let name = &*element.name.local;
+                assert!(ALLOWED.contains(&name), "<{name}> is not allowed");
+                for attribute in element.attributes.borrow().map.keys() {
+                    assert!(
+                        ATTRIBUTES.contains(&&*attribute.local),
+                        "<{name} {}> is not allowed",
+                        attribute.local
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_golden_news() {
+        for name in ["news_page", "news_1376", "news_1460"] {
+            let html = std::fs::read_to_string(format!("tests/{name}.html")).unwrap();
+            let node = optimize_for_telegraph(NewsPage::from_html(html).unwrap().content());
+            assert_allowed(&node);
+            assert_golden(&format!("{name}.html"), &node.to_string());
+        }
+    }
+
+    #[test]
+    fn test_golden_unsupported() {
+        let html = std::fs::read_to_string("tests/unsupported.html").unwrap();
+        let document = kuchikiki::parse_html().one(html);
+        let node = document.select_first("section").unwrap().as_node().clone();
+        sanitize_for_telegraph(&node);
+        assert_allowed(&node);
+        assert_golden("unsupported.html", &node.to_string());
+    }
+
+    #[test]
+    fn test_youtube_embed() {
+        let embed = "/embed/youtube?url=https%3A%2F%2Fwww.youtube.com%2Fwatch%3Fv%3DdQw4w9WgXcQ";
+        for url in [
+            "https://www.youtube.com/embed/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1",
+        ] {
+            assert_eq!(
+                youtube_embed(&Url::parse(url).unwrap()).as_deref(),
+                Some(embed)
+            );
+        }
+        let other = Url::parse("https://www.youtube.com/channel/UC").unwrap();
+        assert_eq!(youtube_embed(&other), None);
+    }
+}
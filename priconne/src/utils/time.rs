@@ -0,0 +1,91 @@
+//! Time zones
+//!
+//! Times on pages are local to the server of their source, see
+//! [`ServerConfig::time_zone`](crate::config::ServerConfig::time_zone), and
+//! are shown to recipients in their configured zone with an explicit offset.
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+pub use chrono_tz::Tz;
+
+/// Zone of the TW server, used when none is configured.
+pub const DEFAULT_TIME_ZONE: Tz = chrono_tz::Asia::Taipei;
+
+/// Format of times shown to recipients.
+pub const TIME_FORMAT: &str = "%Y/%m/%d %H:%M (UTC%:z)";
+
+/// Parse a local time in `zone`.
+pub fn string_to_date(
+    string: &str,
+    format: &str,
+    zone: Tz,
+) -> Result<DateTime<FixedOffset>, chrono::ParseError> {
+    let datetime = NaiveDateTime::parse_from_str(string, format)?;
+    Ok(local_to_fixed(datetime, zone))
+}
+
+/// Attach `zone` to a local time. A time skipped by a transition is taken as
+/// the same time after it.
+pub fn local_to_fixed(datetime: NaiveDateTime, zone: Tz) -> DateTime<FixedOffset> {
+    let local = zone
+        .from_local_datetime(&datetime)
+        .earliest()
+        .or_else(|| {
+            zone.from_local_datetime(&(datetime + Duration::hours(1)))
+                .earliest()
+        })
+        .unwrap_or_else(|| zone.from_utc_datetime(&datetime));
+    local.with_timezone(&local.offset().fix())
+}
+
+/// Start of `date` in `zone`.
+pub fn midnight(date: NaiveDate, zone: Tz) -> DateTime<FixedOffset> {
+    local_to_fixed(date.and_hms_opt(0, 0, 0).unwrap(), zone)
+}
+
+/// Format `time` in `zone`, with the offset in [`TIME_FORMAT`].
+pub fn format_time(time: DateTime<Utc>, zone: Tz) -> String {
+    time.with_timezone(&zone).format(TIME_FORMAT).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_to_date() {
+        let time = string_to_date("2022/07/01 11:55", "%Y/%m/%d %H:%M", DEFAULT_TIME_ZONE).unwrap();
+        assert_eq!(time.to_rfc3339(), "2022-07-01T11:55:00+08:00");
+
+        let time = string_to_date("2022/07/01 11:55", "%Y/%m/%d %H:%M", chrono_tz::Asia::Tokyo);
+        assert_eq!(time.unwrap().to_rfc3339(), "2022-07-01T11:55:00+09:00");
+
+        // Skipped by daylight saving time
+        let time = string_to_date(
+            "2023/03/26 02:30",
+            "%Y/%m/%d %H:%M",
+            chrono_tz::Europe::Berlin,
+        );
+        assert_eq!(time.unwrap().to_rfc3339(), "2023-03-26T03:30:00+02:00");
+
+        assert!(string_to_date("2022/07/01", "%Y/%m/%d %H:%M", DEFAULT_TIME_ZONE).is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = Utc.with_ymd_and_hms(2022, 7, 1, 3, 55, 0).unwrap();
+        assert_eq!(
+            format_time(time, DEFAULT_TIME_ZONE),
+            "2022/07/01 11:55 (UTC+08:00)"
+        );
+        assert_eq!(
+            format_time(time, chrono_tz::Asia::Tokyo),
+            "2022/07/01 12:55 (UTC+09:00)"
+        );
+
+        let date = NaiveDate::from_ymd_opt(2022, 7, 1).unwrap();
+        assert_eq!(
+            midnight(date, chrono_tz::Asia::Tokyo).to_rfc3339(),
+            "2022-07-01T00:00:00+09:00"
+        );
+    }
+}